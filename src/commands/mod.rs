@@ -1,16 +1,31 @@
-use crate::db;
-use crate::domain::schema::{validate_schema_def, SchemaDef};
-use crate::domain::NoopObserver;
-use crate::repository::{dynamic_table_repo, projection_outbox_repo, schema_registry_repo};
-use crate::service::{
-    identity_service, ingest_service, query_service, scope_service, user_service,
+mod output;
+
+pub use output::Output;
+
+use agent_memory_cli::db;
+use agent_memory_cli::db::migrate;
+use agent_memory_cli::domain::materializer::MaterializerRule;
+use agent_memory_cli::domain::schema::{validate_schema_def, SchemaDef};
+use agent_memory_cli::error::{AppError, ErrorKind};
+use agent_memory_cli::repository::dynamic_table_repo::DynamicRecord;
+use agent_memory_cli::service::hook_service::HookConfig;
+use agent_memory_cli::service::identity_service::UserRef;
+use agent_memory_cli::service::query_service::ScopeSelection;
+use agent_memory_cli::service::user_service::UserRefCounts;
+use agent_memory_cli::service::{
+    archive_service, bundle_service, compact_service, ingest_service, materializer_service,
+    outbox_service, record_service, reindex_service,
 };
-use rusqlite::Connection;
+use agent_memory_cli::store::{new_id, now_ts};
+use agent_memory_cli::{http, rpc, MemoryStore};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 
-pub fn doctor(db_path: &str, as_json: bool) -> Result<(), String> {
+pub fn doctor(db_path: &str, output: Output) -> Result<(), AppError> {
     let db_exists = std::path::Path::new(db_path).exists();
     let schema_initialized = if db_exists {
         match db::connect(db_path) {
@@ -30,16 +45,13 @@ pub fn doctor(db_path: &str, as_json: bool) -> Result<(), String> {
         false
     };
 
-    if as_json {
-        println!(
-            "{}",
-            json!({
-                "ok": true,
-                "db_path": db_path,
-                "db_exists": db_exists,
-                "schema_initialized": schema_initialized
-            })
-        );
+    if output.is_json() {
+        output.doc(json!({
+            "ok": true,
+            "db_path": db_path,
+            "db_exists": db_exists,
+            "schema_initialized": schema_initialized
+        }));
     } else {
         println!("agent-memory-cli is ready");
         println!("db_path={db_path}");
@@ -49,375 +61,1310 @@ pub fn doctor(db_path: &str, as_json: bool) -> Result<(), String> {
     Ok(())
 }
 
-pub fn todo(group: &str, command: &str) {
-    println!("TODO: implement {group}::{command}");
-}
+pub fn admin_migrate(
+    db_path: &str,
+    status: bool,
+    to: Option<i64>,
+    dry_run: bool,
+    output: Output,
+) -> Result<(), AppError> {
+    if status {
+        return admin_migrate_status(db_path, output);
+    }
 
-pub fn admin_migrate(db_path: &str) -> Result<(), String> {
     db::ensure_parent_dir(db_path)?;
+    let mut conn = db::connect(db_path)?;
+    let pending = migrate::plan(&conn, to)?;
+
+    if dry_run {
+        if output.is_json() {
+            let mapped: Vec<_> = pending
+                .iter()
+                .map(|m| json!({"version": m.version, "name": m.name, "sql": m.sql.trim_end()}))
+                .collect();
+            output.doc(json!({"db_path": db_path, "dry_run": true, "pending": mapped}));
+            return Ok(());
+        }
+        for m in &pending {
+            println!("-- migration {} ({})", m.version, m.name);
+            println!("{}", m.sql.trim_end());
+        }
+        if pending.is_empty() {
+            println!("-- no pending migrations");
+        }
+        return Ok(());
+    }
+
+    let now = now_ts();
+    migrate::apply(&mut conn, &pending, &now)?;
+    let version = migrate::current_version(&conn)?;
+    if output.is_json() {
+        output.doc(json!({
+            "db_path": db_path,
+            "schema_version": version,
+            "applied": pending.iter().map(|m| m.version).collect::<Vec<_>>(),
+        }));
+    } else {
+        println!(
+            "migrated schema to {db_path} version={version} applied={}",
+            pending.len()
+        );
+    }
+    Ok(())
+}
+
+fn admin_migrate_status(db_path: &str, output: Output) -> Result<(), AppError> {
+    if !Path::new(db_path).exists() {
+        return Err(AppError::not_found(format!(
+            "database not found: {db_path}"
+        )));
+    }
     let conn = db::connect(db_path)?;
-    let schema_sql = include_str!("../../specs/SCHEMA_SQLITE_V01.sql");
-    conn.execute_batch(schema_sql)
-        .map_err(|e| format!("migration failed: {e}"))?;
-    let _ = conn.execute("ALTER TABLE events ADD COLUMN idempotency_key TEXT", []);
-    let _ = conn.execute(
-        "CREATE UNIQUE INDEX IF NOT EXISTS idx_events_idempotency ON events(scope_id, uid, idempotency_key) WHERE idempotency_key IS NOT NULL",
-        [],
-    );
-    println!("migrated schema to {db_path}");
+    let applied = migrate::applied(&conn)?;
+    let current = migrate::current_version(&conn)?;
+
+    let mut rows = Vec::new();
+    for m in migrate::MIGRATIONS {
+        let status = match applied.iter().find(|a| a.version == m.version) {
+            Some(a) if a.checksum != migrate::checksum(m.sql) => ("checksum_mismatch", Some(a)),
+            Some(a) => ("applied", Some(a)),
+            None => ("pending", None),
+        };
+        rows.push((
+            m.version,
+            m.name.to_string(),
+            status.0,
+            status.1.map(|a| a.applied_at.clone()),
+        ));
+    }
+    for a in &applied {
+        if !migrate::MIGRATIONS.iter().any(|m| m.version == a.version) {
+            rows.push((
+                a.version,
+                a.name.clone(),
+                "unknown",
+                Some(a.applied_at.clone()),
+            ));
+        }
+    }
+
+    if output.is_json() {
+        let mapped: Vec<_> = rows
+            .iter()
+            .map(|(version, name, status, applied_at)| {
+                json!({"version": version, "name": name, "status": status, "applied_at": applied_at})
+            })
+            .collect();
+        output.doc(json!({
+            "schema_version": current,
+            "latest_version": migrate::latest_version(),
+            "migrations": mapped,
+        }));
+    } else {
+        println!(
+            "schema_version={current} latest_version={}",
+            migrate::latest_version()
+        );
+        for (version, name, status, applied_at) in rows {
+            match applied_at {
+                Some(at) => {
+                    println!("version={version} name={name} status={status} applied_at={at}")
+                }
+                None => println!("version={version} name={name} status={status}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn outbox_list(
+    db_path: &str,
+    status: Option<&str>,
+    stream: Option<&str>,
+    limit: usize,
+    output: Output,
+) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let rows = outbox_service::list(&conn, status, stream, limit)?;
+    if output.is_json() {
+        output.list(&rows);
+    } else {
+        for r in rows {
+            println!(
+                "outbox_id={} stream={} key={} status={} attempts={} created_at={} last_error={}",
+                r.outbox_id,
+                r.stream,
+                r.item_key,
+                r.status(),
+                r.attempts,
+                r.created_at,
+                r.last_error.as_deref().unwrap_or("")
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn outbox_drain(
+    db_path: &str,
+    sink: &str,
+    target: &str,
+    opts: &outbox_service::DrainOptions<'_>,
+    output: Output,
+) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let mut sink = outbox_service::sink_for(sink, target)?;
+    let now = now_ts();
+    let report = outbox_service::drain(&conn, sink.as_mut(), opts, &now)?;
+    if output.is_json() {
+        output.doc(json!({"delivered": report.delivered, "retrying": report.retrying, "dead": report.dead}));
+    } else {
+        println!(
+            "outbox drained delivered={} retrying={} dead={}",
+            report.delivered, report.retrying, report.dead
+        );
+    }
     Ok(())
 }
 
-fn now_ts() -> String {
-    let n = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    n.to_string()
+pub fn outbox_requeue(
+    db_path: &str,
+    outbox_id: Option<&str>,
+    output: Output,
+) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let n = outbox_service::requeue(&conn, outbox_id)?;
+    if n == 0 {
+        if let Some(id) = outbox_id {
+            return Err(AppError::not_found(format!(
+                "dead outbox row not found: {id}"
+            )));
+        }
+    }
+    if output.is_json() {
+        output.doc(json!({"outbox_id": outbox_id, "requeued": n}));
+    } else {
+        println!("requeued outbox rows={n}");
+    }
+    Ok(())
 }
 
-fn new_id(prefix: &str) -> String {
-    let n = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_nanos();
-    format!("{prefix}_{n}")
+pub fn admin_reindex(
+    db_path: &str,
+    uid: Option<&str>,
+    scope_id: Option<&str>,
+    topic: Option<&str>,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut conn = db::open_checked(db_path)?;
+    let now = now_ts();
+    let report = reindex_service::reindex(
+        &mut conn,
+        reindex_service::ReindexFilter {
+            uid,
+            scope_id,
+            topic,
+        },
+        &now,
+    )?;
+    if output.is_json() {
+        output.doc(json!({
+            "events_scanned": report.events_scanned,
+            "archived_events": report.archived_events,
+            "events_skipped": report.events_skipped,
+            "metrics_cleared": report.metrics_cleared,
+            "topk_cleared": report.topk_cleared,
+            "counters_applied": report.counters_applied,
+            "topk_rebuilt": report.topk_rebuilt,
+        }));
+    } else {
+        println!(
+            "reindexed events={} skipped={} metrics_cleared={} topk_cleared={} counters={} topk_rebuilt={} archived={}",
+            report.events_scanned,
+            report.events_skipped,
+            report.metrics_cleared,
+            report.topk_cleared,
+            report.counters_applied,
+            report.topk_rebuilt,
+            report.archived_events,
+        );
+    }
+    Ok(())
 }
 
-fn open_db_checked(db_path: &str) -> Result<Connection, String> {
-    let conn = db::connect(db_path)?;
-    let exists: i64 = conn
-        .query_row(
-            "SELECT COUNT(1) FROM sqlite_master WHERE type='table' AND name='users'",
-            [],
-            |row| row.get(0),
-        )
-        .map_err(|e| format!("failed schema check: {e}"))?;
-    if exists == 0 {
-        return Err(
-            "schema not initialized. run: agent-memory-cli admin migrate --db <path>".to_string(),
+pub fn admin_export(db_path: &str, out: &str, output: Output) -> Result<(), AppError> {
+    let mut conn = db::open_checked(db_path)?;
+    let version = migrate::current_version(&conn)?;
+    let manifest = bundle_service::export(&mut conn, Path::new(out), version, &now_ts())?;
+    if output.is_json() {
+        output.doc(json!(manifest));
+    } else {
+        for t in &manifest.tables {
+            println!(
+                "exported table={} rows={} file={} sha256={}",
+                t.table, t.rows, t.file, t.sha256
+            );
+        }
+        println!(
+            "export complete dir={out} tables={} schema_version={version}",
+            manifest.tables.len()
         );
     }
-    Ok(conn)
+    Ok(())
+}
+
+fn parse_uid_map(pairs: &[String]) -> Result<BTreeMap<String, String>, AppError> {
+    let mut map = BTreeMap::new();
+    for pair in pairs {
+        match pair.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                map.insert(from.to_string(), to.to_string());
+            }
+            _ => {
+                return Err(AppError::validation(format!(
+                    "invalid --map-uid: {pair}. expected: <old>=<new>"
+                )))
+            }
+        }
+    }
+    Ok(map)
 }
 
-pub fn user_create(db_path: &str, name: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let uid = new_id("u");
+pub fn admin_import(
+    db_path: &str,
+    dir: &str,
+    on_conflict: &str,
+    map_uid: &[String],
+    output: Output,
+) -> Result<(), AppError> {
+    let policy = bundle_service::parse_policy(on_conflict)?;
+    let uid_map = parse_uid_map(map_uid)?;
+    let mut conn = db::open_checked(db_path)?;
+    let schema_version = migrate::current_version(&conn)?;
+    let report = bundle_service::import(
+        &mut conn,
+        Path::new(dir),
+        &bundle_service::ImportOptions {
+            policy,
+            uid_map: &uid_map,
+            schema_version,
+        },
+    )?;
+    if output.is_json() {
+        output.list(&report);
+    } else {
+        for t in &report {
+            println!(
+                "imported table={} rows={} written={} skipped={}",
+                t.table, t.rows, t.written, t.skipped
+            );
+        }
+        println!(
+            "import complete dir={dir} tables={} written={} skipped={}",
+            report.len(),
+            report.iter().map(|t| t.written).sum::<usize>(),
+            report.iter().map(|t| t.skipped).sum::<usize>()
+        );
+    }
+    Ok(())
+}
+
+pub fn admin_compact(db_path: &str, output: Output) -> Result<(), AppError> {
+    let mut conn = db::open_checked(db_path)?;
+    let now = now_ts();
+    let report = compact_service::compact(&mut conn, &now)?;
+    if output.is_json() {
+        output.doc(json!({
+            "events_compacted": report.events_compacted,
+            "summaries_updated": report.summaries_updated,
+            "rows_skipped": report.rows_skipped,
+        }));
+    } else {
+        println!(
+            "compacted events={} summaries={} skipped={}",
+            report.events_compacted, report.summaries_updated, report.rows_skipped
+        );
+    }
+    Ok(())
+}
+
+pub fn admin_archive(
+    db_path: &str,
+    month: &str,
+    dir: Option<&str>,
+    restore: bool,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut conn = db::open_checked(db_path)?;
     let now = now_ts();
-    let observer = NoopObserver;
 
-    user_service::create(&conn, &uid, name, &now, &observer)?;
-    println!("created user uid={uid} name={name}");
+    if restore {
+        let report = archive_service::restore(&mut conn, month, &now)?;
+        if output.is_json() {
+            output.doc(json!({
+                "month": month,
+                "archives": report.archives,
+                "rows_restored": report.rows_restored,
+                "rows_skipped": report.rows_skipped,
+            }));
+        } else {
+            println!(
+                "restored month={month} archives={} rows={} skipped={}",
+                report.archives, report.rows_restored, report.rows_skipped
+            );
+        }
+        return Ok(());
+    }
+
+    let dir = match dir {
+        Some(d) => PathBuf::from(d),
+        None => Path::new(db_path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join("archive"),
+    };
+    let report = archive_service::archive_through(&mut conn, month, &dir, &new_id("arc"), &now)?;
+    if report.rows_skipped > 0 {
+        eprintln!(
+            "warning: skipped {} events with a non-numeric event_ts",
+            report.rows_skipped
+        );
+    }
+    let entries = report.entries;
+    if output.is_json() {
+        let mapped: Vec<_> = entries
+            .iter()
+            .map(|e| {
+                json!({
+                    "archive_id": e.archive_id,
+                    "month": e.month,
+                    "file": e.file_path,
+                    "rows": e.row_count,
+                    "min_event_ts": e.min_event_ts,
+                    "max_event_ts": e.max_event_ts,
+                    "sha256": e.sha256,
+                })
+            })
+            .collect();
+        output.list(&mapped);
+    } else if entries.is_empty() {
+        println!("nothing to archive through month={month}");
+    } else {
+        for e in &entries {
+            println!(
+                "archived month={} rows={} file={} sha256={}",
+                e.month, e.row_count, e.file_path, e.sha256
+            );
+        }
+    }
     Ok(())
 }
 
-pub fn user_list(db_path: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    for (uid, name) in user_service::list(&conn)? {
-        println!("uid={uid} name={name}");
+/// Opens the store with the hooks configured by `--hooks`.
+fn open_store(db_path: &str, hooks: Option<&str>) -> Result<MemoryStore, AppError> {
+    let store = MemoryStore::open(db_path)?;
+    Ok(match hooks {
+        Some(path) => store.with_hooks(HookConfig::load(path)?),
+        None => store,
+    })
+}
+
+/// Post-hooks run once the mutation has committed; their failures are only reported.
+fn report_hook_warnings(store: &mut MemoryStore) {
+    for failure in store.take_hook_warnings() {
+        eprintln!("warning: {failure}");
+    }
+}
+
+pub fn user_create(
+    db_path: &str,
+    hooks: Option<&str>,
+    name: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let user = store.create_user(name)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(user));
+    } else {
+        println!("created user uid={} name={}", user.uid, user.name);
     }
     Ok(())
 }
 
-pub fn user_show(db_path: &str, uid: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    match user_service::show(&conn, uid)? {
-        Some(name) => {
-            println!("uid={uid} name={name}");
-            Ok(())
+pub fn user_list(db_path: &str, output: Output) -> Result<(), AppError> {
+    let users = MemoryStore::open(db_path)?.list_users()?;
+    if output.is_json() {
+        output.list(&users);
+    } else {
+        for u in users {
+            println!("uid={} name={}", u.uid, u.name);
         }
-        None => Err(format!("user not found: {uid}")),
     }
+    Ok(())
 }
 
-pub fn user_update(db_path: &str, uid: &str, name: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let now = now_ts();
-    let n = user_service::update(&conn, uid, name, &now)?;
-    if n == 0 {
-        return Err(format!("user not found: {uid}"));
+pub fn user_show(db_path: &str, uid: &str, output: Output) -> Result<(), AppError> {
+    let user = MemoryStore::open(db_path)?
+        .get_user(uid)?
+        .ok_or_else(|| AppError::not_found(format!("user not found: {uid}")))?;
+    if output.is_json() {
+        output.doc(json!(user));
+    } else {
+        println!("uid={} name={}", user.uid, user.name);
     }
-    println!("updated user uid={uid} name={name}");
     Ok(())
 }
 
-pub fn user_merge(db_path: &str, from_uid: &str, to_uid: &str) -> Result<(), String> {
-    if from_uid == to_uid {
-        return Err("--from and --to must be different users".to_string());
+pub fn user_update(
+    db_path: &str,
+    hooks: Option<&str>,
+    uid: &str,
+    name: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let user = store.update_user(uid, name)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(user));
+    } else {
+        println!("updated user uid={} name={}", user.uid, user.name);
     }
+    Ok(())
+}
 
-    let mut conn = open_db_checked(db_path)?;
-    let now = now_ts();
-    user_service::merge(&mut conn, from_uid, to_uid, &now)?;
-    println!("merged user from_uid={from_uid} to_uid={to_uid}");
+pub fn user_merge(
+    db_path: &str,
+    hooks: Option<&str>,
+    from_uid: &str,
+    to_uid: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    store.merge_users(from_uid, to_uid)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!({"from_uid": from_uid, "to_uid": to_uid}));
+    } else {
+        println!("merged user from_uid={from_uid} to_uid={to_uid}");
+    }
     Ok(())
 }
 
 pub fn user_delete(
     db_path: &str,
+    hooks: Option<&str>,
     uid: &str,
     mode: &str,
     force: bool,
     dry_run: bool,
-) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let counts = user_service::ref_counts(&conn, uid)?;
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
 
     if dry_run {
-        println!(
-            "delete preflight uid={uid} mode={mode} identities={} scope_members={} events={} state={} metrics={} topk={}",
-            counts.identities,
-            counts.scope_members,
-            counts.events,
-            counts.state,
-            counts.metrics,
-            counts.topk,
-        );
+        let counts = store.user_ref_counts(uid)?;
+        if output.is_json() {
+            output.doc(json!({"uid": uid, "mode": mode, "dry_run": true, "counts": counts}));
+        } else {
+            println!(
+                "delete preflight uid={uid} mode={mode} {}",
+                format_counts(&counts)
+            );
+        }
         return Ok(());
     }
 
-    let now = now_ts();
     match mode {
         "soft" => {
-            user_service::delete_soft(&conn, uid, &now)?;
-            println!("deleted user uid={uid} mode=soft");
+            store.delete_user_soft(uid)?;
+            report_hook_warnings(&mut store);
+            if output.is_json() {
+                output.doc(json!({"uid": uid, "mode": "soft", "dry_run": false, "counts": null}));
+            } else {
+                println!("deleted user uid={uid} mode=soft");
+            }
             Ok(())
         }
         "hard" => {
-            user_service::delete_hard(&conn, uid, &now, force)?;
-            println!("deleted user uid={uid} mode=hard");
+            let counts = store.delete_user_hard(uid, force)?;
+            report_hook_warnings(&mut store);
+            if output.is_json() {
+                output.doc(json!({"uid": uid, "mode": "hard", "dry_run": false, "counts": counts}));
+            } else {
+                println!(
+                    "deleted user uid={uid} mode=hard {}",
+                    format_counts(&counts)
+                );
+            }
             Ok(())
         }
-        _ => Err("invalid --mode. expected: soft|hard".to_string()),
+        _ => Err(AppError::validation("invalid --mode. expected: soft|hard")),
+    }
+}
+
+fn format_counts(counts: &UserRefCounts) -> String {
+    counts
+        .fields()
+        .iter()
+        .map(|(name, n)| format!("{name}={n}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn user_export(
+    db_path: &str,
+    uid: &str,
+    out: Option<&str>,
+    output: Output,
+) -> Result<(), AppError> {
+    let doc = MemoryStore::open(db_path)?.export_user(uid)?;
+    match out {
+        Some(path) => {
+            let raw = serde_json::to_string_pretty(&doc)
+                .map_err(|e| AppError::storage(format!("failed to encode export: {e}")))?;
+            fs::write(path, raw).map_err(|e| AppError::io(e, "failed to write export file"))?;
+            let rows: usize = doc["tables"].as_object().map_or(0, |t| {
+                t.values().filter_map(Value::as_array).map(Vec::len).sum()
+            });
+            if output.is_json() {
+                output.doc(json!({"uid": uid, "rows": rows + 1, "file": path}));
+            } else {
+                println!("exported user uid={uid} rows={} file={path}", rows + 1);
+            }
+        }
+        None => output.doc(doc),
     }
+    Ok(())
 }
 
 pub fn identity_link(
     db_path: &str,
+    hooks: Option<&str>,
     uid: &str,
     channel: &str,
     channel_user_id: &str,
-) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let now = now_ts();
-    let identity_id = new_id("ident");
-    let observer = NoopObserver;
-
-    identity_service::link(
-        &conn,
-        &identity_id,
-        uid,
-        channel,
-        channel_user_id,
-        &now,
-        &observer,
-    )?;
-
-    println!("linked identity uid={uid} channel={channel} channel_user_id={channel_user_id}");
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let identity = store.link_identity(uid, channel, channel_user_id)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(identity));
+    } else {
+        println!("linked identity uid={uid} channel={channel} channel_user_id={channel_user_id}");
+    }
     Ok(())
 }
 
-pub fn identity_resolve(db_path: &str, channel: &str, channel_user_id: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    match identity_service::resolve(&conn, channel, channel_user_id)? {
-        Some(uid) => {
-            println!("resolved uid={uid} channel={channel} channel_user_id={channel_user_id}");
-            Ok(())
-        }
-        None => Err(format!("identity not found: {channel}:{channel_user_id}")),
+pub fn identity_resolve(
+    db_path: &str,
+    channel: &str,
+    channel_user_id: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let identity = MemoryStore::open(db_path)?
+        .resolve_identity(channel, channel_user_id)?
+        .ok_or_else(|| {
+            AppError::not_found(format!("identity not found: {channel}:{channel_user_id}"))
+        })?;
+    if output.is_json() {
+        output.doc(json!(identity));
+    } else {
+        println!(
+            "resolved uid={} channel={channel} channel_user_id={channel_user_id}",
+            identity.uid
+        );
     }
+    Ok(())
 }
 
-pub fn identity_unlink(db_path: &str, channel: &str, channel_user_id: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let n = identity_service::unlink(&conn, channel, channel_user_id)?;
-    if n == 0 {
-        return Err(format!("identity not found: {channel}:{channel_user_id}"));
+pub fn identity_unlink(
+    db_path: &str,
+    hooks: Option<&str>,
+    channel: &str,
+    channel_user_id: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    store.unlink_identity(channel, channel_user_id)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!({"channel": channel, "channel_user_id": channel_user_id}));
+    } else {
+        println!("unlinked identity channel={channel} channel_user_id={channel_user_id}");
     }
-    println!("unlinked identity channel={channel} channel_user_id={channel_user_id}");
     Ok(())
 }
 
-pub fn scope_create(db_path: &str, scope_id: &str, scope_type: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let now = now_ts();
-    scope_service::create(&conn, scope_id, scope_type, &now)?;
-    println!("created scope id={scope_id} type={scope_type}");
+pub fn scope_create(
+    db_path: &str,
+    hooks: Option<&str>,
+    scope_id: &str,
+    scope_type: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let scope = store.create_scope(scope_id, scope_type)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(scope));
+    } else {
+        println!("created scope id={scope_id} type={scope_type}");
+    }
     Ok(())
 }
 
 pub fn scope_add_member(
     db_path: &str,
+    hooks: Option<&str>,
     scope_id: &str,
     uid: &str,
     role: &str,
-) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let now = now_ts();
-    let observer = NoopObserver;
-    scope_service::add_member(&conn, scope_id, uid, role, &now, &observer)?;
-    println!("added scope member scope_id={scope_id} uid={uid} role={role}");
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let member = store.add_scope_member(scope_id, uid, role)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(member));
+    } else {
+        println!("added scope member scope_id={scope_id} uid={uid} role={role}");
+    }
     Ok(())
 }
 
-pub fn scope_list(db_path: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    for (id, kind) in scope_service::list(&conn)? {
-        println!("id={id} type={kind}");
+pub fn scope_list(db_path: &str, output: Output) -> Result<(), AppError> {
+    let scopes = MemoryStore::open(db_path)?.list_scopes()?;
+    if output.is_json() {
+        output.list(&scopes);
+    } else {
+        for s in scopes {
+            println!("id={} type={}", s.scope_id, s.scope_type);
+        }
     }
     Ok(())
 }
 
-pub fn scope_members(db_path: &str, scope_id: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    for (uid, role) in scope_service::members(&conn, scope_id)? {
-        println!("scope_id={scope_id} uid={uid} role={role}");
+pub fn scope_members(db_path: &str, scope_id: &str, output: Output) -> Result<(), AppError> {
+    let members = MemoryStore::open(db_path)?.scope_members(scope_id)?;
+    if output.is_json() {
+        output.list(&members);
+    } else {
+        for m in members {
+            println!("scope_id={} uid={} role={}", m.scope_id, m.uid, m.role);
+        }
     }
     Ok(())
 }
 
-fn parse_and_validate_schema(file: &str) -> Result<(SchemaDef, String), String> {
-    let raw = fs::read_to_string(file).map_err(|e| format!("failed to read schema file: {e}"))?;
-    let def: SchemaDef =
-        serde_json::from_str(&raw).map_err(|e| format!("invalid schema json: {e}"))?;
+fn parse_and_validate_schema(file: &str) -> Result<(SchemaDef, String), AppError> {
+    let raw =
+        fs::read_to_string(file).map_err(|e| AppError::io(e, "failed to read schema file"))?;
+    let def: SchemaDef = serde_json::from_str(&raw)
+        .map_err(|e| AppError::validation(format!("invalid schema json: {e}")))?;
     validate_schema_def(&def)?;
     Ok((def, raw))
 }
 
-pub fn schema_validate(file: &str) -> Result<(), String> {
+pub fn schema_validate(file: &str, output: Output) -> Result<(), AppError> {
     let (def, _) = parse_and_validate_schema(file)?;
-    println!("schema valid schema_id={}", def.schema_id);
+    if output.is_json() {
+        output.doc(json!({"schema_id": def.schema_id, "version": def.version, "valid": true}));
+    } else {
+        println!("schema valid schema_id={}", def.schema_id);
+    }
     Ok(())
 }
 
-pub fn schema_register(db_path: &str, file: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
+pub fn schema_register(
+    db_path: &str,
+    hooks: Option<&str>,
+    file: &str,
+    allow_breaking: bool,
+    migrate_data: bool,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
     let (def, raw) = parse_and_validate_schema(file)?;
+    let report = store.register_schema(&def, &raw, allow_breaking, migrate_data)?;
+    report_hook_warnings(&mut store);
 
-    let now = now_ts();
-    schema_registry_repo::upsert(&conn, &def, &raw, &now)?;
-    let table_name = dynamic_table_repo::create_table_for_schema(&conn, &def)?;
-
-    let outbox_id = new_id("outbox");
-    let payload = json!({
-        "event": "schema.registered",
-        "schema_id": &def.schema_id,
-        "version": &def.version,
-        "table": table_name,
+    if output.is_json() {
+        output.doc(json!({
+            "schema_id": def.schema_id,
+            "version": def.version,
+            "table": report.table,
+            "created": report.created,
+            "previous_version": report.previous_version,
+            "changes": report.changes,
+            "rows_copied": report.rows_copied,
+        }));
+        return Ok(());
+    }
+    if report.created {
+        println!(
+            "registered schema schema_id={} version={} table={}",
+            def.schema_id, def.version, report.table
+        );
+    } else {
+        println!(
+            "schema already registered schema_id={} version={} table={}",
+            def.schema_id, def.version, report.table
+        );
+    }
+    if let Some(previous) = &report.previous_version {
+        println!(
+            "previous_version={previous} changes={} breaking={}",
+            report.changes.len(),
+            report.changes.iter().any(|c| c.is_breaking())
+        );
+    }
+    if migrate_data {
+        println!("rows_copied={}", report.rows_copied);
+    }
+    Ok(())
+}
+
+pub fn schema_diff(
+    db_path: &str,
+    schema_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    output: Output,
+) -> Result<(), AppError> {
+    let diff = MemoryStore::open(db_path)?.diff_schema(schema_id, from, to)?;
+    if output.is_json() {
+        let mapped: Vec<_> = diff
+            .changes
+            .iter()
+            .map(|c| {
+                let mut v = json!(c);
+                v["breaking"] = json!(c.is_breaking());
+                v
+            })
+            .collect();
+        output.doc(json!({
+            "schema_id": diff.schema_id,
+            "from": diff.from,
+            "to": diff.to,
+            "compatible": diff.is_compatible(),
+            "changes": mapped,
+        }));
+    } else {
+        println!(
+            "schema diff schema_id={schema_id} from={} to={} compatible={}",
+            diff.from,
+            diff.to,
+            diff.is_compatible()
+        );
+        for c in &diff.changes {
+            println!("{c} breaking={}", c.is_breaking());
+        }
+    }
+    Ok(())
+}
+
+pub fn schema_list(db_path: &str, output: Output) -> Result<(), AppError> {
+    let schemas = MemoryStore::open(db_path)?.list_schemas()?;
+    if output.is_json() {
+        output.list(&schemas);
+    } else {
+        for s in schemas {
+            println!(
+                "schema_id={} version={} active={} created_at={}",
+                s.schema_id, s.version, s.active, s.created_at
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn schema_bind(
+    db_path: &str,
+    event_type: &str,
+    schema_id: &str,
+    mode: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let binding = MemoryStore::open(db_path)?.bind_schema(event_type, schema_id, mode)?;
+    if output.is_json() {
+        output.doc(json!(binding));
+    } else {
+        println!("bound event_type={event_type} schema_id={schema_id} mode={mode}");
+    }
+    Ok(())
+}
+
+pub fn schema_unbind(db_path: &str, event_type: &str, output: Output) -> Result<(), AppError> {
+    MemoryStore::open(db_path)?.unbind_schema(event_type)?;
+    if output.is_json() {
+        output.doc(json!({"event_type": event_type}));
+    } else {
+        println!("unbound event_type={event_type}");
+    }
+    Ok(())
+}
+
+pub fn schema_bindings(db_path: &str, output: Output) -> Result<(), AppError> {
+    let bindings = MemoryStore::open(db_path)?.schema_bindings()?;
+    if output.is_json() {
+        output.list(&bindings);
+    } else {
+        for b in bindings {
+            println!(
+                "event_type={} schema_id={} mode={} updated_at={}",
+                b.event_type, b.schema_id, b.mode, b.updated_at
+            );
+        }
+    }
+    Ok(())
+}
+
+fn record_json(r: &DynamicRecord) -> Value {
+    json!({
+        "record_id": r.record_id,
+        "entity_key": r.entity_key,
+        "ref_user_id": r.ref_user_id,
+        "ref_scope_id": r.ref_scope_id,
+        "payload": r.fields,
+        "created_at": r.created_at,
+        "updated_at": r.updated_at,
     })
-    .to_string();
-    projection_outbox_repo::enqueue(
-        &conn,
-        &outbox_id,
-        "schema",
-        "schema.registered",
-        &payload,
-        &now,
-    )?;
+}
+
+fn record_line(r: &DynamicRecord) -> String {
+    format!(
+        "record id={} entity_key={} payload={} updated_at={}",
+        r.record_id,
+        r.entity_key.as_deref().unwrap_or(""),
+        Value::Object(r.fields.clone()),
+        r.updated_at
+    )
+}
 
-    println!(
-        "registered schema schema_id={} version={} table={}",
-        def.schema_id,
-        def.version,
-        dynamic_table_repo::table_name_for(&def)
-    );
+#[allow(clippy::too_many_arguments)]
+pub fn record_put(
+    db_path: &str,
+    hooks: Option<&str>,
+    schema_id: &str,
+    version: Option<&str>,
+    file: &str,
+    record_id: Option<&str>,
+    entity_key: Option<&str>,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let raw =
+        fs::read_to_string(file).map_err(|e| AppError::io(e, "failed to read record file"))?;
+    let payload: Value = serde_json::from_str(&raw)
+        .map_err(|e| AppError::validation(format!("invalid json payload: {e}")))?;
+    let record_id = record_id.map_or_else(|| new_id("rec"), str::to_string);
+    let table = store.write(|tx, observer, now| {
+        record_service::put(
+            tx,
+            record_service::PutRecord {
+                schema_id,
+                version,
+                record_id: &record_id,
+                entity_key,
+                payload: &payload,
+                now,
+            },
+            observer,
+        )
+    })?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!({"schema_id": schema_id, "record_id": record_id, "table": table}));
+    } else {
+        println!("stored record schema_id={schema_id} id={record_id} table={table}");
+    }
     Ok(())
 }
 
-pub fn schema_list(db_path: &str) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let rows = schema_registry_repo::list(&conn)?;
+pub fn record_get(
+    db_path: &str,
+    schema_id: &str,
+    version: Option<&str>,
+    record_id: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let record = record_service::get(&conn, schema_id, version, record_id)?.ok_or_else(|| {
+        AppError::not_found(format!(
+            "record not found: schema_id={schema_id} id={record_id}"
+        ))
+    })?;
+    if output.is_json() {
+        output.doc(record_json(&record));
+    } else {
+        println!("{}", record_line(&record));
+    }
+    Ok(())
+}
 
-    for (schema_id, version, is_active, created_at) in rows {
+#[allow(clippy::too_many_arguments)]
+pub fn record_list(
+    db_path: &str,
+    schema_id: &str,
+    version: Option<&str>,
+    entity_key: Option<&str>,
+    uid: Option<&str>,
+    limit: usize,
+    output: Output,
+) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let records = record_service::list(&conn, schema_id, version, entity_key, uid, limit)?;
+    if output.is_json() {
+        let mapped: Vec<_> = records.iter().map(record_json).collect();
+        output.list(&mapped);
+    } else {
+        for r in &records {
+            println!("{}", record_line(r));
+        }
+    }
+    Ok(())
+}
+
+pub fn record_delete(
+    db_path: &str,
+    hooks: Option<&str>,
+    schema_id: &str,
+    version: Option<&str>,
+    record_id: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let n = store.write(|tx, observer, _| {
+        record_service::delete(tx, schema_id, version, record_id, observer)
+    })?;
+    if n == 0 {
+        return Err(AppError::not_found(format!(
+            "record not found: schema_id={schema_id} id={record_id}"
+        )));
+    }
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!({"schema_id": schema_id, "record_id": record_id}));
+    } else {
+        println!("deleted record schema_id={schema_id} id={record_id}");
+    }
+    Ok(())
+}
+
+pub fn materializer_register(db_path: &str, file: &str, output: Output) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let raw = fs::read_to_string(file)
+        .map_err(|e| AppError::io(e, "failed to read materializer file"))?;
+    let rule: MaterializerRule = serde_json::from_str(&raw)
+        .map_err(|e| AppError::validation(format!("invalid materializer rule json: {e}")))?;
+    let now = now_ts();
+    materializer_service::register(&conn, &rule, &now)?;
+    if output.is_json() {
+        output.doc(json!(rule));
+    } else {
+        println!(
+            "registered materializer rule_id={} event_type={} topic={}",
+            rule.rule_id, rule.event_type, rule.topic
+        );
+    }
+    Ok(())
+}
+
+pub fn materializer_list(db_path: &str, output: Output) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let rows = materializer_service::list(&conn)?;
+    if output.is_json() {
+        let mapped: Vec<_> = rows
+            .iter()
+            .map(|(rule, active, updated_at)| {
+                let mut v = json!(rule);
+                v["active"] = json!(active);
+                v["updated_at"] = json!(updated_at);
+                v
+            })
+            .collect();
+        output.list(&mapped);
+        return Ok(());
+    }
+    for (rule, active, updated_at) in rows {
         println!(
-            "schema_id={schema_id} version={version} active={} created_at={created_at}",
-            is_active == 1
+            "rule_id={} event_type={} item_path={} topic={} target={} active={active} updated_at={updated_at}",
+            rule.rule_id,
+            rule.event_type,
+            rule.item_path,
+            rule.topic,
+            rule.target.as_str(),
         );
     }
+    Ok(())
+}
 
+pub fn materializer_disable(db_path: &str, rule_id: &str, output: Output) -> Result<(), AppError> {
+    let conn = db::open_checked(db_path)?;
+    let now = now_ts();
+    if materializer_service::disable(&conn, rule_id, &now)? == 0 {
+        return Err(AppError::not_found(format!(
+            "materializer rule not found: {rule_id}"
+        )));
+    }
+    if output.is_json() {
+        output.doc(json!({"rule_id": rule_id, "active": false}));
+    } else {
+        println!("disabled materializer rule_id={rule_id}");
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn ingest_event(
     db_path: &str,
-    uid: &str,
+    hooks: Option<&str>,
+    user: &UserRef,
     scope_id: &str,
     event_type: &str,
     file: &str,
     idempotency_key: Option<&str>,
-) -> Result<(), String> {
-    let mut conn = open_db_checked(db_path)?;
-    let raw = fs::read_to_string(file).map_err(|e| format!("failed to read event file: {e}"))?;
-    let payload: Value =
-        serde_json::from_str(&raw).map_err(|e| format!("invalid json payload: {e}"))?;
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let raw = fs::read_to_string(file).map_err(|e| AppError::io(e, "failed to read event file"))?;
+    let payload: Value = serde_json::from_str(&raw)
+        .map_err(|e| AppError::validation(format!("invalid json payload: {e}")))?;
 
-    let event_id = new_id("evt");
-    let now = now_ts();
+    let outcome = store.ingest_event(user, scope_id, event_type, &payload, idempotency_key)?;
+    report_hook_warnings(&mut store);
 
-    match ingest_service::ingest(
-        &mut conn,
-        ingest_service::IngestInput {
-            uid,
-            scope_id,
-            event_type,
-            payload: &payload,
-            idempotency_key,
-            event_id: &event_id,
-            now: &now,
-        },
-    )? {
+    if output.is_json() {
+        output.doc(json!(outcome));
+        return Ok(());
+    }
+    match outcome {
         ingest_service::IngestOutcome::Duplicate { idempotency_key } => {
             println!("duplicate event ignored idempotency_key={idempotency_key}");
         }
         ingest_service::IngestOutcome::Inserted {
+            uid,
             event_id,
             event_type,
         } => {
-            println!("ingested event id={event_id} type={event_type}");
+            println!("ingested event id={event_id} type={event_type} uid={uid}");
         }
     }
 
     Ok(())
 }
 
-pub fn query_latest(db_path: &str, uid: &str, scope_id: &str, as_json: bool) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    if let Some((event_id, event_type, event_ts)) = query_service::latest(&conn, uid, scope_id)? {
-        if as_json {
-            println!(
-                "{}",
-                json!({"event_id": event_id, "event_type": event_type, "event_ts": event_ts})
-            );
-        } else {
-            println!("latest event_id={event_id} type={event_type} ts={event_ts}");
+#[derive(Deserialize)]
+struct BatchLineJson {
+    uid: Option<String>,
+    channel: Option<String>,
+    channel_user_id: Option<String>,
+    #[serde(alias = "scope")]
+    scope_id: String,
+    #[serde(alias = "type")]
+    event_type: String,
+    payload: Value,
+    idempotency_key: Option<String>,
+}
+
+fn read_batch_input(file: &str) -> Result<String, AppError> {
+    if file == "-" {
+        let mut raw = String::new();
+        std::io::stdin()
+            .read_to_string(&mut raw)
+            .map_err(|e| AppError::io(e, "failed to read batch from stdin"))?;
+        Ok(raw)
+    } else {
+        fs::read_to_string(file).map_err(|e| AppError::io(e, "failed to read batch file"))
+    }
+}
+
+pub fn ingest_batch(
+    db_path: &str,
+    hooks: Option<&str>,
+    file: &str,
+    atomic: bool,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let raw = read_batch_input(file)?;
+    let batch_id = new_id("evt");
+
+    let lines: Vec<_> = raw
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(idx, l)| {
+            let line = idx + 1;
+            let parsed = serde_json::from_str::<BatchLineJson>(l)
+                .map_err(|e| AppError::validation(format!("invalid json line: {e}")))
+                .and_then(|j| {
+                    Ok(ingest_service::BatchEvent {
+                        user: UserRef::from_parts(j.uid, j.channel, j.channel_user_id)
+                            .map_err(|e| e.prefixed("line "))?,
+                        scope_id: j.scope_id,
+                        event_type: j.event_type,
+                        payload: j.payload,
+                        idempotency_key: j.idempotency_key,
+                        event_id: format!("{batch_id}_{line}"),
+                    })
+                });
+            ingest_service::BatchLine { line, parsed }
+        })
+        .collect();
+
+    let results = store.ingest_batch(lines, atomic)?;
+    report_hook_warnings(&mut store);
+
+    let (mut inserted, mut duplicate, mut errors, mut rolled_back) = (0, 0, 0, 0);
+    let mut report = Vec::with_capacity(results.len());
+    let mut text = Vec::with_capacity(results.len());
+    for (line, outcome) in &results {
+        match outcome {
+            ingest_service::BatchLineOutcome::Ingested(
+                ingest_service::IngestOutcome::Inserted {
+                    uid,
+                    event_id,
+                    event_type,
+                },
+            ) => {
+                inserted += 1;
+                report.push(json!({"line": line, "status": "inserted", "event_id": event_id, "event_type": event_type, "uid": uid}));
+                text.push(format!(
+                    "line={line} status=inserted id={event_id} type={event_type}"
+                ));
+            }
+            ingest_service::BatchLineOutcome::Ingested(
+                ingest_service::IngestOutcome::Duplicate { idempotency_key },
+            ) => {
+                duplicate += 1;
+                report.push(json!({"line": line, "status": "duplicate", "idempotency_key": idempotency_key}));
+                text.push(format!(
+                    "line={line} status=duplicate idempotency_key={idempotency_key}"
+                ));
+            }
+            ingest_service::BatchLineOutcome::Failed(e) => {
+                errors += 1;
+                report.push(json!({"line": line, "status": "error", "code": e.kind().code(), "message": e.message()}));
+                text.push(format!("line={line} status=error message={e}"));
+            }
+            ingest_service::BatchLineOutcome::RolledBack => {
+                rolled_back += 1;
+                report.push(json!({"line": line, "status": "rolled_back"}));
+                text.push(format!("line={line} status=rolled_back"));
+            }
+        }
+    }
+
+    if output.is_json() {
+        output.doc(json!({
+            "atomic": atomic,
+            "lines": results.len(),
+            "inserted": inserted,
+            "duplicate": duplicate,
+            "errors": errors,
+            "rolled_back": rolled_back,
+            "results": report,
+        }));
+    } else {
+        for t in text {
+            println!("{t}");
+        }
+        println!(
+            "batch lines={} inserted={inserted} duplicate={duplicate} errors={errors} rolled_back={rolled_back} atomic={atomic}",
+            results.len()
+        );
+    }
+
+    if errors > 0 {
+        if atomic {
+            return Err(AppError::validation(format!(
+                "batch rolled back: {errors} line(s) failed"
+            )));
+        }
+        return Err(AppError::validation(format!(
+            "batch completed with {errors} failed line(s)"
+        )));
+    }
+    Ok(())
+}
+
+pub fn query_latest(
+    db_path: &str,
+    user: &UserRef,
+    scopes: &ScopeSelection,
+    output: Output,
+) -> Result<(), AppError> {
+    let latest = MemoryStore::open(db_path)?.latest_event(user, scopes)?;
+    match latest {
+        Some(e) if scopes.is_single() => {
+            if output.is_json() {
+                output.doc(
+                    json!({"event_id": e.event_id, "event_type": e.event_type, "event_ts": e.event_ts}),
+                );
+            } else {
+                println!(
+                    "latest event_id={} type={} ts={}",
+                    e.event_id, e.event_type, e.event_ts
+                );
+            }
         }
+        Some(e) => {
+            if output.is_json() {
+                output.doc(json!(e));
+            } else {
+                println!(
+                    "latest scope={} event_id={} type={} ts={}",
+                    e.scope_id, e.event_id, e.event_type, e.event_ts
+                );
+            }
+        }
+        None if output.is_json() => output.doc(Value::Null),
+        None => {}
     }
     Ok(())
 }
 
 pub fn query_metric(
     db_path: &str,
-    uid: &str,
-    scope_id: &str,
+    user: &UserRef,
+    scopes: &ScopeSelection,
     key: Option<&str>,
     prefix: Option<&str>,
-    as_json: bool,
-) -> Result<(), String> {
+    output: Output,
+) -> Result<(), AppError> {
     if key.is_none() && prefix.is_none() {
-        return Err("query metric requires either --key or --prefix".to_string());
+        return Err(AppError::validation(
+            "query metric requires either --key or --prefix",
+        ));
     }
-    let conn = open_db_checked(db_path)?;
-    let rows = query_service::metric(&conn, uid, scope_id, key, prefix)?;
-    if as_json {
-        let mapped: Vec<_> = rows
-            .into_iter()
-            .map(|(k, v, j)| json!({"key": k, "value": v, "json": j}))
+    let metrics = MemoryStore::open(db_path)?.metrics(user, scopes, key, prefix)?;
+    if output.is_json() {
+        let mapped: Vec<_> = metrics
+            .iter()
+            .map(|m| {
+                if scopes.is_single() {
+                    json!({"key": m.key, "value": m.value, "json": m.json})
+                } else {
+                    json!(m)
+                }
+            })
             .collect();
-        println!("{}", json!(mapped));
+        output.list(&mapped);
     } else {
-        for (k, v, j) in rows {
-            println!("metric key={k} value={v} json={j}");
+        for m in metrics {
+            if scopes.is_single() {
+                println!("metric key={} value={} json={}", m.key, m.value, m.json);
+            } else {
+                println!(
+                    "metric scope={} key={} value={} json={}",
+                    m.scope_id, m.key, m.value, m.json
+                );
+            }
         }
     }
     Ok(())
@@ -425,24 +1372,189 @@ pub fn query_metric(
 
 pub fn query_topk(
     db_path: &str,
-    uid: &str,
-    scope_id: &str,
+    user: &UserRef,
+    scopes: &ScopeSelection,
     topic: &str,
     limit: usize,
-    as_json: bool,
-) -> Result<(), String> {
-    let conn = open_db_checked(db_path)?;
-    let rows = query_service::topk(&conn, uid, scope_id, topic, limit)?;
-    if as_json {
-        let mapped: Vec<_> = rows
-            .into_iter()
-            .map(|(rank, item, weight)| json!({"rank": rank, "item": item, "weight": weight}))
+    output: Output,
+) -> Result<(), AppError> {
+    let items = MemoryStore::open(db_path)?.topk(user, scopes, topic, limit)?;
+    if output.is_json() {
+        let mapped: Vec<_> = items
+            .iter()
+            .map(|t| {
+                if scopes.is_single() {
+                    json!({"rank": t.rank, "item": t.item, "weight": t.weight})
+                } else {
+                    json!(t)
+                }
+            })
             .collect();
-        println!("{}", json!(mapped));
+        output.list(&mapped);
+    } else {
+        for t in items {
+            if scopes.is_single() {
+                println!("rank={} item={} weight={}", t.rank, t.item, t.weight);
+            } else {
+                println!(
+                    "rank={} scope={} item={} weight={}",
+                    t.rank, t.scope_id, t.item, t.weight
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn state_get(
+    db_path: &str,
+    user: &UserRef,
+    scope_id: &str,
+    key: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let store = MemoryStore::open(db_path)?;
+    let entry = store.get_state(user, scope_id, key)?;
+    if output.is_json() {
+        output.doc(json!(entry));
+    } else {
+        println!(
+            "state key={key} value={} updated_at={}",
+            entry.value, entry.updated_at
+        );
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn state_set(
+    db_path: &str,
+    hooks: Option<&str>,
+    user: &UserRef,
+    scope_id: &str,
+    key: &str,
+    value: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let value: Value = serde_json::from_str(value)
+        .map_err(|e| AppError::validation(format!("invalid json value: {e}")))?;
+    let mut store = open_store(db_path, hooks)?;
+    let entry = store.set_state(user, scope_id, key, &value)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!(entry));
+    } else {
+        println!("set state scope_id={scope_id} uid={} key={key}", entry.uid);
+    }
+    Ok(())
+}
+
+pub fn state_delete(
+    db_path: &str,
+    hooks: Option<&str>,
+    user: &UserRef,
+    scope_id: &str,
+    key: &str,
+    output: Output,
+) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let uid = store.delete_state(user, scope_id, key)?;
+    report_hook_warnings(&mut store);
+    if output.is_json() {
+        output.doc(json!({"scope_id": scope_id, "uid": uid, "key": key}));
     } else {
-        for (rank, item, weight) in rows {
-            println!("rank={rank} item={item} weight={weight}");
+        println!("deleted state scope_id={scope_id} uid={uid} key={key}");
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn query_summary(
+    db_path: &str,
+    user: &UserRef,
+    scope_id: &str,
+    period: &str,
+    event_type: Option<&str>,
+    topic: Option<&str>,
+    limit: usize,
+    output: Output,
+) -> Result<(), AppError> {
+    let summaries =
+        MemoryStore::open(db_path)?.summaries(user, scope_id, period, event_type, topic, limit)?;
+    if output.is_json() {
+        output.list(&summaries);
+    } else {
+        for r in summaries {
+            println!(
+                "period={period} start={} type={} topic={} count={} sum={} distinct={}",
+                r.period_start, r.event_type, r.topic, r.count, r.sum, r.distinct_items
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Answers JSON-RPC requests from stdin, one per line, on a single open store until EOF.
+pub fn serve_stdio(db_path: &str, hooks: Option<&str>) -> Result<(), AppError> {
+    let mut store = open_store(db_path, hooks)?;
+    let mut stdout = std::io::stdout().lock();
+    for line in std::io::stdin().lock().lines() {
+        let line = line.map_err(|e| AppError::io(e, "failed to read request"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = rpc::handle_line(&mut store, &line) {
+            writeln!(stdout, "{response}")
+                .and_then(|()| stdout.flush())
+                .map_err(|e| AppError::io(e, "failed to write response"))?;
         }
+        report_hook_warnings(&mut store);
     }
     Ok(())
 }
+
+/// Serves the HTTP API on a TCP address; prints the bound address first so port 0 is usable.
+pub fn serve_http(db_path: &str, hooks: Option<&str>, addr: &str) -> Result<(), AppError> {
+    // The API has no authentication, so it is only served on loopback addresses.
+    let socket: std::net::SocketAddr = addr.parse().map_err(|_| {
+        AppError::validation(format!(
+            "invalid --http address: {addr}. expected IP:PORT such as 127.0.0.1:8080"
+        ))
+    })?;
+    if !socket.ip().is_loopback() {
+        return Err(AppError::validation(format!(
+            "--http must listen on a loopback address (127.0.0.1 or ::1); got {addr}"
+        )));
+    }
+    let store = open_store(db_path, hooks)?;
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| AppError::new(ErrorKind::Io, format!("failed to listen on {addr}: {e}")))?;
+    let bound = server
+        .server_addr()
+        .to_ip()
+        .map_or_else(|| addr.to_string(), |a| a.to_string());
+    run_http_server(&server, db_path, store, &format!("http://{bound}"))
+}
+
+/// Serves the HTTP API on a Unix domain socket at `path`.
+pub fn serve_unix(db_path: &str, hooks: Option<&str>, path: &str) -> Result<(), AppError> {
+    let store = open_store(db_path, hooks)?;
+    let server = tiny_http::Server::http_unix(Path::new(path))
+        .map_err(|e| AppError::new(ErrorKind::Io, format!("failed to listen on {path}: {e}")))?;
+    run_http_server(&server, db_path, store, &format!("unix:{path}"))
+}
+
+fn run_http_server(
+    server: &tiny_http::Server,
+    db_path: &str,
+    store: MemoryStore,
+    listening: &str,
+) -> Result<(), AppError> {
+    println!("listening={listening}");
+    std::io::stdout()
+        .flush()
+        .map_err(|e| AppError::io(e, "failed to write stdout"))?;
+    http::serve(server, db_path, store, &|failure| {
+        eprintln!("warning: {failure}")
+    })
+}
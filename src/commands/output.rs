@@ -0,0 +1,53 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// How a command prints its result; selected by the global `--json` / `--ndjson` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `key=value` lines.
+    Text,
+    /// One JSON document per command.
+    Json,
+    /// Like `Json`, but list results print one object per line.
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    format: Format,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Self {
+        Output { format }
+    }
+
+    /// `--ndjson` wins over `--json`; neither means text.
+    pub fn from_flags(json: bool, ndjson: bool) -> Self {
+        Self::new(match (json, ndjson) {
+            (_, true) => Format::Ndjson,
+            (true, false) => Format::Json,
+            (false, false) => Format::Text,
+        })
+    }
+
+    pub fn is_json(self) -> bool {
+        self.format != Format::Text
+    }
+
+    /// Prints a single result document on one line.
+    pub fn doc(self, doc: Value) {
+        println!("{doc}");
+    }
+
+    /// Prints a list result: a JSON array, or one object per line under `--ndjson`.
+    pub fn list<T: Serialize>(self, items: &[T]) {
+        if self.format == Format::Ndjson {
+            for item in items {
+                println!("{}", json!(item));
+            }
+        } else {
+            println!("{}", json!(items));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::error::AppError;
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Embedded migrations in apply order. Append new entries; never edit an applied one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "core_schema",
+        sql: include_str!("../../specs/SCHEMA_SQLITE_V01.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "materializer_rules",
+        sql: include_str!("migrations/0002_materializer_rules.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "archive_manifest",
+        sql: include_str!("migrations/0003_archive_manifest.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "event_summaries",
+        sql: include_str!("migrations/0004_event_summaries.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "event_schema_bindings",
+        sql: include_str!("migrations/0005_event_schema_bindings.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "schema_versions",
+        sql: include_str!("migrations/0006_schema_versions.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "outbox_delivery",
+        sql: include_str!("migrations/0007_outbox_delivery.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "user_tombstones",
+        sql: include_str!("migrations/0008_user_tombstones.sql"),
+    },
+];
+
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn migrations_table_exists(conn: &Connection) -> Result<bool, AppError> {
+    let n: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM sqlite_master WHERE type='table' AND name='schema_migrations'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| AppError::db(e, "failed migration table check"))?;
+    Ok(n > 0)
+}
+
+pub fn applied(conn: &Connection) -> Result<Vec<AppliedMigration>, AppError> {
+    if !migrations_table_exists(conn)? {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn
+        .prepare(
+            "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to read schema_migrations"))?;
+    let rows = stmt
+        .query_map([], |r| {
+            Ok(AppliedMigration {
+                version: r.get(0)?,
+                name: r.get(1)?,
+                checksum: r.get(2)?,
+                applied_at: r.get(3)?,
+            })
+        })
+        .map_err(|e| AppError::db(e, "failed to read schema_migrations"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read schema_migrations row"))?);
+    }
+    Ok(out)
+}
+
+pub fn current_version(conn: &Connection) -> Result<i64, AppError> {
+    if !migrations_table_exists(conn)? {
+        return Ok(0);
+    }
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        [],
+        |r| r.get(0),
+    )
+    .map_err(|e| AppError::db(e, "failed to read schema version"))
+}
+
+/// Rejects databases written by a newer binary or whose applied migrations were altered.
+pub fn verify(conn: &Connection) -> Result<(), AppError> {
+    for a in applied(conn)? {
+        match MIGRATIONS.iter().find(|m| m.version == a.version) {
+            None => {
+                return Err(AppError::schema_mismatch(format!(
+                    "database schema version {} is newer than this binary supports ({}); upgrade agent-memory-cli",
+                    a.version,
+                    latest_version()
+                )))
+            }
+            Some(m) if checksum(m.sql) != a.checksum => {
+                return Err(AppError::schema_mismatch(format!(
+                    "checksum mismatch for applied migration {} ({}); refusing to continue",
+                    a.version, a.name
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Pending migrations up to `to` (latest when `None`), after verifying applied history.
+pub fn plan(conn: &Connection, to: Option<i64>) -> Result<Vec<&'static Migration>, AppError> {
+    verify(conn)?;
+    let current = current_version(conn)?;
+    let target = to.unwrap_or_else(latest_version);
+    if !MIGRATIONS.iter().any(|m| m.version == target) && target != 0 {
+        return Err(AppError::validation(format!(
+            "unknown migration version: {target}"
+        )));
+    }
+    if target < current {
+        return Err(AppError::validation(format!(
+            "cannot migrate down from version {current} to {target}: downgrades are not supported"
+        )));
+    }
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+        .collect())
+}
+
+pub fn apply(
+    conn: &mut Connection,
+    pending: &[&'static Migration],
+    now: &str,
+) -> Result<(), AppError> {
+    // journal_mode cannot change inside a transaction, so switch before applying.
+    conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))
+        .map_err(|e| AppError::db(e, "failed to enable WAL"))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+           version INTEGER PRIMARY KEY,
+           name TEXT NOT NULL,
+           checksum TEXT NOT NULL,
+           applied_at TEXT NOT NULL
+         );",
+    )
+    .map_err(|e| AppError::db(e, "failed to create schema_migrations"))?;
+
+    for m in pending {
+        let tx = conn
+            .transaction()
+            .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+        tx.execute_batch(m.sql)
+            .map_err(|e| AppError::db(e, format!("migration {} ({}) failed", m.version, m.name)))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES (?1, ?2, ?3, ?4)",
+            params![m.version, m.name, checksum(m.sql), now],
+        )
+        .map_err(|e| AppError::db(e, format!("failed to record migration {}", m.version)))?;
+        tx.commit()
+            .map_err(|e| AppError::db(e, format!("failed to commit migration {}", m.version)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS materializer_rules (
+  rule_id TEXT PRIMARY KEY,
+  event_type TEXT NOT NULL,
+  rule_json TEXT NOT NULL,
+  is_active INTEGER NOT NULL DEFAULT 1,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_materializer_rules_event_type ON materializer_rules(event_type, is_active);
+
+INSERT OR IGNORE INTO materializer_rules (rule_id, event_type, rule_json, is_active, created_at, updated_at) VALUES
+  ('builtin.meal_rated.food_pref', 'meal.rated', '{"rule_id":"builtin.meal_rated.food_pref","event_type":"meal.rated","item_path":"$.cuisine","topic":"food_pref","target":"topk"}', 1, '0', '0'),
+  ('builtin.expense_logged.spend_category', 'expense.logged', '{"rule_id":"builtin.expense_logged.spend_category","event_type":"expense.logged","item_path":"$.category","topic":"spend_category","target":"topk"}', 1, '0', '0'),
+  ('builtin.request_logged.request_pattern', 'request.logged', '{"rule_id":"builtin.request_logged.request_pattern","event_type":"request.logged","item_path":"$.pattern","topic":"request_pattern","target":"topk"}', 1, '0', '0');
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS archive_manifest (
+  archive_id TEXT PRIMARY KEY,
+  month TEXT NOT NULL,
+  file_path TEXT NOT NULL,
+  row_count INTEGER NOT NULL,
+  min_event_ts TEXT NOT NULL,
+  max_event_ts TEXT NOT NULL,
+  sha256 TEXT NOT NULL,
+  archived_at TEXT NOT NULL,
+  restored_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_archive_manifest_month ON archive_manifest(month);
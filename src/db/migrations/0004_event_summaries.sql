@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS event_summaries (
+  period TEXT NOT NULL,
+  period_start TEXT NOT NULL,
+  scope_id TEXT NOT NULL,
+  uid TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  topic TEXT NOT NULL DEFAULT '',
+  event_count INTEGER NOT NULL,
+  value_sum REAL NOT NULL DEFAULT 0,
+  distinct_items INTEGER NOT NULL DEFAULT 0,
+  items_json TEXT NOT NULL DEFAULT '{}',
+  updated_at TEXT NOT NULL,
+  PRIMARY KEY(period, period_start, scope_id, uid, event_type, topic)
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_summaries_lookup ON event_summaries(scope_id, uid, period, period_start DESC);
+
+CREATE TABLE IF NOT EXISTS compaction_ledger (
+  event_id TEXT PRIMARY KEY,
+  compacted_at TEXT NOT NULL
+);
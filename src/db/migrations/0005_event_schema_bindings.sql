@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS event_schema_bindings (
+  event_type TEXT PRIMARY KEY,
+  schema_id TEXT NOT NULL,
+  mode TEXT NOT NULL DEFAULT 'strict',
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_schema_bindings_schema ON event_schema_bindings(schema_id);
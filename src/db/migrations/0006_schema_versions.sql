@@ -0,0 +1,33 @@
+-- schema_registry becomes version history keyed on (schema_id, version).
+-- dynamic_records referenced schema_registry(schema_id), which is no longer unique,
+-- so it is rebuilt first without that foreign key.
+CREATE TABLE dynamic_records_new (
+  record_id TEXT PRIMARY KEY,
+  schema_id TEXT NOT NULL,
+  entity_key TEXT NOT NULL,
+  uid TEXT,
+  scope_id TEXT,
+  payload_json TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+INSERT INTO dynamic_records_new
+  SELECT record_id, schema_id, entity_key, uid, scope_id, payload_json, created_at, updated_at
+  FROM dynamic_records;
+DROP TABLE dynamic_records;
+ALTER TABLE dynamic_records_new RENAME TO dynamic_records;
+CREATE INDEX IF NOT EXISTS idx_dynamic_records_schema_entity ON dynamic_records(schema_id, entity_key);
+CREATE INDEX IF NOT EXISTS idx_dynamic_records_uid ON dynamic_records(uid);
+
+CREATE TABLE schema_registry_new (
+  schema_id TEXT NOT NULL,
+  version TEXT NOT NULL,
+  schema_json TEXT NOT NULL,
+  is_active INTEGER NOT NULL DEFAULT 1,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY(schema_id, version)
+);
+INSERT INTO schema_registry_new
+  SELECT schema_id, version, schema_json, is_active, created_at FROM schema_registry;
+DROP TABLE schema_registry;
+ALTER TABLE schema_registry_new RENAME TO schema_registry;
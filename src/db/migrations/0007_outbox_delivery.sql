@@ -0,0 +1,6 @@
+ALTER TABLE projection_outbox ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE projection_outbox ADD COLUMN next_attempt_at TEXT;
+ALTER TABLE projection_outbox ADD COLUMN last_error TEXT;
+ALTER TABLE projection_outbox ADD COLUMN dead_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_projection_outbox_pending ON projection_outbox(delivered_at, dead_at, created_at);
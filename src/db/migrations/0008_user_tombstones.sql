@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS user_tombstones (
+  uid TEXT PRIMARY KEY,
+  counts_json TEXT NOT NULL,
+  deleted_at TEXT NOT NULL
+);
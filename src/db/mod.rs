@@ -1,17 +1,59 @@
+pub mod migrate;
+
+use crate::error::AppError;
 use rusqlite::Connection;
 use std::path::Path;
+use std::time::Duration;
 
-pub fn connect(db_path: &str) -> Result<Connection, String> {
-    Connection::open(db_path).map_err(|e| format!("failed to open db {}: {e}", db_path))
+pub fn connect(db_path: &str) -> Result<Connection, AppError> {
+    let conn = Connection::open(db_path)
+        .map_err(|e| AppError::db(e, format!("failed to open db {}", db_path)))?;
+    // `foreign_keys` is a per-connection setting; the PRAGMA in the schema file does not persist.
+    conn.pragma_update(None, "foreign_keys", "ON")
+        .map_err(|e| AppError::db(e, "failed to enable foreign keys"))?;
+    // Several connections may share the file (e.g. `serve --http` readers); wait out locks.
+    conn.busy_timeout(Duration::from_secs(5))
+        .map_err(|e| AppError::db(e, "failed to set busy timeout"))?;
+    Ok(conn)
 }
 
-pub fn ensure_parent_dir(db_path: &str) -> Result<(), String> {
+pub fn ensure_parent_dir(db_path: &str) -> Result<(), AppError> {
     let db = Path::new(db_path);
     if let Some(parent) = db.parent() {
         if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| format!("failed to create db directory {}: {e}", parent.display()))?;
+            std::fs::create_dir_all(parent).map_err(|e| {
+                AppError::io(
+                    e,
+                    format!("failed to create db directory {}", parent.display()),
+                )
+            })?;
         }
     }
     Ok(())
 }
+
+/// Opens a db that is migrated to this binary's latest schema version.
+pub fn open_checked(db_path: &str) -> Result<Connection, AppError> {
+    let conn = connect(db_path)?;
+    let exists: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM sqlite_master WHERE type='table' AND name='users'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| AppError::db(e, "failed schema check"))?;
+    if exists == 0 {
+        return Err(AppError::schema_not_initialized(
+            "schema not initialized. run: agent-memory-cli admin migrate --db <path>",
+        ));
+    }
+    migrate::verify(&conn)?;
+    let version = migrate::current_version(&conn)?;
+    if version < migrate::latest_version() {
+        return Err(AppError::schema_not_initialized(format!(
+            "schema version {version} is behind {}. run: agent-memory-cli admin migrate --db <path>",
+            migrate::latest_version()
+        )));
+    }
+    Ok(conn)
+}
@@ -0,0 +1,178 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterializerTarget {
+    /// `metrics` row `counter:<topic>:<item>` incremented by delta
+    Counter,
+    /// counter plus a rebuilt `topk` ranking for the topic
+    Topk,
+    /// `state` row keyed by topic holding the latest item value
+    State,
+}
+
+impl MaterializerTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaterializerTarget::Counter => "counter",
+            MaterializerTarget::Topk => "topk",
+            MaterializerTarget::State => "state",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaterializerRule {
+    pub rule_id: String,
+    pub event_type: String,
+    pub item_path: String,
+    pub topic: String,
+    /// Number literal or `$.path` into the payload; defaults to 1.
+    #[serde(default)]
+    pub delta: Option<Value>,
+    pub target: MaterializerTarget,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Materialization {
+    Counter {
+        topic: String,
+        item: String,
+        delta: f64,
+    },
+    Topk {
+        topic: String,
+        item: String,
+        delta: f64,
+    },
+    State {
+        key: String,
+        value: Value,
+    },
+}
+
+enum DeltaExpr {
+    Const(f64),
+    Path(String),
+}
+
+fn parse_delta(delta: Option<&Value>) -> Result<DeltaExpr, AppError> {
+    match delta {
+        None => Ok(DeltaExpr::Const(1.0)),
+        Some(Value::Number(n)) => n
+            .as_f64()
+            .map(DeltaExpr::Const)
+            .ok_or_else(|| AppError::validation("materializer rule delta must be a finite number")),
+        Some(Value::String(s)) if s.starts_with('$') => {
+            validate_path(s)?;
+            Ok(DeltaExpr::Path(s.clone()))
+        }
+        Some(Value::String(s)) => s.trim().parse::<f64>().map(DeltaExpr::Const).map_err(|_| {
+            AppError::validation(format!(
+                "materializer rule delta is not a number or $.path: {s}"
+            ))
+        }),
+        Some(other) => Err(AppError::validation(format!(
+            "materializer rule delta must be a number or $.path, got: {other}"
+        ))),
+    }
+}
+
+fn validate_path(path: &str) -> Result<(), AppError> {
+    let rest = path.strip_prefix("$.").unwrap_or(path);
+    if rest.is_empty() || rest.split('.').any(|seg| seg.trim().is_empty()) {
+        return Err(AppError::validation(format!("invalid json path: {path}")));
+    }
+    Ok(())
+}
+
+fn display_path(path: &str) -> &str {
+    path.strip_prefix("$.").unwrap_or(path)
+}
+
+/// Resolves a dotted path (`$.a.b` or `a.b`) against the payload.
+pub fn extract_path<'a>(payload: &'a Value, path: &str) -> Option<&'a Value> {
+    display_path(path)
+        .split('.')
+        .try_fold(payload, |cur, seg| cur.get(seg))
+}
+
+pub fn validate_rule(rule: &MaterializerRule) -> Result<(), AppError> {
+    if rule.rule_id.trim().is_empty() {
+        return Err(AppError::validation(
+            "materializer rule validation failed: rule_id is required",
+        ));
+    }
+    if rule.event_type.trim().is_empty() {
+        return Err(AppError::validation(
+            "materializer rule validation failed: event_type is required",
+        ));
+    }
+    if rule.topic.trim().is_empty() {
+        return Err(AppError::validation(
+            "materializer rule validation failed: topic is required",
+        ));
+    }
+    if rule.topic.contains(':') {
+        return Err(AppError::validation(format!(
+            "materializer rule validation failed: topic must not contain ':' (rule_id={})",
+            rule.rule_id
+        )));
+    }
+    validate_path(&rule.item_path)
+        .map_err(|e| e.prefixed("materializer rule validation failed: item_path "))?;
+    parse_delta(rule.delta.as_ref())
+        .map_err(|e| e.prefixed("materializer rule validation failed: "))?;
+    Ok(())
+}
+
+/// Evaluates a rule against an event payload, failing when the payload lacks the fields
+/// the rule depends on.
+pub fn evaluate(rule: &MaterializerRule, payload: &Value) -> Result<Materialization, AppError> {
+    if rule.target == MaterializerTarget::State {
+        let value = extract_path(payload, &rule.item_path).ok_or_else(|| {
+            AppError::validation(format!(
+                "{} requires field: {}",
+                rule.event_type,
+                display_path(&rule.item_path)
+            ))
+        })?;
+        return Ok(Materialization::State {
+            key: rule.topic.clone(),
+            value: value.clone(),
+        });
+    }
+
+    let item = extract_path(payload, &rule.item_path)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| {
+            AppError::validation(format!(
+                "{} requires string field: {}",
+                rule.event_type,
+                display_path(&rule.item_path)
+            ))
+        })?
+        .to_string();
+
+    let delta = match parse_delta(rule.delta.as_ref())? {
+        DeltaExpr::Const(n) => n,
+        DeltaExpr::Path(path) => extract_path(payload, &path)
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| {
+                AppError::validation(format!(
+                    "{} requires numeric field: {}",
+                    rule.event_type,
+                    display_path(&path)
+                ))
+            })?,
+    };
+
+    let topic = rule.topic.clone();
+    if rule.target == MaterializerTarget::Counter {
+        Ok(Materialization::Counter { topic, item, delta })
+    } else {
+        Ok(Materialization::Topk { topic, item, delta })
+    }
+}
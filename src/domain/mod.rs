@@ -1,33 +1,144 @@
+pub mod materializer;
+pub mod period;
 pub mod schema;
 
-#[derive(Debug, Clone)]
+use crate::error::AppError;
+use rusqlite::Connection;
+use serde::Serialize;
+
+/// Every value [`DomainEvent::name`] can return.
+pub const EVENT_NAMES: [&str; 14] = [
+    "user.created",
+    "user.updated",
+    "user.merged",
+    "user.deleted",
+    "identity.linked",
+    "identity.unlinked",
+    "scope.created",
+    "scope.member_added",
+    "event.ingested",
+    "state.set",
+    "state.deleted",
+    "record.stored",
+    "record.deleted",
+    "schema.registered",
+];
+
+/// A committed change to the memory store. Serializes to the outbox payload, tagged with
+/// `event` = [`DomainEvent::name`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
 pub enum DomainEvent {
+    #[serde(rename = "user.created")]
     UserCreated { uid: String },
-    IdentityLinked { uid: String, channel: String },
-    ScopeMemberAdded { scope_id: String, uid: String },
+    #[serde(rename = "user.updated")]
+    UserUpdated { uid: String },
+    #[serde(rename = "user.merged")]
+    UsersMerged { from_uid: String, to_uid: String },
+    #[serde(rename = "user.deleted")]
+    UserDeleted { uid: String, mode: String },
+    #[serde(rename = "identity.linked")]
+    IdentityLinked {
+        uid: String,
+        channel: String,
+        channel_user_id: String,
+    },
+    #[serde(rename = "identity.unlinked")]
+    IdentityUnlinked {
+        uid: String,
+        channel: String,
+        channel_user_id: String,
+    },
+    #[serde(rename = "scope.created")]
+    ScopeCreated {
+        scope_id: String,
+        scope_type: String,
+    },
+    #[serde(rename = "scope.member_added")]
+    ScopeMemberAdded {
+        scope_id: String,
+        uid: String,
+        role: String,
+    },
+    #[serde(rename = "event.ingested")]
+    EventIngested {
+        event_id: String,
+        uid: String,
+        scope_id: String,
+        event_type: String,
+    },
+    #[serde(rename = "state.set")]
+    StateSet {
+        scope_id: String,
+        uid: String,
+        key: String,
+    },
+    #[serde(rename = "state.deleted")]
+    StateDeleted {
+        scope_id: String,
+        uid: String,
+        key: String,
+    },
+    #[serde(rename = "record.stored")]
+    RecordStored {
+        schema_id: String,
+        version: String,
+        record_id: String,
+        table: String,
+    },
+    #[serde(rename = "record.deleted")]
+    RecordDeleted {
+        schema_id: String,
+        version: String,
+        record_id: String,
+        table: String,
+    },
+    #[serde(rename = "schema.registered")]
+    SchemaRegistered {
+        schema_id: String,
+        version: String,
+        table: String,
+    },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UserCreated { .. } => "user.created",
+            DomainEvent::UserUpdated { .. } => "user.updated",
+            DomainEvent::UsersMerged { .. } => "user.merged",
+            DomainEvent::UserDeleted { .. } => "user.deleted",
+            DomainEvent::IdentityLinked { .. } => "identity.linked",
+            DomainEvent::IdentityUnlinked { .. } => "identity.unlinked",
+            DomainEvent::ScopeCreated { .. } => "scope.created",
+            DomainEvent::ScopeMemberAdded { .. } => "scope.member_added",
+            DomainEvent::EventIngested { .. } => "event.ingested",
+            DomainEvent::StateSet { .. } => "state.set",
+            DomainEvent::StateDeleted { .. } => "state.deleted",
+            DomainEvent::RecordStored { .. } => "record.stored",
+            DomainEvent::RecordDeleted { .. } => "record.deleted",
+            DomainEvent::SchemaRegistered { .. } => "schema.registered",
+        }
+    }
+
+    /// Outbox stream the event is published on: the part of the name before the dot.
+    pub fn stream(&self) -> &'static str {
+        let name = self.name();
+        name.split_once('.').map_or(name, |(stream, _)| stream)
+    }
 }
 
+/// Receives domain events from services. `conn` is the connection or transaction the
+/// mutation ran on, so observers that write to the database commit or roll back with it.
 pub trait EventObserver {
-    fn on_event(&self, _event: &DomainEvent) -> Result<(), String> {
+    fn on_event(&self, _conn: &Connection, _event: &DomainEvent) -> Result<(), AppError> {
         Ok(())
     }
 }
 
+/// Discards events; for service tests that do not assert on the outbox.
+#[cfg(test)]
 pub struct NoopObserver;
 
-impl EventObserver for NoopObserver {
-    fn on_event(&self, event: &DomainEvent) -> Result<(), String> {
-        match event {
-            DomainEvent::UserCreated { uid } => {
-                let _ = uid;
-            }
-            DomainEvent::IdentityLinked { uid, channel } => {
-                let _ = (uid, channel);
-            }
-            DomainEvent::ScopeMemberAdded { scope_id, uid } => {
-                let _ = (scope_id, uid);
-            }
-        }
-        Ok(())
-    }
-}
+#[cfg(test)]
+impl EventObserver for NoopObserver {}
@@ -0,0 +1,62 @@
+//! Calendar helpers over the unix-seconds timestamps stored in `event_ts` (UTC).
+
+use crate::error::AppError;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// Parses `YYYY-MM` into the `[start, end)` unix-seconds range of that month.
+pub fn month_bounds(month: &str) -> Result<(i64, i64), AppError> {
+    let invalid = || AppError::validation(format!("invalid month (expected YYYY-MM): {month}"));
+    let (y, m) = month.split_once('-').ok_or_else(invalid)?;
+    if y.len() != 4 || m.len() != 2 {
+        return Err(invalid());
+    }
+    let y: i64 = y.parse().map_err(|_| invalid())?;
+    let m: i64 = m.parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&m) {
+        return Err(invalid());
+    }
+    let (ny, nm) = if m == 12 { (y + 1, 1) } else { (y, m + 1) };
+    Ok((
+        days_from_civil(y, m, 1) * SECS_PER_DAY,
+        days_from_civil(ny, nm, 1) * SECS_PER_DAY,
+    ))
+}
+
+/// `YYYY-MM` of a unix-seconds timestamp.
+pub fn month_of(ts: i64) -> String {
+    let (y, m, _) = civil_from_days(ts.div_euclid(SECS_PER_DAY));
+    format!("{y:04}-{m:02}")
+}
+
+/// Monday (`YYYY-MM-DD`, UTC) of the week containing a unix-seconds timestamp.
+pub fn week_of(ts: i64) -> String {
+    let days = ts.div_euclid(SECS_PER_DAY);
+    // 1970-01-01 was a Thursday, three days after Monday.
+    let monday = days - (days + 3).rem_euclid(7);
+    let (y, m, d) = civil_from_days(monday);
+    format!("{y:04}-{m:02}-{d:02}")
+}
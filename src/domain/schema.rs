@@ -1,4 +1,6 @@
+use crate::error::AppError;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashSet;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -8,6 +10,15 @@ pub enum SchemaClass {
     UserContext,
 }
 
+impl SchemaClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaClass::Domain => "domain",
+            SchemaClass::UserContext => "user_context",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FieldDef {
     pub name: String,
@@ -27,36 +38,285 @@ pub struct SchemaDef {
     pub fields: Vec<FieldDef>,
 }
 
-pub fn validate_schema_def(def: &SchemaDef) -> Result<(), String> {
+pub fn validate_schema_def(def: &SchemaDef) -> Result<(), AppError> {
     if def.schema_id.trim().is_empty() {
-        return Err("schema validation failed: schema_id is required".to_string());
+        return Err(AppError::validation(
+            "schema validation failed: schema_id is required",
+        ));
     }
     if def.version.trim().is_empty() {
-        return Err("schema validation failed: version is required".to_string());
+        return Err(AppError::validation(
+            "schema validation failed: version is required",
+        ));
     }
     if def.fields.is_empty() {
-        return Err("schema validation failed: fields[] is required".to_string());
+        return Err(AppError::validation(
+            "schema validation failed: fields[] is required",
+        ));
     }
 
     let mut seen = HashSet::new();
     for f in &def.fields {
         if f.name.trim().is_empty() {
-            return Err("schema validation failed: each field requires non-empty name".to_string());
+            return Err(AppError::validation(
+                "schema validation failed: each field requires non-empty name",
+            ));
         }
         if !seen.insert(f.name.clone()) {
-            return Err(format!(
+            return Err(AppError::validation(format!(
                 "schema validation failed: duplicate field name='{}' in schema_id={}",
                 f.name, def.schema_id
-            ));
+            )));
         }
     }
 
     if def.class == SchemaClass::UserContext && !seen.contains("refUserId") {
-        return Err(format!(
+        return Err(AppError::validation(format!(
             "schema validation failed: user_context schema_id={} must include field name=refUserId",
             def.schema_id
-        ));
+        )));
     }
 
     Ok(())
 }
+
+/// Collapses type aliases (`int`/`long`, `float`/`double`/`real`, `bool`) to one name.
+pub fn canonical_type(field_type: &str) -> String {
+    match field_type.to_ascii_lowercase().as_str() {
+        "int" | "integer" | "long" => "integer".to_string(),
+        "float" | "double" | "number" | "real" => "number".to_string(),
+        "bool" | "boolean" => "boolean".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn field_type_matches(field_type: &str, value: &serde_json::Value) -> bool {
+    match canonical_type(field_type).as_str() {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "json" => true,
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        _ => value.is_string(),
+    }
+}
+
+/// One field-level validation failure.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "field='{}': {}", self.field, self.reason)
+    }
+}
+
+fn field_error(field: &str, reason: impl Into<String>) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Checks a payload against `def` and returns one value per declared field, with `default`
+/// filled in for missing fields and `null` for missing nullable fields. With `strict`,
+/// undeclared keys are errors; otherwise they are passed through unchanged. Every failing
+/// field is reported, not just the first.
+pub fn validate_payload(
+    def: &SchemaDef,
+    payload: &serde_json::Value,
+    strict: bool,
+) -> Result<serde_json::Map<String, serde_json::Value>, Vec<FieldError>> {
+    let Some(obj) = payload.as_object() else {
+        return Err(vec![field_error("$", "payload must be a JSON object")]);
+    };
+
+    let mut errors = Vec::new();
+    let mut out = serde_json::Map::new();
+    for (k, v) in obj {
+        if !def.fields.iter().any(|f| &f.name == k) {
+            if strict {
+                errors.push(field_error(k, "unknown field"));
+            } else {
+                out.insert(k.clone(), v.clone());
+            }
+        }
+    }
+
+    for f in &def.fields {
+        let value = match obj.get(&f.name) {
+            Some(v) if !v.is_null() => v.clone(),
+            _ => match (&f.default, f.nullable) {
+                (Some(d), _) => d.clone(),
+                (None, true) => serde_json::Value::Null,
+                (None, false) => {
+                    errors.push(field_error(&f.name, "missing required field"));
+                    continue;
+                }
+            },
+        };
+        if !value.is_null() && !field_type_matches(&f.field_type, &value) {
+            errors.push(field_error(
+                &f.name,
+                format!("expects type={}", f.field_type),
+            ));
+            continue;
+        }
+        out.insert(f.name.clone(), value);
+    }
+
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn format_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Strict validation for records written to a schema's physical table.
+pub fn validate_record(
+    def: &SchemaDef,
+    payload: &serde_json::Value,
+) -> Result<serde_json::Map<String, serde_json::Value>, AppError> {
+    validate_payload(def, payload, true).map_err(|errors| {
+        AppError::validation(format!(
+            "record validation failed for schema_id={}: {}",
+            def.schema_id,
+            format_field_errors(&errors)
+        ))
+        .with_details(json!({ "field_errors": errors }))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SchemaChange {
+    ClassChanged {
+        from: SchemaClass,
+        to: SchemaClass,
+    },
+    FieldAdded {
+        field: String,
+        nullable: bool,
+        has_default: bool,
+    },
+    FieldRemoved {
+        field: String,
+    },
+    FieldRetyped {
+        field: String,
+        from: String,
+        to: String,
+    },
+    NullabilityChanged {
+        field: String,
+        nullable: bool,
+    },
+}
+
+impl SchemaChange {
+    /// Additive changes readers of the old version can ignore are compatible; anything that
+    /// drops, retypes or newly requires data is breaking.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            SchemaChange::ClassChanged { .. } => true,
+            SchemaChange::FieldAdded {
+                nullable,
+                has_default,
+                ..
+            } => !nullable && !has_default,
+            SchemaChange::FieldRemoved { .. } => true,
+            SchemaChange::FieldRetyped { .. } => true,
+            SchemaChange::NullabilityChanged { nullable, .. } => !nullable,
+        }
+    }
+}
+
+impl std::fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaChange::ClassChanged { from, to } => {
+                write!(
+                    f,
+                    "change=class_changed from={} to={}",
+                    from.as_str(),
+                    to.as_str()
+                )
+            }
+            SchemaChange::FieldAdded {
+                field,
+                nullable,
+                has_default,
+            } => write!(
+                f,
+                "change=field_added field={field} nullable={nullable} has_default={has_default}"
+            ),
+            SchemaChange::FieldRemoved { field } => {
+                write!(f, "change=field_removed field={field}")
+            }
+            SchemaChange::FieldRetyped { field, from, to } => {
+                write!(f, "change=field_retyped field={field} from={from} to={to}")
+            }
+            SchemaChange::NullabilityChanged { field, nullable } => {
+                write!(
+                    f,
+                    "change=nullability_changed field={field} nullable={nullable}"
+                )
+            }
+        }
+    }
+}
+
+/// Field-level changes going from `old` to `new`, in `new`'s field order followed by removals.
+pub fn diff_schemas(old: &SchemaDef, new: &SchemaDef) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+    if old.class != new.class {
+        changes.push(SchemaChange::ClassChanged {
+            from: old.class.clone(),
+            to: new.class.clone(),
+        });
+    }
+    for nf in &new.fields {
+        match old.fields.iter().find(|of| of.name == nf.name) {
+            None => changes.push(SchemaChange::FieldAdded {
+                field: nf.name.clone(),
+                nullable: nf.nullable,
+                has_default: nf.default.is_some(),
+            }),
+            Some(of) => {
+                if canonical_type(&of.field_type) != canonical_type(&nf.field_type) {
+                    changes.push(SchemaChange::FieldRetyped {
+                        field: nf.name.clone(),
+                        from: of.field_type.clone(),
+                        to: nf.field_type.clone(),
+                    });
+                }
+                if of.nullable != nf.nullable {
+                    changes.push(SchemaChange::NullabilityChanged {
+                        field: nf.name.clone(),
+                        nullable: nf.nullable,
+                    });
+                }
+            }
+        }
+    }
+    for of in &old.fields {
+        if !new.fields.iter().any(|nf| nf.name == of.name) {
+            changes.push(SchemaChange::FieldRemoved {
+                field: of.name.clone(),
+            });
+        }
+    }
+    changes
+}
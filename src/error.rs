@@ -0,0 +1,154 @@
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Error categories with stable codes and process exit codes; see `specs/CLI_SPEC.md`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// SQLite or other internal failure.
+    Storage,
+    /// Malformed input: bad flags, payloads, schemas or files.
+    Validation,
+    NotFound,
+    /// The write collides with existing data, including SQLite constraint failures.
+    Conflict,
+    Unauthorized,
+    /// The database has no schema yet or is behind this binary.
+    SchemaNotInitialized,
+    /// The database was written by a newer binary or its migration history was altered.
+    SchemaMismatch,
+    /// A pre-commit hook rejected the mutation.
+    HookVetoed,
+    /// Reading or writing a file outside the database failed.
+    Io,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Storage => "storage",
+            ErrorKind::Validation => "validation",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::SchemaNotInitialized => "schema_not_initialized",
+            ErrorKind::SchemaMismatch => "schema_mismatch",
+            ErrorKind::HookVetoed => "hook_vetoed",
+            ErrorKind::Io => "io",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Storage => 1,
+            ErrorKind::Validation => 2,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Conflict => 4,
+            ErrorKind::Unauthorized => 5,
+            ErrorKind::SchemaNotInitialized => 6,
+            ErrorKind::SchemaMismatch => 7,
+            ErrorKind::HookVetoed => 8,
+            ErrorKind::Io => 9,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppError {
+    kind: ErrorKind,
+    message: String,
+    details: Option<Value>,
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        AppError {
+            kind,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Storage, message)
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Validation, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Conflict, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unauthorized, message)
+    }
+
+    pub fn schema_not_initialized(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::SchemaNotInitialized, message)
+    }
+
+    pub fn schema_mismatch(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::SchemaMismatch, message)
+    }
+
+    pub fn hook_vetoed(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::HookVetoed, message)
+    }
+
+    /// `<context>: <e>` for a failed file operation.
+    pub fn io(e: std::io::Error, context: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Io, format!("{context}: {e}"))
+    }
+
+    /// `<context>: <e>` for a failed SQLite call; constraint violations become `Conflict`.
+    pub fn db(e: rusqlite::Error, context: impl fmt::Display) -> Self {
+        let kind = match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => ErrorKind::Conflict,
+            _ => ErrorKind::Storage,
+        };
+        Self::new(kind, format!("{context}: {e}"))
+    }
+
+    /// Prepends `prefix` to the message, keeping kind and details.
+    pub fn prefixed(mut self, prefix: impl fmt::Display) -> Self {
+        self.message = format!("{prefix}{}", self.message);
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// `{"error":{"code":..,"message":..,"details":..}}`; `details` is null when absent.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": {
+                "code": self.kind.code(),
+                "message": self.message,
+                "details": self.details,
+            }
+        })
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AppError {}
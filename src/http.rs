@@ -0,0 +1,638 @@
+//! Local HTTP API: REST routes over the same methods as [`crate::rpc`], plus an OpenAPI
+//! document generated from the route table. Reads run on per-worker connections; every
+//! mutation is handed to one writer thread that owns the only writing connection.
+
+use crate::error::{AppError, ErrorKind};
+use crate::rpc::{self, RpcError};
+use crate::store::MemoryStore;
+use serde_json::{json, Map, Value};
+use std::sync::mpsc;
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+/// Worker threads answering requests, each with its own read connection.
+const READ_WORKERS: usize = 4;
+
+#[derive(Clone, Copy)]
+enum ParamType {
+    String,
+    Integer,
+    Boolean,
+    /// Comma-separated in query strings.
+    StringList,
+    /// Any JSON value.
+    Json,
+}
+
+impl ParamType {
+    fn schema(self) -> Value {
+        match self {
+            ParamType::String => json!({"type": "string"}),
+            ParamType::Integer => json!({"type": "integer"}),
+            ParamType::Boolean => json!({"type": "boolean"}),
+            ParamType::StringList => json!({"type": "array", "items": {"type": "string"}}),
+            ParamType::Json => json!({}),
+        }
+    }
+
+    fn parse_query(self, name: &str, raw: String) -> Result<Value, AppError> {
+        let invalid = || AppError::validation(format!("invalid query parameter {name}: {raw}"));
+        match self {
+            ParamType::String => Ok(Value::String(raw)),
+            ParamType::Integer => raw.parse::<u64>().map(Value::from).map_err(|_| invalid()),
+            ParamType::Boolean => match raw.as_str() {
+                "true" | "1" => Ok(Value::Bool(true)),
+                "false" | "0" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+            ParamType::StringList => Ok(json!(raw.split(',').collect::<Vec<_>>())),
+            ParamType::Json => serde_json::from_str(&raw).map_err(|_| invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Param {
+    name: &'static str,
+    ty: ParamType,
+    required: bool,
+}
+
+fn req(name: &'static str, ty: ParamType) -> Param {
+    Param {
+        name,
+        ty,
+        required: true,
+    }
+}
+
+fn opt(name: &'static str, ty: ParamType) -> Param {
+    Param {
+        name,
+        ty,
+        required: false,
+    }
+}
+
+/// `uid` or `channel` + `channel_user_id`.
+fn user_params() -> Vec<Param> {
+    vec![
+        opt("uid", ParamType::String),
+        opt("channel", ParamType::String),
+        opt("channel_user_id", ParamType::String),
+    ]
+}
+
+/// One of `scope_id`, `scopes` or `read_order=auto`.
+fn read_scope_params() -> Vec<Param> {
+    vec![
+        opt("scope_id", ParamType::String),
+        opt("scopes", ParamType::StringList),
+        opt("read_order", ParamType::String),
+    ]
+}
+
+/// Maps `method path` to an RPC method; `{name}` path segments become string params.
+struct Route {
+    method: &'static str,
+    path: &'static str,
+    op: &'static str,
+    summary: &'static str,
+    query: Vec<Param>,
+    body: Vec<Param>,
+}
+
+impl Route {
+    fn new(
+        method: &'static str,
+        path: &'static str,
+        op: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        Route {
+            method,
+            path,
+            op,
+            summary,
+            query: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn query(mut self, params: Vec<Param>) -> Self {
+        self.query = params;
+        self
+    }
+
+    fn body(mut self, params: Vec<Param>) -> Self {
+        self.body = params;
+        self
+    }
+
+    /// Everything but `GET` goes through the writer connection.
+    fn is_write(&self) -> bool {
+        self.method != "GET"
+    }
+
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+    }
+
+    /// Path params by name when `segments` fit this route's path.
+    fn match_path(&self, segments: &[&str]) -> Option<Vec<(&'static str, String)>> {
+        let pattern: Vec<&'static str> = self.path.split('/').skip(1).collect();
+        if pattern.len() != segments.len() {
+            return None;
+        }
+        let mut params = Vec::new();
+        for (p, s) in pattern.into_iter().zip(segments) {
+            match p.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                Some(name) => params.push((name, (*s).to_string())),
+                None if p == *s => {}
+                None => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+fn routes() -> Vec<Route> {
+    use ParamType::*;
+    vec![
+        Route::new("GET", "/users", "user.list", "List users"),
+        Route::new("POST", "/users", "user.create", "Create a user")
+            .body(vec![req("name", String)]),
+        Route::new(
+            "POST",
+            "/users/merge",
+            "user.merge",
+            "Merge one user into another",
+        )
+        .body(vec![req("from_uid", String), req("to_uid", String)]),
+        Route::new("GET", "/users/{uid}", "user.show", "Show a user"),
+        Route::new("PATCH", "/users/{uid}", "user.update", "Rename a user")
+            .body(vec![req("name", String)]),
+        Route::new("DELETE", "/users/{uid}", "user.delete", "Delete a user").query(vec![
+            opt("mode", String),
+            opt("force", Boolean),
+            opt("dry_run", Boolean),
+        ]),
+        Route::new(
+            "GET",
+            "/users/{uid}/export",
+            "user.export",
+            "Export a user's data",
+        ),
+        Route::new(
+            "POST",
+            "/identities",
+            "identity.link",
+            "Link a channel identity",
+        )
+        .body(vec![
+            req("uid", String),
+            req("channel", String),
+            req("channel_user_id", String),
+        ]),
+        Route::new(
+            "GET",
+            "/identities/{channel}/{channel_user_id}",
+            "identity.resolve",
+            "Resolve a channel identity",
+        ),
+        Route::new(
+            "DELETE",
+            "/identities/{channel}/{channel_user_id}",
+            "identity.unlink",
+            "Unlink a channel identity",
+        ),
+        Route::new("GET", "/scopes", "scope.list", "List scopes"),
+        Route::new("POST", "/scopes", "scope.create", "Create a scope")
+            .body(vec![req("scope_id", String), req("scope_type", String)]),
+        Route::new(
+            "GET",
+            "/scopes/{scope_id}/members",
+            "scope.members",
+            "List scope members",
+        ),
+        Route::new(
+            "POST",
+            "/scopes/{scope_id}/members",
+            "scope.add_member",
+            "Add a scope member",
+        )
+        .body(vec![req("uid", String), opt("role", String)]),
+        Route::new("POST", "/events", "ingest.event", "Ingest one event").body(
+            [
+                user_params(),
+                vec![
+                    req("scope_id", String),
+                    req("event_type", String),
+                    req("payload", Json),
+                    opt("idempotency_key", String),
+                ],
+            ]
+            .concat(),
+        ),
+        Route::new("GET", "/query/latest", "query.latest", "Latest event")
+            .query([user_params(), read_scope_params()].concat()),
+        Route::new(
+            "GET",
+            "/query/metric",
+            "query.metric",
+            "Metrics by key or prefix",
+        )
+        .query(
+            [
+                user_params(),
+                read_scope_params(),
+                vec![opt("key", String), opt("prefix", String)],
+            ]
+            .concat(),
+        ),
+        Route::new("GET", "/query/topk", "query.topk", "Top items for a topic").query(
+            [
+                user_params(),
+                read_scope_params(),
+                vec![req("topic", String), opt("limit", Integer)],
+            ]
+            .concat(),
+        ),
+        Route::new("GET", "/query/summary", "query.summary", "Period summaries").query(
+            [
+                user_params(),
+                vec![
+                    req("scope_id", String),
+                    req("period", String),
+                    opt("event_type", String),
+                    opt("topic", String),
+                    opt("limit", Integer),
+                ],
+            ]
+            .concat(),
+        ),
+        Route::new(
+            "GET",
+            "/state/{scope_id}/{key}",
+            "state.get",
+            "Read a state entry",
+        )
+        .query(user_params()),
+        Route::new(
+            "PUT",
+            "/state/{scope_id}/{key}",
+            "state.set",
+            "Write a state entry",
+        )
+        .body([user_params(), vec![req("value", Json)]].concat()),
+        Route::new(
+            "DELETE",
+            "/state/{scope_id}/{key}",
+            "state.delete",
+            "Delete a state entry",
+        )
+        .query(user_params()),
+    ]
+}
+
+/// OpenAPI 3.0 description of every route, served at `GET /openapi.json`.
+pub fn openapi() -> Value {
+    let mut paths = Map::new();
+    paths.insert(
+        "/openapi.json".to_string(),
+        json!({"get": {
+            "operationId": "openapi",
+            "summary": "This document",
+            "responses": {"200": {"description": "OpenAPI document"}},
+        }}),
+    );
+    for route in routes() {
+        let mut parameters: Vec<Value> = route
+            .path_params()
+            .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+            .collect();
+        parameters.extend(route.query.iter().map(|p| {
+            let mut param = json!({
+                "name": p.name,
+                "in": "query",
+                "required": p.required,
+                "schema": p.ty.schema(),
+            });
+            if matches!(p.ty, ParamType::StringList) {
+                param["explode"] = json!(false);
+            }
+            param
+        }));
+        let mut operation = json!({
+            "operationId": route.op,
+            "summary": route.summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "The result document of the matching JSON-RPC method",
+                    "content": {"application/json": {"schema": {}}},
+                },
+                "default": {
+                    "description": "Error envelope",
+                    "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}},
+                },
+            },
+        });
+        if !route.body.is_empty() {
+            let properties: Map<String, Value> = route
+                .body
+                .iter()
+                .map(|p| (p.name.to_string(), p.ty.schema()))
+                .collect();
+            let required: Vec<&str> = route
+                .body
+                .iter()
+                .filter(|p| p.required)
+                .map(|p| p.name)
+                .collect();
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": {"application/json": {"schema": {
+                    "type": "object",
+                    "required": required,
+                    "properties": properties,
+                }}},
+            });
+        }
+        let item = paths
+            .entry(route.path.to_string())
+            .or_insert_with(|| json!({}));
+        item[route.method.to_lowercase()] = operation;
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {"title": "agent-memory-cli", "version": env!("CARGO_PKG_VERSION")},
+        "paths": paths,
+        "components": {"schemas": {"Error": {
+            "type": "object",
+            "properties": {"error": {
+                "type": "object",
+                "properties": {
+                    "code": {"type": "string"},
+                    "message": {"type": "string"},
+                    "details": {},
+                },
+            }},
+        }}},
+    })
+}
+
+fn status_for(kind: ErrorKind) -> u16 {
+    match kind {
+        ErrorKind::Validation => 400,
+        ErrorKind::Unauthorized => 403,
+        ErrorKind::NotFound => 404,
+        ErrorKind::Conflict => 409,
+        ErrorKind::HookVetoed => 422,
+        ErrorKind::SchemaNotInitialized | ErrorKind::SchemaMismatch => 503,
+        ErrorKind::Storage | ErrorKind::Io => 500,
+    }
+}
+
+fn percent_decode(s: &str, plus_as_space: bool) -> Result<String, AppError> {
+    let invalid = || AppError::validation(format!("invalid percent-encoding: {s}"));
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = s
+                    .get(i + 1..i + 3)
+                    .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(invalid)?;
+                out.push(byte);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| invalid())
+}
+
+/// A routed request: the RPC method, its params and whether it mutates.
+struct Call {
+    op: &'static str,
+    params: Value,
+    write: bool,
+}
+
+fn route_request(routes: &[Route], request: &mut Request) -> Result<Call, (u16, AppError)> {
+    let method = request.method().as_str().to_string();
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments = path
+        .trim_end_matches('/')
+        .split('/')
+        .skip(1)
+        .map(|s| percent_decode(s, false))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| (400, e))?;
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let mut path_matched = false;
+    let mut found = None;
+    for route in routes {
+        if let Some(path_params) = route.match_path(&segments) {
+            path_matched = true;
+            if route.method == method {
+                found = Some((route, path_params));
+                break;
+            }
+        }
+    }
+    let (route, path_params) = match found {
+        Some(found) => found,
+        None if path_matched => {
+            return Err((
+                405,
+                AppError::validation(format!("method not allowed: {method} {path}")),
+            ))
+        }
+        None => return Err((404, AppError::not_found(format!("no route: {path}")))),
+    };
+
+    let mut params = Map::new();
+    if !route.body.is_empty() {
+        let mut body = String::new();
+        request
+            .as_reader()
+            .read_to_string(&mut body)
+            .map_err(|e| (400, AppError::io(e, "failed to read request body")))?;
+        if !body.trim().is_empty() {
+            match serde_json::from_str(&body) {
+                Ok(Value::Object(object)) => params = object,
+                Ok(_) => {
+                    return Err((
+                        400,
+                        AppError::validation("request body must be a JSON object"),
+                    ))
+                }
+                Err(e) => {
+                    return Err((
+                        400,
+                        AppError::validation(format!("invalid request body: {e}")),
+                    ))
+                }
+            }
+        }
+    }
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (name, raw) = pair.split_once('=').unwrap_or((pair, ""));
+        let name = percent_decode(name, true).map_err(|e| (400, e))?;
+        let param = route.query.iter().find(|p| p.name == name).ok_or_else(|| {
+            (
+                400,
+                AppError::validation(format!("unknown query parameter: {name}")),
+            )
+        })?;
+        let raw = percent_decode(raw, true).map_err(|e| (400, e))?;
+        let value = param
+            .ty
+            .parse_query(param.name, raw)
+            .map_err(|e| (400, e))?;
+        params.insert(name, value);
+    }
+    for (name, value) in path_params {
+        params.insert(name.to_string(), Value::String(value));
+    }
+    Ok(Call {
+        op: route.op,
+        params: Value::Object(params),
+        write: route.is_write(),
+    })
+}
+
+type WriteJob = (&'static str, Value, mpsc::Sender<Result<Value, AppError>>);
+
+fn respond(request: Request, status: u16, body: &Value) {
+    let header =
+        Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    // The client may already be gone; there is nobody left to tell.
+    let _ = request.respond(response);
+}
+
+fn handle(
+    routes: &[Route],
+    reader: &mut MemoryStore,
+    writes: &mpsc::Sender<WriteJob>,
+    mut request: Request,
+) {
+    if request.method().as_str() == "GET" && request.url() == "/openapi.json" {
+        respond(request, 200, &openapi());
+        return;
+    }
+    let result = route_request(routes, &mut request).and_then(|call| {
+        let result = if call.write {
+            let (reply, answer) = mpsc::channel();
+            writes
+                .send((call.op, call.params, reply))
+                .map_err(|_| AppError::storage("writer connection is gone"))
+                .and_then(|()| {
+                    answer
+                        .recv()
+                        .unwrap_or_else(|_| Err(AppError::storage("writer connection is gone")))
+                })
+        } else {
+            rpc::call(reader, call.op, call.params).map_err(RpcError::into_app_error)
+        };
+        result.map_err(|e| (status_for(e.kind()), e))
+    });
+    match result {
+        Ok(doc) => respond(request, 200, &doc),
+        Err((status, e)) => respond(request, status, &e.to_json()),
+    }
+}
+
+/// Answers requests on `server` until it stops accepting; `writer` is the only connection
+/// that mutates, and `on_warning` receives its post-hook failures.
+pub fn serve(
+    server: &Server,
+    db_path: &str,
+    mut writer: MemoryStore,
+    on_warning: &(dyn Fn(String) + Sync),
+) -> Result<(), AppError> {
+    let readers = (0..READ_WORKERS)
+        .map(|_| MemoryStore::open(db_path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let routes = routes();
+    let (writes, jobs) = mpsc::channel::<WriteJob>();
+    thread::scope(|s| {
+        s.spawn(move || {
+            for (op, params, reply) in jobs {
+                let result = rpc::call(&mut writer, op, params).map_err(RpcError::into_app_error);
+                for failure in writer.take_hook_warnings() {
+                    on_warning(failure);
+                }
+                let _ = reply.send(result);
+            }
+        });
+        for mut reader in readers {
+            let writes = writes.clone();
+            let routes = &routes;
+            s.spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(routes, &mut reader, &writes, request);
+                }
+            });
+        }
+        drop(writes);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_lists_every_route_with_its_path_params() {
+        let doc = openapi();
+        for route in routes() {
+            let op = &doc["paths"][route.path][route.method.to_lowercase()];
+            assert_eq!(
+                op["operationId"], route.op,
+                "{} {}",
+                route.method, route.path
+            );
+            let path_params: Vec<&str> = op["parameters"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|p| p["in"] == "path")
+                .map(|p| p["name"].as_str().unwrap())
+                .collect();
+            assert_eq!(path_params, route.path_params().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn percent_decode_handles_escapes_and_rejects_bad_ones() {
+        assert_eq!(
+            percent_decode("private%3Au_1", false).unwrap(),
+            "private:u_1"
+        );
+        assert_eq!(percent_decode("a+b", true).unwrap(), "a b");
+        assert_eq!(percent_decode("a+b", false).unwrap(), "a+b");
+        assert!(percent_decode("%zz", false).is_err());
+        assert!(percent_decode("%+f", false).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+//! Local-first memory engine for personal agents: canonical users, channel identities,
+//! scopes, events and the projections derived from them, in one SQLite file.
+//!
+//! [`MemoryStore`] is the Rust API; the other modules are public so the bundled CLI can
+//! reach the services it does not wrap.
+
+pub mod db;
+pub mod domain;
+pub mod error;
+pub mod http;
+pub mod repository;
+pub mod rpc;
+pub mod service;
+pub mod store;
+
+pub use error::{AppError, ErrorKind};
+pub use service::identity_service::UserRef;
+pub use service::query_service::ScopeSelection;
+pub use store::MemoryStore;
@@ -1,9 +1,7 @@
 mod commands;
-mod db;
-mod domain;
-mod repository;
-mod service;
 
+use agent_memory_cli::{AppError, ScopeSelection, UserRef};
+use clap::error::ErrorKind as ClapErrorKind;
 use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
@@ -14,10 +12,18 @@ struct Cli {
     #[arg(long, global = true, default_value = "data/agent-memory.db")]
     db: String,
 
-    /// Emit JSON output for supported commands
+    /// Emit one JSON document per command
     #[arg(long, global = true, default_value_t = false)]
     json: bool,
 
+    /// Emit JSON, with list results as one object per line
+    #[arg(long, global = true, default_value_t = false)]
+    ndjson: bool,
+
+    /// JSON file of shell hooks to run on domain events
+    #[arg(long, global = true)]
+    hooks: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -46,6 +52,16 @@ enum Commands {
         #[command(subcommand)]
         command: SchemaCommands,
     },
+    /// Write and read records in dynamic schema tables
+    Record {
+        #[command(subcommand)]
+        command: RecordCommands,
+    },
+    /// Register declarative materializer rules applied at ingest
+    Materializer {
+        #[command(subcommand)]
+        command: MaterializerCommands,
+    },
     /// Ingest time-series events
     Ingest {
         #[command(subcommand)]
@@ -61,11 +77,32 @@ enum Commands {
         #[command(subcommand)]
         command: StateCommands,
     },
+    /// Inspect and deliver the projection outbox to downstream sinks
+    Outbox {
+        #[command(subcommand)]
+        command: OutboxCommands,
+    },
     /// Operational and maintenance commands
     Admin {
         #[command(subcommand)]
         command: AdminCommands,
     },
+    /// Serve JSON-RPC over stdio or a local HTTP API
+    Serve(ServeArgs),
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct ServeArgs {
+    /// JSON-RPC 2.0, one request per line on stdin and one response per line on stdout
+    #[arg(long, default_value_t = false)]
+    stdio: bool,
+    /// HTTP API on a loopback TCP address such as 127.0.0.1:8080 (port 0 picks a free one)
+    #[arg(long, value_name = "ADDR")]
+    http: Option<String>,
+    /// HTTP API on a Unix domain socket
+    #[arg(long, value_name = "PATH")]
+    unix_socket: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -76,6 +113,8 @@ enum UserCommands {
     Update(UserUpdateArgs),
     Merge(UserMergeArgs),
     Delete(UserDeleteArgs),
+    /// Export every row referencing a user as one JSON document
+    Export(UserExportArgs),
 }
 
 #[derive(Args, Debug)]
@@ -118,6 +157,15 @@ struct UserDeleteArgs {
     dry_run: bool,
 }
 
+#[derive(Args, Debug)]
+struct UserExportArgs {
+    #[arg(long)]
+    uid: String,
+    /// Write the document to this file instead of stdout
+    #[arg(long)]
+    out: Option<String>,
+}
+
 #[derive(Subcommand, Debug)]
 enum IdentityCommands {
     Link(IdentityLinkArgs),
@@ -177,9 +225,57 @@ struct ScopeMembersArgs {
 
 #[derive(Subcommand, Debug)]
 enum SchemaCommands {
-    Register(SchemaFileArgs),
+    Register(SchemaRegisterArgs),
     List,
     Validate(SchemaFileArgs),
+    /// Validate payloads of an event type against a registered schema at ingest
+    Bind(SchemaBindArgs),
+    Unbind(SchemaUnbindArgs),
+    /// List event type -> schema bindings
+    Bindings,
+    /// Show field changes between two registered versions
+    Diff(SchemaDiffArgs),
+}
+
+#[derive(Args, Debug)]
+struct SchemaRegisterArgs {
+    #[arg(long = "file")]
+    file: String,
+    /// Register even if the new version breaks compatibility with the previous one
+    #[arg(long = "allow-breaking", default_value_t = false)]
+    allow_breaking: bool,
+    /// Copy rows from the previous version's table into the new table
+    #[arg(long = "migrate-data", default_value_t = false)]
+    migrate_data: bool,
+}
+
+#[derive(Args, Debug)]
+struct SchemaDiffArgs {
+    #[arg(long = "schema")]
+    schema_id: String,
+    /// Defaults to the version registered before --to
+    #[arg(long)]
+    from: Option<String>,
+    /// Defaults to the latest version
+    #[arg(long)]
+    to: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct SchemaBindArgs {
+    #[arg(long = "event-type")]
+    event_type: String,
+    #[arg(long = "schema")]
+    schema_id: String,
+    /// strict rejects undeclared fields; lenient keeps them
+    #[arg(long, default_value = "strict")]
+    mode: String,
+}
+
+#[derive(Args, Debug)]
+struct SchemaUnbindArgs {
+    #[arg(long = "event-type")]
+    event_type: String,
 }
 
 #[derive(Args, Debug)]
@@ -188,16 +284,140 @@ struct SchemaFileArgs {
     file: String,
 }
 
+#[derive(Subcommand, Debug)]
+enum RecordCommands {
+    Put(RecordPutArgs),
+    Get(RecordIdArgs),
+    List(RecordListArgs),
+    Delete(RecordIdArgs),
+}
+
+#[derive(Args, Debug)]
+struct RecordPutArgs {
+    #[arg(long = "schema")]
+    schema_id: String,
+    /// Schema version; defaults to the latest registered version
+    #[arg(long)]
+    version: Option<String>,
+    /// JSON payload with one key per schema field
+    #[arg(long = "file")]
+    file: String,
+    /// Record id to create or replace; generated when omitted
+    #[arg(long = "id")]
+    record_id: Option<String>,
+    /// Entity key (required for domain schemas)
+    #[arg(long = "key")]
+    entity_key: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RecordIdArgs {
+    #[arg(long = "schema")]
+    schema_id: String,
+    /// Schema version; defaults to the latest registered version
+    #[arg(long)]
+    version: Option<String>,
+    #[arg(long = "id")]
+    record_id: String,
+}
+
+#[derive(Args, Debug)]
+struct RecordListArgs {
+    #[arg(long = "schema")]
+    schema_id: String,
+    /// Schema version; defaults to the latest registered version
+    #[arg(long)]
+    version: Option<String>,
+    #[arg(long = "key")]
+    entity_key: Option<String>,
+    /// Filter user_context records by refUserId
+    #[arg(long)]
+    uid: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum MaterializerCommands {
+    Register(SchemaFileArgs),
+    List,
+    Disable(MaterializerDisableArgs),
+}
+
+#[derive(Args, Debug)]
+struct MaterializerDisableArgs {
+    #[arg(long = "id")]
+    rule_id: String,
+}
+
+/// Selects a user by canonical uid or by a linked channel identity.
+#[derive(Args, Debug)]
+struct UserRefArgs {
+    #[arg(long, required_unless_present = "channel", conflicts_with = "channel")]
+    uid: Option<String>,
+    #[arg(long, requires = "channel_user_id")]
+    channel: Option<String>,
+    #[arg(long = "channel-user-id", requires = "channel")]
+    channel_user_id: Option<String>,
+}
+
+impl UserRefArgs {
+    fn to_ref(&self) -> UserRef {
+        match (&self.uid, &self.channel, &self.channel_user_id) {
+            (Some(uid), _, _) => UserRef::Uid(uid.clone()),
+            (None, Some(channel), Some(channel_user_id)) => UserRef::Channel {
+                channel: channel.clone(),
+                channel_user_id: channel_user_id.clone(),
+            },
+            _ => unreachable!("clap enforces --uid or --channel/--channel-user-id"),
+        }
+    }
+}
+
+/// Selects the scopes a query reads: one `--scope`, an ordered `--scopes` list, or
+/// `--read-order auto` (private, then shared, then global scopes of the user).
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct ReadScopeArgs {
+    #[arg(long = "scope")]
+    scope_id: Option<String>,
+    /// Comma-separated scopes, read in the given order
+    #[arg(long, value_delimiter = ',')]
+    scopes: Vec<String>,
+    #[arg(long = "read-order", value_parser = ["auto"])]
+    read_order: Option<String>,
+}
+
+impl ReadScopeArgs {
+    fn to_selection(&self) -> ScopeSelection {
+        match (&self.scope_id, &self.read_order) {
+            (Some(scope_id), _) => ScopeSelection::Single(scope_id.clone()),
+            (None, Some(_)) => ScopeSelection::Auto,
+            (None, None) => ScopeSelection::List(self.scopes.clone()),
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum IngestCommands {
     Event(IngestEventArgs),
-    Batch,
+    Batch(IngestBatchArgs),
+}
+
+#[derive(Args, Debug)]
+struct IngestBatchArgs {
+    /// NDJSON file with one event per line, or `-` for stdin
+    #[arg(long = "file")]
+    file: String,
+    /// Commit all lines in a single transaction (all-or-nothing)
+    #[arg(long, default_value_t = false)]
+    atomic: bool,
 }
 
 #[derive(Args, Debug)]
 struct IngestEventArgs {
-    #[arg(long)]
-    uid: String,
+    #[command(flatten)]
+    user: UserRefArgs,
     #[arg(long = "scope")]
     scope_id: String,
     #[arg(long = "type")]
@@ -213,22 +433,24 @@ enum QueryCommands {
     Latest(QueryLatestArgs),
     Metric(QueryMetricArgs),
     Topk(QueryTopkArgs),
+    /// Read weekly/monthly summaries built by `admin compact`
+    Summary(QuerySummaryArgs),
 }
 
 #[derive(Args, Debug)]
 struct QueryLatestArgs {
-    #[arg(long)]
-    uid: String,
-    #[arg(long = "scope")]
-    scope_id: String,
+    #[command(flatten)]
+    user: UserRefArgs,
+    #[command(flatten)]
+    scopes: ReadScopeArgs,
 }
 
 #[derive(Args, Debug)]
 struct QueryMetricArgs {
-    #[arg(long)]
-    uid: String,
-    #[arg(long = "scope")]
-    scope_id: String,
+    #[command(flatten)]
+    user: UserRefArgs,
+    #[command(flatten)]
+    scopes: ReadScopeArgs,
     #[arg(long = "key")]
     key: Option<String>,
     #[arg(long = "prefix")]
@@ -237,132 +459,496 @@ struct QueryMetricArgs {
 
 #[derive(Args, Debug)]
 struct QueryTopkArgs {
+    #[command(flatten)]
+    user: UserRefArgs,
+    #[command(flatten)]
+    scopes: ReadScopeArgs,
     #[arg(long)]
-    uid: String,
+    topic: String,
+    #[arg(long, default_value_t = 3)]
+    limit: usize,
+}
+
+#[derive(Args, Debug)]
+struct QuerySummaryArgs {
+    #[command(flatten)]
+    user: UserRefArgs,
     #[arg(long = "scope")]
     scope_id: String,
+    /// week|month
     #[arg(long)]
-    topic: String,
-    #[arg(long, default_value_t = 3)]
+    period: String,
+    #[arg(long = "type")]
+    event_type: Option<String>,
+    #[arg(long)]
+    topic: Option<String>,
+    #[arg(long, default_value_t = 20)]
     limit: usize,
 }
 
+#[derive(Subcommand, Debug)]
+enum OutboxCommands {
+    List(OutboxListArgs),
+    /// Deliver due rows to a sink (at-least-once)
+    Drain(OutboxDrainArgs),
+    /// Move dead-lettered rows back to pending
+    Requeue(OutboxRequeueArgs),
+}
+
+#[derive(Args, Debug)]
+struct OutboxListArgs {
+    /// pending|delivered|dead
+    #[arg(long)]
+    status: Option<String>,
+    #[arg(long)]
+    stream: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+}
+
+#[derive(Args, Debug)]
+struct OutboxDrainArgs {
+    /// file|exec|http
+    #[arg(long)]
+    sink: String,
+    /// File path, shell command or URL, depending on --sink
+    #[arg(long)]
+    target: String,
+    #[arg(long)]
+    stream: Option<String>,
+    #[arg(long, default_value_t = 100)]
+    limit: usize,
+    /// Failed attempts before a row is dead-lettered
+    #[arg(long = "max-attempts", default_value_t = 5)]
+    max_attempts: i64,
+    /// Base retry delay in seconds, doubled per failed attempt
+    #[arg(long = "backoff-secs", default_value_t = 30)]
+    backoff_secs: i64,
+}
+
+#[derive(Args, Debug)]
+struct OutboxRequeueArgs {
+    /// Requeue one row; all dead rows when omitted
+    #[arg(long = "id")]
+    outbox_id: Option<String>,
+}
+
 #[derive(Subcommand, Debug)]
 enum StateCommands {
-    Get,
-    Set,
-    Delete,
+    Get(StateKeyArgs),
+    Set(StateSetArgs),
+    Delete(StateKeyArgs),
+}
+
+#[derive(Args, Debug)]
+struct StateKeyArgs {
+    #[command(flatten)]
+    user: UserRefArgs,
+    #[arg(long = "scope")]
+    scope_id: String,
+    #[arg(long)]
+    key: String,
+}
+
+#[derive(Args, Debug)]
+struct StateSetArgs {
+    #[command(flatten)]
+    user: UserRefArgs,
+    #[arg(long = "scope")]
+    scope_id: String,
+    #[arg(long)]
+    key: String,
+    /// JSON-encoded value
+    #[arg(long)]
+    value: String,
 }
 
 #[derive(Subcommand, Debug)]
 enum AdminCommands {
-    Migrate,
-    Reindex,
+    /// Apply embedded schema migrations
+    Migrate(AdminMigrateArgs),
+    /// Rebuild counter metrics and topk from the events log
+    Reindex(AdminReindexArgs),
+    /// Fold new events into weekly/monthly summary tables
     Compact,
-    Archive,
+    /// Move events through a month into cold gzip NDJSON archives (or restore them)
+    Archive(AdminArchiveArgs),
+    /// Write every table to a portable NDJSON bundle with a manifest
+    Export(AdminExportArgs),
+    /// Restore a bundle written by `admin export`
+    Import(AdminImportArgs),
+}
+
+#[derive(Args, Debug)]
+struct AdminExportArgs {
+    /// Bundle directory (must not already contain a bundle)
+    #[arg(long)]
+    out: String,
+}
+
+#[derive(Args, Debug)]
+struct AdminImportArgs {
+    /// Bundle directory written by `admin export`
+    #[arg(long = "in")]
+    dir: String,
+    /// What to do with rows whose key already exists with different values: skip|overwrite|fail
+    #[arg(long = "on-conflict", default_value = "fail")]
+    on_conflict: String,
+    /// Store rows of an exported uid under another uid (`<old>=<new>`, repeatable)
+    #[arg(long = "map-uid")]
+    map_uid: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct AdminMigrateArgs {
+    /// Show applied and pending migrations without changing the database
+    #[arg(long, default_value_t = false)]
+    status: bool,
+    /// Migrate up to this version instead of the latest
+    #[arg(long)]
+    to: Option<i64>,
+    /// Print the SQL that would run without applying it
+    #[arg(long = "dry-run", default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct AdminReindexArgs {
+    #[arg(long)]
+    uid: Option<String>,
+    #[arg(long = "scope")]
+    scope_id: Option<String>,
+    #[arg(long)]
+    topic: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct AdminArchiveArgs {
+    /// Archive every event up to and including this month (YYYY-MM)
+    #[arg(long)]
+    month: String,
+    /// Archive directory (defaults to `archive/` next to the database)
+    #[arg(long)]
+    dir: Option<String>,
+    /// Restore the archived month back into the events table
+    #[arg(long, default_value_t = false)]
+    restore: bool,
+}
+
+/// Parses the command line. Under `--json`/`--ndjson` usage errors are reported with the
+/// JSON error envelope like any other validation error; help and version output is left to clap.
+fn parse_cli() -> Cli {
+    let err = match Cli::try_parse() {
+        Ok(cli) => return cli,
+        Err(err) => err,
+    };
+    let json = std::env::args_os().any(|arg| arg == "--json" || arg == "--ndjson");
+    if !json
+        || matches!(
+            err.kind(),
+            ClapErrorKind::DisplayHelp | ClapErrorKind::DisplayVersion
+        )
+    {
+        err.exit();
+    }
+    let message = match err.kind() {
+        ClapErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => {
+            "missing subcommand; run with --help for usage".to_string()
+        }
+        // The error section ends at the blank line before clap's usage block.
+        _ => err
+            .to_string()
+            .lines()
+            .take_while(|line| !line.trim().is_empty())
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_start_matches("error: ")
+            .to_string(),
+    };
+    let e = AppError::validation(message);
+    eprintln!("{}", e.to_json());
+    std::process::exit(e.kind().exit_code());
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let cli = parse_cli();
+    let hooks = cli.hooks.as_deref();
+    let output = commands::Output::from_flags(cli.json, cli.ndjson);
 
     let result = match cli.command {
-        Commands::Doctor => commands::doctor(&cli.db, cli.json),
+        Commands::Doctor => commands::doctor(&cli.db, output),
         Commands::User { command } => match command {
-            UserCommands::Create(args) => commands::user_create(&cli.db, &args.name),
-            UserCommands::List => commands::user_list(&cli.db),
-            UserCommands::Show(args) => commands::user_show(&cli.db, &args.uid),
-            UserCommands::Update(args) => commands::user_update(&cli.db, &args.uid, &args.name),
+            UserCommands::Create(args) => commands::user_create(&cli.db, hooks, &args.name, output),
+            UserCommands::List => commands::user_list(&cli.db, output),
+            UserCommands::Show(args) => commands::user_show(&cli.db, &args.uid, output),
+            UserCommands::Update(args) => {
+                commands::user_update(&cli.db, hooks, &args.uid, &args.name, output)
+            }
             UserCommands::Merge(args) => {
-                commands::user_merge(&cli.db, &args.from_uid, &args.to_uid)
+                commands::user_merge(&cli.db, hooks, &args.from_uid, &args.to_uid, output)
             }
-            UserCommands::Delete(args) => {
-                commands::user_delete(&cli.db, &args.uid, &args.mode, args.force, args.dry_run)
+            UserCommands::Delete(args) => commands::user_delete(
+                &cli.db,
+                hooks,
+                &args.uid,
+                &args.mode,
+                args.force,
+                args.dry_run,
+                output,
+            ),
+            UserCommands::Export(args) => {
+                commands::user_export(&cli.db, &args.uid, args.out.as_deref(), output)
             }
         },
         Commands::Identity { command } => match command {
-            IdentityCommands::Link(args) => {
-                commands::identity_link(&cli.db, &args.uid, &args.channel, &args.channel_user_id)
-            }
+            IdentityCommands::Link(args) => commands::identity_link(
+                &cli.db,
+                hooks,
+                &args.uid,
+                &args.channel,
+                &args.channel_user_id,
+                output,
+            ),
             IdentityCommands::Resolve(args) => {
-                commands::identity_resolve(&cli.db, &args.channel, &args.channel_user_id)
-            }
-            IdentityCommands::Unlink(args) => {
-                commands::identity_unlink(&cli.db, &args.channel, &args.channel_user_id)
+                commands::identity_resolve(&cli.db, &args.channel, &args.channel_user_id, output)
             }
+            IdentityCommands::Unlink(args) => commands::identity_unlink(
+                &cli.db,
+                hooks,
+                &args.channel,
+                &args.channel_user_id,
+                output,
+            ),
         },
         Commands::Scope { command } => match command {
             ScopeCommands::Create(args) => {
-                commands::scope_create(&cli.db, &args.scope_id, &args.scope_type)
+                commands::scope_create(&cli.db, hooks, &args.scope_id, &args.scope_type, output)
             }
-            ScopeCommands::AddMember(args) => {
-                commands::scope_add_member(&cli.db, &args.scope_id, &args.uid, &args.role)
+            ScopeCommands::AddMember(args) => commands::scope_add_member(
+                &cli.db,
+                hooks,
+                &args.scope_id,
+                &args.uid,
+                &args.role,
+                output,
+            ),
+            ScopeCommands::List => commands::scope_list(&cli.db, output),
+            ScopeCommands::Members(args) => {
+                commands::scope_members(&cli.db, &args.scope_id, output)
             }
-            ScopeCommands::List => commands::scope_list(&cli.db),
-            ScopeCommands::Members(args) => commands::scope_members(&cli.db, &args.scope_id),
         },
         Commands::Schema { command } => match command {
-            SchemaCommands::Register(args) => commands::schema_register(&cli.db, &args.file),
-            SchemaCommands::List => commands::schema_list(&cli.db),
-            SchemaCommands::Validate(args) => commands::schema_validate(&args.file),
+            SchemaCommands::Register(args) => commands::schema_register(
+                &cli.db,
+                hooks,
+                &args.file,
+                args.allow_breaking,
+                args.migrate_data,
+                output,
+            ),
+            SchemaCommands::List => commands::schema_list(&cli.db, output),
+            SchemaCommands::Validate(args) => commands::schema_validate(&args.file, output),
+            SchemaCommands::Bind(args) => commands::schema_bind(
+                &cli.db,
+                &args.event_type,
+                &args.schema_id,
+                &args.mode,
+                output,
+            ),
+            SchemaCommands::Unbind(args) => {
+                commands::schema_unbind(&cli.db, &args.event_type, output)
+            }
+            SchemaCommands::Bindings => commands::schema_bindings(&cli.db, output),
+            SchemaCommands::Diff(args) => commands::schema_diff(
+                &cli.db,
+                &args.schema_id,
+                args.from.as_deref(),
+                args.to.as_deref(),
+                output,
+            ),
+        },
+        Commands::Record { command } => match command {
+            RecordCommands::Put(args) => commands::record_put(
+                &cli.db,
+                hooks,
+                &args.schema_id,
+                args.version.as_deref(),
+                &args.file,
+                args.record_id.as_deref(),
+                args.entity_key.as_deref(),
+                output,
+            ),
+            RecordCommands::Get(args) => commands::record_get(
+                &cli.db,
+                &args.schema_id,
+                args.version.as_deref(),
+                &args.record_id,
+                output,
+            ),
+            RecordCommands::List(args) => commands::record_list(
+                &cli.db,
+                &args.schema_id,
+                args.version.as_deref(),
+                args.entity_key.as_deref(),
+                args.uid.as_deref(),
+                args.limit,
+                output,
+            ),
+            RecordCommands::Delete(args) => commands::record_delete(
+                &cli.db,
+                hooks,
+                &args.schema_id,
+                args.version.as_deref(),
+                &args.record_id,
+                output,
+            ),
+        },
+        Commands::Materializer { command } => match command {
+            MaterializerCommands::Register(args) => {
+                commands::materializer_register(&cli.db, &args.file, output)
+            }
+            MaterializerCommands::List => commands::materializer_list(&cli.db, output),
+            MaterializerCommands::Disable(args) => {
+                commands::materializer_disable(&cli.db, &args.rule_id, output)
+            }
         },
         Commands::Ingest { command } => match command {
             IngestCommands::Event(args) => commands::ingest_event(
                 &cli.db,
-                &args.uid,
+                hooks,
+                &args.user.to_ref(),
                 &args.scope_id,
                 &args.event_type,
                 &args.file,
                 args.idempotency_key.as_deref(),
+                output,
             ),
-            IngestCommands::Batch => {
-                commands::todo("ingest", "batch");
-                Ok(())
+            IngestCommands::Batch(args) => {
+                commands::ingest_batch(&cli.db, hooks, &args.file, args.atomic, output)
             }
         },
         Commands::Query { command } => match command {
-            QueryCommands::Latest(args) => {
-                commands::query_latest(&cli.db, &args.uid, &args.scope_id, cli.json)
-            }
+            QueryCommands::Latest(args) => commands::query_latest(
+                &cli.db,
+                &args.user.to_ref(),
+                &args.scopes.to_selection(),
+                output,
+            ),
             QueryCommands::Metric(args) => commands::query_metric(
                 &cli.db,
-                &args.uid,
-                &args.scope_id,
+                &args.user.to_ref(),
+                &args.scopes.to_selection(),
                 args.key.as_deref(),
                 args.prefix.as_deref(),
-                cli.json,
+                output,
             ),
             QueryCommands::Topk(args) => commands::query_topk(
                 &cli.db,
-                &args.uid,
-                &args.scope_id,
+                &args.user.to_ref(),
+                &args.scopes.to_selection(),
                 &args.topic,
                 args.limit,
-                cli.json,
+                output,
+            ),
+            QueryCommands::Summary(args) => commands::query_summary(
+                &cli.db,
+                &args.user.to_ref(),
+                &args.scope_id,
+                &args.period,
+                args.event_type.as_deref(),
+                args.topic.as_deref(),
+                args.limit,
+                output,
             ),
         },
-        Commands::State { command } => {
-            commands::todo("state", &format!("{:?}", command));
-            Ok(())
-        }
-        Commands::Admin { command } => match command {
-            AdminCommands::Migrate => commands::admin_migrate(&cli.db),
-            AdminCommands::Reindex => {
-                commands::todo("admin", "reindex");
-                Ok(())
+        Commands::State { command } => match command {
+            StateCommands::Get(args) => commands::state_get(
+                &cli.db,
+                &args.user.to_ref(),
+                &args.scope_id,
+                &args.key,
+                output,
+            ),
+            StateCommands::Set(args) => commands::state_set(
+                &cli.db,
+                hooks,
+                &args.user.to_ref(),
+                &args.scope_id,
+                &args.key,
+                &args.value,
+                output,
+            ),
+            StateCommands::Delete(args) => commands::state_delete(
+                &cli.db,
+                hooks,
+                &args.user.to_ref(),
+                &args.scope_id,
+                &args.key,
+                output,
+            ),
+        },
+        Commands::Outbox { command } => match command {
+            OutboxCommands::List(args) => commands::outbox_list(
+                &cli.db,
+                args.status.as_deref(),
+                args.stream.as_deref(),
+                args.limit,
+                output,
+            ),
+            OutboxCommands::Drain(args) => commands::outbox_drain(
+                &cli.db,
+                &args.sink,
+                &args.target,
+                &agent_memory_cli::service::outbox_service::DrainOptions {
+                    stream: args.stream.as_deref(),
+                    limit: args.limit,
+                    max_attempts: args.max_attempts,
+                    backoff_secs: args.backoff_secs,
+                },
+                output,
+            ),
+            OutboxCommands::Requeue(args) => {
+                commands::outbox_requeue(&cli.db, args.outbox_id.as_deref(), output)
             }
-            AdminCommands::Compact => {
-                commands::todo("admin", "compact");
-                Ok(())
+        },
+        Commands::Admin { command } => match command {
+            AdminCommands::Migrate(args) => {
+                commands::admin_migrate(&cli.db, args.status, args.to, args.dry_run, output)
             }
-            AdminCommands::Archive => {
-                commands::todo("admin", "archive");
-                Ok(())
+            AdminCommands::Reindex(args) => commands::admin_reindex(
+                &cli.db,
+                args.uid.as_deref(),
+                args.scope_id.as_deref(),
+                args.topic.as_deref(),
+                output,
+            ),
+            AdminCommands::Compact => commands::admin_compact(&cli.db, output),
+            AdminCommands::Archive(args) => commands::admin_archive(
+                &cli.db,
+                &args.month,
+                args.dir.as_deref(),
+                args.restore,
+                output,
+            ),
+            AdminCommands::Export(args) => commands::admin_export(&cli.db, &args.out, output),
+            AdminCommands::Import(args) => {
+                commands::admin_import(&cli.db, &args.dir, &args.on_conflict, &args.map_uid, output)
             }
         },
+        Commands::Serve(args) => match (args.http, args.unix_socket) {
+            (Some(addr), _) => commands::serve_http(&cli.db, hooks, &addr),
+            (None, Some(path)) => commands::serve_unix(&cli.db, hooks, &path),
+            (None, None) => commands::serve_stdio(&cli.db, hooks),
+        },
     };
 
     if let Err(e) = result {
-        eprintln!("{e}");
-        std::process::exit(1);
+        if output.is_json() {
+            eprintln!("{}", e.to_json());
+        } else {
+            eprintln!("{e}");
+        }
+        std::process::exit(e.kind().exit_code());
     }
 }
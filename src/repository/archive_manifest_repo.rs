@@ -0,0 +1,142 @@
+use crate::error::AppError;
+use rusqlite::{params, Connection};
+
+pub struct ManifestEntry {
+    pub archive_id: String,
+    pub month: String,
+    pub file_path: String,
+    pub row_count: i64,
+    pub min_event_ts: String,
+    pub max_event_ts: String,
+    pub sha256: String,
+}
+
+pub fn insert(conn: &Connection, entry: &ManifestEntry, now: &str) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO archive_manifest (archive_id, month, file_path, row_count, min_event_ts, max_event_ts, sha256, archived_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            entry.archive_id,
+            entry.month,
+            entry.file_path,
+            entry.row_count,
+            entry.min_event_ts,
+            entry.max_event_ts,
+            entry.sha256,
+            now
+        ],
+    )
+    .map_err(|e| AppError::db(e, "failed to record archive manifest"))?;
+    Ok(())
+}
+
+const ENTRY_COLUMNS: &str =
+    "archive_id, month, file_path, row_count, min_event_ts, max_event_ts, sha256";
+
+fn entry_row(r: &rusqlite::Row<'_>) -> rusqlite::Result<ManifestEntry> {
+    Ok(ManifestEntry {
+        archive_id: r.get(0)?,
+        month: r.get(1)?,
+        file_path: r.get(2)?,
+        row_count: r.get(3)?,
+        min_event_ts: r.get(4)?,
+        max_event_ts: r.get(5)?,
+        sha256: r.get(6)?,
+    })
+}
+
+/// Every archive, restored or not.
+pub fn list_all(conn: &Connection) -> Result<Vec<ManifestEntry>, AppError> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM archive_manifest ORDER BY archived_at ASC, archive_id ASC"
+        ))
+        .map_err(|e| AppError::db(e, "failed to prepare archive manifest query"))?;
+    let rows = stmt
+        .query_map([], entry_row)
+        .map_err(|e| AppError::db(e, "failed to read archive manifest"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed to read archive manifest row"))
+}
+
+/// Archives whose rows are not back in `events`, oldest first.
+pub fn list_all_unrestored(conn: &Connection) -> Result<Vec<ManifestEntry>, AppError> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM archive_manifest WHERE restored_at IS NULL
+             ORDER BY month ASC, archived_at ASC, archive_id ASC"
+        ))
+        .map_err(|e| AppError::db(e, "failed to prepare archive manifest query"))?;
+    let rows = stmt
+        .query_map([], entry_row)
+        .map_err(|e| AppError::db(e, "failed to read archive manifest"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed to read archive manifest row"))
+}
+
+/// Replaces the content fields of an archive after its file was rewritten.
+pub fn update_content(conn: &Connection, entry: &ManifestEntry) -> Result<(), AppError> {
+    conn.execute(
+        "UPDATE archive_manifest
+         SET row_count = ?1, min_event_ts = ?2, max_event_ts = ?3, sha256 = ?4
+         WHERE archive_id = ?5",
+        params![
+            entry.row_count,
+            entry.min_event_ts,
+            entry.max_event_ts,
+            entry.sha256,
+            entry.archive_id
+        ],
+    )
+    .map_err(|e| AppError::db(e, "failed to update archive manifest"))?;
+    Ok(())
+}
+
+pub fn delete(conn: &Connection, archive_id: &str) -> Result<(), AppError> {
+    conn.execute(
+        "DELETE FROM archive_manifest WHERE archive_id = ?1",
+        params![archive_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to delete archive manifest entry"))?;
+    Ok(())
+}
+
+pub fn list_unrestored(conn: &Connection, month: &str) -> Result<Vec<ManifestEntry>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT archive_id, month, file_path, row_count, min_event_ts, max_event_ts, sha256
+             FROM archive_manifest
+             WHERE month = ?1 AND restored_at IS NULL
+             ORDER BY archived_at ASC, archive_id ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare archive manifest query"))?;
+
+    let rows = stmt
+        .query_map(params![month], |r| {
+            Ok(ManifestEntry {
+                archive_id: r.get(0)?,
+                month: r.get(1)?,
+                file_path: r.get(2)?,
+                row_count: r.get(3)?,
+                min_event_ts: r.get(4)?,
+                max_event_ts: r.get(5)?,
+                sha256: r.get(6)?,
+            })
+        })
+        .map_err(|e| AppError::db(e, "failed to read archive manifest"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read archive manifest row"))?);
+    }
+    Ok(out)
+}
+
+pub fn mark_restored(conn: &Connection, archive_id: &str, now: &str) -> Result<(), AppError> {
+    conn.execute(
+        "UPDATE archive_manifest SET restored_at = ?1 WHERE archive_id = ?2",
+        params![now, archive_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to update archive manifest"))?;
+    Ok(())
+}
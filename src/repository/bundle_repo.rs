@@ -0,0 +1,192 @@
+use crate::error::AppError;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use serde_json::{Map, Value};
+
+/// What to do when an imported row collides with an existing, different row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    Fail,
+}
+
+pub fn table_exists(conn: &Connection, table: &str) -> Result<bool, AppError> {
+    let n: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |r| r.get(0),
+        )
+        .map_err(|e| AppError::db(e, format!("failed to check table {table}")))?;
+    Ok(n > 0)
+}
+
+pub fn columns(conn: &Connection, table: &str) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({table})"))
+        .map_err(|e| AppError::db(e, format!("failed to read columns of {table}")))?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| AppError::db(e, format!("failed to read columns of {table}")))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, format!("failed to read columns of {table}")))
+}
+
+fn to_json(value: ValueRef<'_>) -> Result<Value, AppError> {
+    Ok(match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(t) => Value::String(String::from_utf8_lossy(t).to_string()),
+        ValueRef::Blob(_) => return Err(AppError::validation("blob columns are not exportable")),
+    })
+}
+
+fn to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+/// Every row of `table` in insertion order, as column-name keyed JSON objects.
+pub fn dump_table(conn: &Connection, table: &str) -> Result<Vec<Map<String, Value>>, AppError> {
+    select_rows(conn, table, None)
+}
+
+/// Rows of `table` whose `column` equals `value`, in insertion order.
+pub fn dump_rows_where(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    value: &str,
+) -> Result<Vec<Map<String, Value>>, AppError> {
+    select_rows(conn, table, Some((column, value)))
+}
+
+fn select_rows(
+    conn: &Connection,
+    table: &str,
+    filter: Option<(&str, &str)>,
+) -> Result<Vec<Map<String, Value>>, AppError> {
+    let cols = columns(conn, table)?;
+    let (clause, args): (String, Vec<&str>) = match filter {
+        Some((column, value)) => (format!(" WHERE {column} = ?1"), vec![value]),
+        None => (String::new(), Vec::new()),
+    };
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM {table}{clause} ORDER BY rowid",
+            cols.join(", ")
+        ))
+        .map_err(|e| AppError::db(e, format!("failed to prepare export of {table}")))?;
+    let mut rows = stmt
+        .query(params_from_iter(args))
+        .map_err(|e| AppError::db(e, format!("failed to export {table}")))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .map_err(|e| AppError::db(e, format!("failed to export {table}")))?
+    {
+        let mut obj = Map::new();
+        for (idx, col) in cols.iter().enumerate() {
+            let value = row
+                .get_ref(idx)
+                .map_err(|e| AppError::db(e, format!("failed to export {table}.{col}")))?;
+            obj.insert(
+                col.clone(),
+                to_json(value).map_err(|e| e.prefixed(format!("{table}.{col}: ")))?,
+            );
+        }
+        out.push(obj);
+    }
+    Ok(out)
+}
+
+fn identical_exists(
+    conn: &Connection,
+    table: &str,
+    cols: &[&String],
+    values: &[SqlValue],
+) -> Result<bool, AppError> {
+    let filter: Vec<String> = cols
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("{c} IS ?{}", i + 1))
+        .collect();
+    let n: i64 = conn
+        .query_row(
+            &format!(
+                "SELECT COUNT(1) FROM {table} WHERE {}",
+                filter.join(" AND ")
+            ),
+            params_from_iter(values.iter()),
+            |r| r.get(0),
+        )
+        .map_err(|e| AppError::db(e, format!("failed to compare row in {table}")))?;
+    Ok(n > 0)
+}
+
+/// Inserts one exported row. `table_columns` must come from [`columns`]; keys outside it are
+/// rejected. Returns false when the row was skipped, either because an identical row already
+/// exists or because it conflicts and the policy is `Skip`.
+pub fn import_row(
+    conn: &Connection,
+    table: &str,
+    table_columns: &[String],
+    row: &Map<String, Value>,
+    policy: ConflictPolicy,
+) -> Result<bool, AppError> {
+    let mut cols = Vec::with_capacity(row.len());
+    let mut values = Vec::with_capacity(row.len());
+    for (key, value) in row {
+        let col = table_columns.iter().find(|c| *c == key).ok_or_else(|| {
+            AppError::validation(format!(
+                "bundle column {key} does not exist in table {table}"
+            ))
+        })?;
+        cols.push(col);
+        values.push(to_sql(value));
+    }
+    if cols.is_empty() {
+        return Err(AppError::validation(format!(
+            "empty row in bundle table {table}"
+        )));
+    }
+    if identical_exists(conn, table, &cols, &values)? {
+        return Ok(false);
+    }
+
+    let names: Vec<&str> = cols.iter().map(|c| c.as_str()).collect();
+    let placeholders: Vec<String> = (1..=cols.len()).map(|i| format!("?{i}")).collect();
+    let insert = format!(
+        "INTO {table} ({}) VALUES ({})",
+        names.join(", "),
+        placeholders.join(", ")
+    );
+    let sql = match policy {
+        ConflictPolicy::Skip => format!("INSERT OR IGNORE {insert}"),
+        ConflictPolicy::Fail => format!("INSERT {insert}"),
+        ConflictPolicy::Overwrite => {
+            let sets: Vec<String> = names
+                .iter()
+                .map(|c| format!("{c} = excluded.{c}"))
+                .collect();
+            format!(
+                "INSERT {insert} ON CONFLICT DO UPDATE SET {}",
+                sets.join(", ")
+            )
+        }
+    };
+    let changed = conn
+        .execute(&sql, params_from_iter(values.iter()))
+        .map_err(|e| AppError::db(e, format!("failed to import row into {table}")))?;
+    Ok(changed > 0)
+}
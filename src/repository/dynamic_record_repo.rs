@@ -1,3 +1,4 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection};
 
 #[allow(dead_code)]
@@ -12,7 +13,7 @@ pub struct DynamicRecordUpsert<'a> {
 }
 
 #[allow(dead_code)]
-pub fn upsert(conn: &Connection, input: DynamicRecordUpsert<'_>) -> Result<(), String> {
+pub fn upsert(conn: &Connection, input: DynamicRecordUpsert<'_>) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO dynamic_records (record_id, schema_id, entity_key, uid, scope_id, payload_json, created_at, updated_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
@@ -33,6 +34,6 @@ pub fn upsert(conn: &Connection, input: DynamicRecordUpsert<'_>) -> Result<(), S
             input.now
         ],
     )
-    .map_err(|e| format!("failed to upsert dynamic record: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to upsert dynamic record"))?;
     Ok(())
 }
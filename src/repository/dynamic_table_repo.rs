@@ -1,5 +1,17 @@
-use crate::domain::schema::{SchemaClass, SchemaDef};
-use rusqlite::Connection;
+use crate::domain::schema::{FieldDef, SchemaClass, SchemaDef};
+use crate::error::AppError;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+
+const RESERVED_COLUMNS: [&str; 6] = [
+    "record_id",
+    "created_at",
+    "updated_at",
+    "entity_key",
+    "ref_user_id",
+    "ref_scope_id",
+];
 
 fn sanitize_ident(raw: &str) -> String {
     raw.chars()
@@ -28,7 +40,7 @@ fn map_sql_type(t: &str) -> &'static str {
     }
 }
 
-pub fn create_table_for_schema(conn: &Connection, def: &SchemaDef) -> Result<String, String> {
+pub fn create_table_for_schema(conn: &Connection, def: &SchemaDef) -> Result<String, AppError> {
     let table = table_name_for(def);
 
     let mut cols = vec![
@@ -60,19 +72,294 @@ pub fn create_table_for_schema(conn: &Connection, def: &SchemaDef) -> Result<Str
 
     let ddl = format!("CREATE TABLE IF NOT EXISTS {table} ({})", cols.join(", "));
     conn.execute_batch(&ddl)
-        .map_err(|e| format!("failed to create dynamic table {table}: {e}"))?;
+        .map_err(|e| AppError::db(e, format!("failed to create dynamic table {table}")))?;
 
     conn.execute_batch(&format!(
         "CREATE INDEX IF NOT EXISTS idx_{table}_updated_at ON {table}(updated_at);"
     ))
-    .map_err(|e| format!("failed to create index for {table}: {e}"))?;
+    .map_err(|e| AppError::db(e, format!("failed to create index for {table}")))?;
 
     if matches!(def.class, SchemaClass::UserContext) {
         conn.execute_batch(&format!(
             "CREATE INDEX IF NOT EXISTS idx_{table}_ref_user_id ON {table}(ref_user_id);"
         ))
-        .map_err(|e| format!("failed to create user index for {table}: {e}"))?;
+        .map_err(|e| AppError::db(e, format!("failed to create user index for {table}")))?;
     }
 
     Ok(table)
 }
+
+pub struct DynamicRow<'a> {
+    pub record_id: &'a str,
+    pub entity_key: Option<&'a str>,
+    pub ref_user_id: Option<&'a str>,
+    pub ref_scope_id: Option<&'a str>,
+    pub fields: &'a Map<String, Value>,
+    pub now: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamicRecord {
+    pub record_id: String,
+    pub entity_key: Option<String>,
+    pub ref_user_id: Option<String>,
+    pub ref_scope_id: Option<String>,
+    pub fields: Map<String, Value>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+fn to_sql_value(field_type: &str, value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) if !is_json_type(field_type) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn is_json_type(field_type: &str) -> bool {
+    matches!(
+        field_type.to_ascii_lowercase().as_str(),
+        "json" | "object" | "array"
+    )
+}
+
+fn from_sql_value(field: &FieldDef, value: ValueRef<'_>) -> Value {
+    let is_bool = matches!(
+        field.field_type.to_ascii_lowercase().as_str(),
+        "bool" | "boolean"
+    );
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) if is_bool => Value::Bool(i != 0),
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(t) | ValueRef::Blob(t) => {
+            let text = String::from_utf8_lossy(t).to_string();
+            if is_json_type(&field.field_type) {
+                serde_json::from_str(&text).unwrap_or(Value::String(text))
+            } else {
+                Value::String(text)
+            }
+        }
+    }
+}
+
+fn field_columns(def: &SchemaDef) -> Result<Vec<(&FieldDef, String)>, AppError> {
+    def.fields
+        .iter()
+        .map(|f| {
+            let col = sanitize_ident(&f.name);
+            if RESERVED_COLUMNS.contains(&col.as_str()) {
+                return Err(AppError::validation(format!(
+                    "field name='{}' collides with reserved column {col} in schema_id={}",
+                    f.name, def.schema_id
+                )));
+            }
+            Ok((f, col))
+        })
+        .collect()
+}
+
+fn meta_columns(def: &SchemaDef) -> &'static [&'static str] {
+    match def.class {
+        SchemaClass::Domain => &["record_id", "created_at", "updated_at", "entity_key"],
+        SchemaClass::UserContext => &[
+            "record_id",
+            "created_at",
+            "updated_at",
+            "entity_key",
+            "ref_user_id",
+            "ref_scope_id",
+        ],
+    }
+}
+
+pub fn upsert_row(conn: &Connection, def: &SchemaDef, row: DynamicRow<'_>) -> Result<(), AppError> {
+    let table = table_name_for(def);
+    let fields = field_columns(def)?;
+
+    let mut cols: Vec<String> = vec![
+        "record_id".into(),
+        "created_at".into(),
+        "updated_at".into(),
+        "entity_key".into(),
+    ];
+    let mut values = vec![
+        SqlValue::Text(row.record_id.to_string()),
+        SqlValue::Text(row.now.to_string()),
+        SqlValue::Text(row.now.to_string()),
+        row.entity_key
+            .map_or(SqlValue::Null, |k| SqlValue::Text(k.to_string())),
+    ];
+    if def.class == SchemaClass::UserContext {
+        cols.push("ref_user_id".into());
+        values.push(
+            row.ref_user_id
+                .map_or(SqlValue::Null, |u| SqlValue::Text(u.to_string())),
+        );
+        cols.push("ref_scope_id".into());
+        values.push(
+            row.ref_scope_id
+                .map_or(SqlValue::Null, |s| SqlValue::Text(s.to_string())),
+        );
+    }
+    for (f, col) in &fields {
+        cols.push(col.clone());
+        values.push(to_sql_value(
+            &f.field_type,
+            row.fields.get(&f.name).unwrap_or(&Value::Null),
+        ));
+    }
+
+    let placeholders: Vec<String> = (1..=cols.len()).map(|i| format!("?{i}")).collect();
+    let updates: Vec<String> = cols
+        .iter()
+        .filter(|c| *c != "record_id" && *c != "created_at")
+        .map(|c| format!("{c}=excluded.{c}"))
+        .collect();
+    let sql = format!(
+        "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT(record_id) DO UPDATE SET {}",
+        cols.join(", "),
+        placeholders.join(", "),
+        updates.join(", ")
+    );
+    conn.execute(&sql, params_from_iter(values))
+        .map_err(|e| AppError::db(e, format!("failed to write record to {table}")))?;
+    Ok(())
+}
+
+fn select_sql(def: &SchemaDef, fields: &[(&FieldDef, String)]) -> String {
+    let mut cols: Vec<String> = meta_columns(def).iter().map(|c| c.to_string()).collect();
+    cols.extend(fields.iter().map(|(_, c)| c.clone()));
+    format!("SELECT {} FROM {}", cols.join(", "), table_name_for(def))
+}
+
+fn record_from_row(
+    def: &SchemaDef,
+    fields: &[(&FieldDef, String)],
+    row: &rusqlite::Row<'_>,
+) -> rusqlite::Result<DynamicRecord> {
+    let meta = meta_columns(def).len();
+    let (ref_user_id, ref_scope_id) = match def.class {
+        SchemaClass::Domain => (None, None),
+        SchemaClass::UserContext => (row.get(4)?, row.get(5)?),
+    };
+    let mut out = Map::new();
+    for (i, (f, _)) in fields.iter().enumerate() {
+        out.insert(f.name.clone(), from_sql_value(f, row.get_ref(meta + i)?));
+    }
+    Ok(DynamicRecord {
+        record_id: row.get(0)?,
+        created_at: row.get(1)?,
+        updated_at: row.get(2)?,
+        entity_key: row.get(3)?,
+        ref_user_id,
+        ref_scope_id,
+        fields: out,
+    })
+}
+
+pub fn get_row(
+    conn: &Connection,
+    def: &SchemaDef,
+    record_id: &str,
+) -> Result<Option<DynamicRecord>, AppError> {
+    let fields = field_columns(def)?;
+    let select = select_sql(def, &fields);
+    conn.query_row(
+        &format!("{select} WHERE record_id = ?1"),
+        params![record_id],
+        |row| record_from_row(def, &fields, row),
+    )
+    .optional()
+    .map_err(|e| AppError::db(e, "failed to read record"))
+}
+
+pub fn list_rows(
+    conn: &Connection,
+    def: &SchemaDef,
+    entity_key: Option<&str>,
+    ref_user_id: Option<&str>,
+    limit: usize,
+) -> Result<Vec<DynamicRecord>, AppError> {
+    let fields = field_columns(def)?;
+    let select = select_sql(def, &fields);
+    let user_filter = match def.class {
+        SchemaClass::Domain => "?2 IS NULL",
+        SchemaClass::UserContext => "(?2 IS NULL OR ref_user_id = ?2)",
+    };
+    let mut stmt = conn
+        .prepare(&format!(
+            "{select} WHERE (?1 IS NULL OR entity_key = ?1) AND {user_filter}
+             ORDER BY updated_at DESC, record_id ASC
+             LIMIT ?3"
+        ))
+        .map_err(|e| AppError::db(e, "failed to prepare record list"))?;
+
+    let rows = stmt
+        .query_map(params![entity_key, ref_user_id, limit as i64], |row| {
+            record_from_row(def, &fields, row)
+        })
+        .map_err(|e| AppError::db(e, "failed to list records"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read record row"))?);
+    }
+    Ok(out)
+}
+
+pub fn delete_row(conn: &Connection, def: &SchemaDef, record_id: &str) -> Result<usize, AppError> {
+    let table = table_name_for(def);
+    conn.execute(
+        &format!("DELETE FROM {table} WHERE record_id = ?1"),
+        params![record_id],
+    )
+    .map_err(|e| AppError::db(e, format!("failed to delete record from {table}")))
+}
+
+/// Copies rows of `from`'s table into `to`'s table. Fields present in both versions are
+/// copied as-is, new fields take their `default` (or NULL) and removed fields are dropped.
+/// A row the target's constraints reject (e.g. NULL in a field that became NOT NULL)
+/// fails the copy instead of being skipped.
+pub fn copy_rows(conn: &Connection, from: &SchemaDef, to: &SchemaDef) -> Result<usize, AppError> {
+    let from_table = table_name_for(from);
+    let to_table = table_name_for(to);
+    let from_fields = field_columns(from)?;
+    let to_fields = field_columns(to)?;
+
+    let mut cols: Vec<String> = meta_columns(to).iter().map(|c| c.to_string()).collect();
+    let mut exprs = cols.clone();
+    let mut values = Vec::new();
+    for (f, col) in &to_fields {
+        cols.push(col.clone());
+        match from_fields.iter().find(|(of, _)| of.name == f.name) {
+            Some((_, from_col)) => exprs.push(from_col.clone()),
+            None => {
+                values.push(to_sql_value(
+                    &f.field_type,
+                    f.default.as_ref().unwrap_or(&Value::Null),
+                ));
+                exprs.push(format!("?{}", values.len()));
+            }
+        }
+    }
+
+    let sql = format!(
+        "INSERT INTO {to_table} ({}) SELECT {} FROM {from_table}",
+        cols.join(", "),
+        exprs.join(", ")
+    );
+    conn.execute(&sql, params_from_iter(values)).map_err(|e| {
+        AppError::db(
+            e,
+            format!("failed to copy rows from {from_table} to {to_table}"),
+        )
+    })
+}
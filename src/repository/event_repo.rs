@@ -1,4 +1,6 @@
+use crate::error::AppError;
 use rusqlite::{params, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
 
 pub struct NewEvent<'a> {
     pub event_id: &'a str,
@@ -10,23 +12,39 @@ pub struct NewEvent<'a> {
     pub idempotency_key: Option<&'a str>,
 }
 
+/// Full `events` row, used where rows leave or re-enter SQLite verbatim.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRow {
+    pub event_id: String,
+    pub uid: String,
+    pub scope_id: String,
+    pub event_type: String,
+    pub event_ts: String,
+    pub payload_json: String,
+    pub source_channel: Option<String>,
+    pub source_message_id: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub schema_version: String,
+    pub created_at: String,
+}
+
 pub fn idempotency_exists(
     tx: &Transaction<'_>,
     scope_id: &str,
     uid: &str,
     key: &str,
-) -> Result<bool, String> {
+) -> Result<bool, AppError> {
     let count: i64 = tx
         .query_row(
             "SELECT COUNT(1) FROM events WHERE scope_id = ?1 AND uid = ?2 AND idempotency_key = ?3",
             params![scope_id, uid, key],
             |row| row.get(0),
         )
-        .map_err(|e| format!("failed idempotency check: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed idempotency check"))?;
     Ok(count > 0)
 }
 
-pub fn insert(tx: &Transaction<'_>, e: NewEvent<'_>) -> Result<(), String> {
+pub fn insert(tx: &Transaction<'_>, e: NewEvent<'_>) -> Result<(), AppError> {
     tx.execute(
         "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, idempotency_key, schema_version, created_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, '1', ?5)",
@@ -40,7 +58,7 @@ pub fn insert(tx: &Transaction<'_>, e: NewEvent<'_>) -> Result<(), String> {
             e.idempotency_key
         ],
     )
-    .map_err(|e| format!("failed to insert event: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to insert event"))?;
     Ok(())
 }
 
@@ -48,15 +66,127 @@ pub fn latest(
     conn: &rusqlite::Connection,
     uid: &str,
     scope_id: &str,
-) -> Result<Option<(String, String, String)>, String> {
-    conn.query_row(
+) -> Result<Option<(String, String, String)>, AppError> {
+    conn.prepare_cached(
         "SELECT event_id, event_type, event_ts FROM events
          WHERE uid = ?1 AND scope_id = ?2
          ORDER BY rowid DESC
          LIMIT 1",
-        params![uid, scope_id],
-        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
     )
+    .map_err(|e| AppError::db(e, "failed to prepare latest query"))?
+    .query_row(params![uid, scope_id], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })
     .optional()
-    .map_err(|e| format!("failed latest query: {e}"))
+    .map_err(|e| AppError::db(e, "failed latest query"))
+}
+
+const ROW_COLUMNS: &str =
+    "event_id, uid, scope_id, event_type, event_ts, payload_json, source_channel,
+     source_message_id, idempotency_key, schema_version, created_at";
+
+fn event_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<EventRow> {
+    Ok(EventRow {
+        event_id: row.get(0)?,
+        uid: row.get(1)?,
+        scope_id: row.get(2)?,
+        event_type: row.get(3)?,
+        event_ts: row.get(4)?,
+        payload_json: row.get(5)?,
+        source_channel: row.get(6)?,
+        source_message_id: row.get(7)?,
+        idempotency_key: row.get(8)?,
+        schema_version: row.get(9)?,
+        created_at: row.get(10)?,
+    })
+}
+
+pub fn list_uncompacted(conn: &rusqlite::Connection) -> Result<Vec<EventRow>, AppError> {
+    list_rows(
+        conn,
+        &format!(
+            "SELECT {ROW_COLUMNS} FROM events
+             WHERE NOT EXISTS (SELECT 1 FROM compaction_ledger l WHERE l.event_id = events.event_id)
+             ORDER BY rowid ASC"
+        ),
+        [],
+    )
+}
+
+fn list_rows(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<EventRow>, AppError> {
+    let mut stmt = conn
+        .prepare(sql)
+        .map_err(|e| AppError::db(e, "failed to prepare event query"))?;
+    let rows = stmt
+        .query_map(params, event_row)
+        .map_err(|e| AppError::db(e, "failed to load events"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed to read event row"))
+}
+
+pub fn list_for_replay(
+    conn: &rusqlite::Connection,
+    uid: Option<&str>,
+    scope_id: Option<&str>,
+) -> Result<Vec<EventRow>, AppError> {
+    list_rows(
+        conn,
+        &format!(
+            "SELECT {ROW_COLUMNS} FROM events
+             WHERE (?1 IS NULL OR uid = ?1) AND (?2 IS NULL OR scope_id = ?2)
+             ORDER BY rowid ASC"
+        ),
+        params![uid, scope_id],
+    )
+}
+
+/// Rows whose `event_ts` casts below `end_ts`. Non-numeric timestamps cast to 0, so callers
+/// must check `event_ts` before trusting the row's position in time.
+pub fn list_rows_before(
+    conn: &rusqlite::Connection,
+    end_ts: i64,
+) -> Result<Vec<EventRow>, AppError> {
+    list_rows(
+        conn,
+        &format!(
+            "SELECT {ROW_COLUMNS} FROM events
+             WHERE CAST(event_ts AS INTEGER) < ?1
+             ORDER BY CAST(event_ts AS INTEGER) ASC, rowid ASC"
+        ),
+        params![end_ts],
+    )
+}
+
+pub fn delete_by_id(tx: &Transaction<'_>, event_id: &str) -> Result<usize, AppError> {
+    tx.execute("DELETE FROM events WHERE event_id = ?1", params![event_id])
+        .map_err(|e| AppError::db(e, "failed to delete event"))
+}
+
+/// Inserts a verbatim row, ignoring rows whose id or idempotency key already exist.
+pub fn insert_row(tx: &Transaction<'_>, row: &EventRow) -> Result<bool, AppError> {
+    let n = tx
+        .execute(
+            "INSERT OR IGNORE INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json,
+                source_channel, source_message_id, idempotency_key, schema_version, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                row.event_id,
+                row.uid,
+                row.scope_id,
+                row.event_type,
+                row.event_ts,
+                row.payload_json,
+                row.source_channel,
+                row.source_message_id,
+                row.idempotency_key,
+                row.schema_version,
+                row.created_at
+            ],
+        )
+        .map_err(|e| AppError::db(e, "failed to restore event"))?;
+    Ok(n > 0)
 }
@@ -0,0 +1,59 @@
+use crate::error::AppError;
+use rusqlite::{params, Connection, OptionalExtension};
+
+pub fn upsert(
+    conn: &Connection,
+    event_type: &str,
+    schema_id: &str,
+    mode: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO event_schema_bindings (event_type, schema_id, mode, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT(event_type) DO UPDATE SET
+           schema_id=excluded.schema_id,
+           mode=excluded.mode,
+           updated_at=excluded.updated_at",
+        params![event_type, schema_id, mode, now],
+    )
+    .map_err(|e| AppError::db(e, "failed to bind event schema"))?;
+    Ok(())
+}
+
+pub fn delete(conn: &Connection, event_type: &str) -> Result<usize, AppError> {
+    conn.execute(
+        "DELETE FROM event_schema_bindings WHERE event_type = ?1",
+        params![event_type],
+    )
+    .map_err(|e| AppError::db(e, "failed to unbind event schema"))
+}
+
+/// Returns `(schema_id, mode)` bound to `event_type`.
+pub fn get(conn: &Connection, event_type: &str) -> Result<Option<(String, String)>, AppError> {
+    conn.prepare_cached("SELECT schema_id, mode FROM event_schema_bindings WHERE event_type = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_row(params![event_type], |r| Ok((r.get(0)?, r.get(1)?)))
+                .optional()
+        })
+        .map_err(|e| AppError::db(e, "failed to read event schema binding"))
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<(String, String, String, String)>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT event_type, schema_id, mode, updated_at FROM event_schema_bindings
+             ORDER BY event_type ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to list event schema bindings"))?;
+
+    let rows = stmt
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .map_err(|e| AppError::db(e, "failed to read event schema bindings"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read event schema binding row"))?);
+    }
+    Ok(out)
+}
@@ -1,3 +1,4 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection, OptionalExtension};
 
 pub fn insert(
@@ -7,13 +8,13 @@ pub fn insert(
     channel: &str,
     channel_user_id: &str,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO user_identities (identity_id, uid, channel, channel_user_id, is_verified, confidence, created_at, updated_at)
          VALUES (?1, ?2, ?3, ?4, 0, 1.0, ?5, ?5)",
         params![identity_id, uid, channel, channel_user_id, now],
     )
-    .map_err(|e| format!("failed to link identity: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to link identity"))?;
     Ok(())
 }
 
@@ -21,20 +22,20 @@ pub fn resolve_uid(
     conn: &Connection,
     channel: &str,
     channel_user_id: &str,
-) -> Result<Option<String>, String> {
-    conn.query_row(
+) -> Result<Option<String>, AppError> {
+    conn.prepare_cached(
         "SELECT uid FROM user_identities WHERE channel = ?1 AND channel_user_id = ?2",
-        params![channel, channel_user_id],
-        |row| row.get(0),
     )
+    .map_err(|e| AppError::db(e, "failed to prepare identity lookup"))?
+    .query_row(params![channel, channel_user_id], |row| row.get(0))
     .optional()
-    .map_err(|e| format!("failed to resolve identity: {e}"))
+    .map_err(|e| AppError::db(e, "failed to resolve identity"))
 }
 
-pub fn delete(conn: &Connection, channel: &str, channel_user_id: &str) -> Result<usize, String> {
+pub fn delete(conn: &Connection, channel: &str, channel_user_id: &str) -> Result<usize, AppError> {
     conn.execute(
         "DELETE FROM user_identities WHERE channel = ?1 AND channel_user_id = ?2",
         params![channel, channel_user_id],
     )
-    .map_err(|e| format!("failed to unlink identity: {e}"))
+    .map_err(|e| AppError::db(e, "failed to unlink identity"))
 }
@@ -0,0 +1,82 @@
+use crate::domain::materializer::MaterializerRule;
+use crate::error::AppError;
+use rusqlite::{params, Connection};
+
+pub fn upsert(
+    conn: &Connection,
+    rule: &MaterializerRule,
+    rule_json: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO materializer_rules (rule_id, event_type, rule_json, is_active, created_at, updated_at)
+         VALUES (?1, ?2, ?3, 1, ?4, ?4)
+         ON CONFLICT(rule_id) DO UPDATE SET
+           event_type=excluded.event_type,
+           rule_json=excluded.rule_json,
+           is_active=1,
+           updated_at=excluded.updated_at",
+        params![rule.rule_id, rule.event_type, rule_json, now],
+    )
+    .map_err(|e| AppError::db(e, "failed to register materializer rule"))?;
+    Ok(())
+}
+
+pub fn set_active(
+    conn: &Connection,
+    rule_id: &str,
+    active: bool,
+    now: &str,
+) -> Result<usize, AppError> {
+    conn.execute(
+        "UPDATE materializer_rules SET is_active = ?1, updated_at = ?2 WHERE rule_id = ?3",
+        params![active as i64, now, rule_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to update materializer rule"))
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<(String, String, i64, String)>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT rule_id, rule_json, is_active, updated_at FROM materializer_rules
+             ORDER BY event_type ASC, rule_id ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to list materializer rules"))?;
+
+    let rows = stmt
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, i64>(2)?,
+                r.get::<_, String>(3)?,
+            ))
+        })
+        .map_err(|e| AppError::db(e, "failed to read materializer rules"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read materializer rule row"))?);
+    }
+    Ok(out)
+}
+
+pub fn active_for_event_type(conn: &Connection, event_type: &str) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT rule_json FROM materializer_rules
+             WHERE event_type = ?1 AND is_active = 1
+             ORDER BY rule_id ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare materializer rule query"))?;
+
+    let rows = stmt
+        .query_map(params![event_type], |r| r.get::<_, String>(0))
+        .map_err(|e| AppError::db(e, "failed to load materializer rules"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read materializer rule row"))?);
+    }
+    Ok(out)
+}
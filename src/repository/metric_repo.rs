@@ -1,3 +1,4 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection, Transaction};
 
 pub fn upsert_counter(
@@ -8,7 +9,7 @@ pub fn upsert_counter(
     item: &str,
     delta: f64,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     tx.execute(
         "INSERT INTO metrics (scope_id, uid, metric_key, metric_value, metric_json, updated_at)
          VALUES (?1, ?2, ?3, ?4, NULL, ?5)
@@ -16,30 +17,79 @@ pub fn upsert_counter(
          DO UPDATE SET metric_value = COALESCE(metrics.metric_value, 0) + excluded.metric_value, updated_at = excluded.updated_at",
         params![scope_id, uid, format!("counter:{topic}:{item}"), delta, now],
     )
-    .map_err(|e| format!("failed to update counter: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to update counter"))?;
     Ok(())
 }
 
+/// `LIKE` pattern matching keys that start with `prefix` literally; pair with `ESCAPE '\'`.
+fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+pub fn clear_counters(
+    tx: &Transaction<'_>,
+    uid: Option<&str>,
+    scope_id: Option<&str>,
+    topic: Option<&str>,
+) -> Result<usize, AppError> {
+    let like = match topic {
+        Some(topic) => like_prefix(&format!("counter:{topic}:")),
+        None => like_prefix("counter:"),
+    };
+    tx.execute(
+        "DELETE FROM metrics
+         WHERE (?1 IS NULL OR uid = ?1) AND (?2 IS NULL OR scope_id = ?2) AND metric_key LIKE ?3 ESCAPE '\\'",
+        params![uid, scope_id, like],
+    )
+    .map_err(|e| AppError::db(e, "failed to clear counters"))
+}
+
+/// Scopes in which `uid` holds `counter:*` metrics.
+pub fn counter_scopes(conn: &Connection, uid: &str) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT scope_id FROM metrics
+             WHERE uid = ?1 AND metric_key LIKE 'counter:%' ORDER BY scope_id",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare counter scope query"))?;
+    let rows = stmt
+        .query_map(params![uid], |row| row.get(0))
+        .map_err(|e| AppError::db(e, "failed to load counter scopes"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed row read"))
+}
+
 pub fn query_by_key(
     conn: &Connection,
     scope_id: &str,
     uid: &str,
     key: &str,
-) -> Result<Option<(String, f64, String)>, String> {
+) -> Result<Option<(String, f64, String)>, AppError> {
     let mut stmt = conn
-        .prepare(
+        .prepare_cached(
             "SELECT metric_key, COALESCE(metric_value, 0), COALESCE(metric_json, '')
              FROM metrics
              WHERE scope_id = ?1 AND uid = ?2 AND metric_key = ?3",
         )
-        .map_err(|e| format!("failed to prepare metric query: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare metric query"))?;
     let mut rows = stmt
         .query(params![scope_id, uid, key])
-        .map_err(|e| format!("failed to run metric query: {e}"))?;
-    if let Some(row) = rows.next().map_err(|e| format!("failed row read: {e}"))? {
-        let k: String = row.get(0).map_err(|e| format!("failed col read: {e}"))?;
-        let v: f64 = row.get(1).map_err(|e| format!("failed col read: {e}"))?;
-        let j: String = row.get(2).map_err(|e| format!("failed col read: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to run metric query"))?;
+    if let Some(row) = rows
+        .next()
+        .map_err(|e| AppError::db(e, "failed row read"))?
+    {
+        let k: String = row.get(0).map_err(|e| AppError::db(e, "failed col read"))?;
+        let v: f64 = row.get(1).map_err(|e| AppError::db(e, "failed col read"))?;
+        let j: String = row.get(2).map_err(|e| AppError::db(e, "failed col read"))?;
         Ok(Some((k, v, j)))
     } else {
         Ok(None)
@@ -51,16 +101,16 @@ pub fn query_by_prefix(
     scope_id: &str,
     uid: &str,
     prefix: &str,
-) -> Result<Vec<(String, f64, String)>, String> {
+) -> Result<Vec<(String, f64, String)>, AppError> {
     let mut stmt = conn
-        .prepare(
+        .prepare_cached(
             "SELECT metric_key, COALESCE(metric_value, 0), COALESCE(metric_json, '')
              FROM metrics
-             WHERE scope_id = ?1 AND uid = ?2 AND metric_key LIKE ?3
+             WHERE scope_id = ?1 AND uid = ?2 AND metric_key LIKE ?3 ESCAPE '\\'
              ORDER BY metric_key ASC",
         )
-        .map_err(|e| format!("failed to prepare metric prefix query: {e}"))?;
-    let like = format!("{prefix}%");
+        .map_err(|e| AppError::db(e, "failed to prepare metric prefix query"))?;
+    let like = like_prefix(prefix);
     let rows = stmt
         .query_map(params![scope_id, uid, like], |row| {
             let k: String = row.get(0)?;
@@ -68,11 +118,11 @@ pub fn query_by_prefix(
             let j: String = row.get(2)?;
             Ok((k, v, j))
         })
-        .map_err(|e| format!("failed to run metric prefix query: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to run metric prefix query"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed row read: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed row read"))?);
     }
     Ok(out)
 }
@@ -82,29 +132,29 @@ pub fn topk_source(
     scope_id: &str,
     uid: &str,
     topic: &str,
-) -> Result<Vec<(String, f64)>, String> {
+) -> Result<Vec<(String, f64)>, AppError> {
     let mut stmt = tx
         .prepare(
             "SELECT metric_key, COALESCE(metric_value, 0) as score
              FROM metrics
-             WHERE scope_id = ?1 AND uid = ?2 AND metric_key LIKE ?3
+             WHERE scope_id = ?1 AND uid = ?2 AND metric_key LIKE ?3 ESCAPE '\\'
              ORDER BY score DESC, metric_key ASC
              LIMIT 10",
         )
-        .map_err(|e| format!("failed to prepare topk query: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare topk query"))?;
 
-    let like = format!("counter:{topic}:%");
+    let like = like_prefix(&format!("counter:{topic}:"));
     let rows = stmt
         .query_map(params![scope_id, uid, like], |row| {
             let key: String = row.get(0)?;
             let score: f64 = row.get(1)?;
             Ok((key, score))
         })
-        .map_err(|e| format!("failed to load topk counters: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to load topk counters"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed row: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed row"))?);
     }
     Ok(out)
 }
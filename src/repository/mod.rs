@@ -1,10 +1,16 @@
+pub mod archive_manifest_repo;
+pub mod bundle_repo;
 pub mod dynamic_record_repo;
 pub mod dynamic_table_repo;
 pub mod event_repo;
+pub mod event_schema_binding_repo;
 pub mod identity_repo;
+pub mod materializer_rule_repo;
 pub mod metric_repo;
 pub mod projection_outbox_repo;
 pub mod schema_registry_repo;
 pub mod scope_repo;
+pub mod state_repo;
+pub mod summary_repo;
 pub mod topk_repo;
 pub mod user_repo;
@@ -1,4 +1,6 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection};
+use serde::Serialize;
 
 pub fn enqueue(
     conn: &Connection,
@@ -7,12 +9,151 @@ pub fn enqueue(
     key: &str,
     payload_json: &str,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO projection_outbox (outbox_id, stream, item_key, payload_json, created_at)
          VALUES (?1, ?2, ?3, ?4, ?5)",
         params![outbox_id, stream, key, payload_json, now],
     )
-    .map_err(|e| format!("failed to enqueue projection outbox: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to enqueue projection outbox"))?;
     Ok(())
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxRow {
+    pub outbox_id: String,
+    pub stream: String,
+    pub item_key: String,
+    pub payload_json: String,
+    pub created_at: String,
+    pub attempts: i64,
+    pub next_attempt_at: Option<String>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<String>,
+    pub dead_at: Option<String>,
+}
+
+impl OutboxRow {
+    pub fn status(&self) -> &'static str {
+        if self.delivered_at.is_some() {
+            "delivered"
+        } else if self.dead_at.is_some() {
+            "dead"
+        } else {
+            "pending"
+        }
+    }
+}
+
+const OUTBOX_COLUMNS: &str = "outbox_id, stream, item_key, payload_json, created_at, attempts, next_attempt_at, last_error, delivered_at, dead_at";
+
+fn outbox_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<OutboxRow> {
+    Ok(OutboxRow {
+        outbox_id: row.get(0)?,
+        stream: row.get(1)?,
+        item_key: row.get(2)?,
+        payload_json: row.get(3)?,
+        created_at: row.get(4)?,
+        attempts: row.get(5)?,
+        next_attempt_at: row.get(6)?,
+        last_error: row.get(7)?,
+        delivered_at: row.get(8)?,
+        dead_at: row.get(9)?,
+    })
+}
+
+fn collect_rows(
+    stmt: &mut rusqlite::Statement<'_>,
+    params: impl rusqlite::Params,
+) -> Result<Vec<OutboxRow>, AppError> {
+    let rows = stmt
+        .query_map(params, outbox_row)
+        .map_err(|e| AppError::db(e, "failed to read projection outbox"))?;
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read projection outbox row"))?);
+    }
+    Ok(out)
+}
+
+/// Rows filtered by `status` (`pending`, `delivered` or `dead`) and `stream`, oldest first.
+pub fn list(
+    conn: &Connection,
+    status: Option<&str>,
+    stream: Option<&str>,
+    limit: usize,
+) -> Result<Vec<OutboxRow>, AppError> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM projection_outbox
+             WHERE (?1 IS NULL
+                    OR (?1 = 'pending' AND delivered_at IS NULL AND dead_at IS NULL)
+                    OR (?1 = 'delivered' AND delivered_at IS NOT NULL)
+                    OR (?1 = 'dead' AND dead_at IS NOT NULL))
+               AND (?2 IS NULL OR stream = ?2)
+             ORDER BY created_at ASC, rowid ASC
+             LIMIT ?3"
+        ))
+        .map_err(|e| AppError::db(e, "failed to prepare projection outbox list"))?;
+    collect_rows(&mut stmt, params![status, stream, limit as i64])
+}
+
+/// Undelivered, non-dead rows whose backoff has elapsed at `now`.
+pub fn due(
+    conn: &Connection,
+    stream: Option<&str>,
+    now: &str,
+    limit: usize,
+) -> Result<Vec<OutboxRow>, AppError> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {OUTBOX_COLUMNS} FROM projection_outbox
+             WHERE delivered_at IS NULL AND dead_at IS NULL
+               AND (next_attempt_at IS NULL OR CAST(next_attempt_at AS INTEGER) <= CAST(?1 AS INTEGER))
+               AND (?2 IS NULL OR stream = ?2)
+             ORDER BY created_at ASC, rowid ASC
+             LIMIT ?3"
+        ))
+        .map_err(|e| AppError::db(e, "failed to prepare projection outbox drain"))?;
+    collect_rows(&mut stmt, params![now, stream, limit as i64])
+}
+
+pub fn mark_delivered(conn: &Connection, outbox_id: &str, now: &str) -> Result<(), AppError> {
+    conn.execute(
+        "UPDATE projection_outbox
+         SET delivered_at = ?1, attempts = attempts + 1, last_error = NULL, next_attempt_at = NULL
+         WHERE outbox_id = ?2",
+        params![now, outbox_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to mark outbox delivered"))?;
+    Ok(())
+}
+
+/// Records a failed attempt; `dead_at` is set once the row is given up on.
+pub fn record_failure(
+    conn: &Connection,
+    outbox_id: &str,
+    error: &str,
+    next_attempt_at: Option<&str>,
+    dead_at: Option<&str>,
+) -> Result<(), AppError> {
+    conn.execute(
+        "UPDATE projection_outbox
+         SET attempts = attempts + 1, last_error = ?1, next_attempt_at = ?2, dead_at = ?3
+         WHERE outbox_id = ?4",
+        params![error, next_attempt_at, dead_at, outbox_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to record outbox failure"))?;
+    Ok(())
+}
+
+/// Moves dead rows (one, or all when `outbox_id` is `None`) back to pending.
+pub fn requeue_dead(conn: &Connection, outbox_id: Option<&str>) -> Result<usize, AppError> {
+    conn.execute(
+        "UPDATE projection_outbox
+         SET dead_at = NULL, attempts = 0, next_attempt_at = NULL
+         WHERE dead_at IS NOT NULL AND (?1 IS NULL OR outbox_id = ?1)",
+        params![outbox_id],
+    )
+    .map_err(|e| AppError::db(e, "failed to requeue outbox rows"))
+}
@@ -1,26 +1,90 @@
 use crate::domain::schema::SchemaDef;
-use rusqlite::{params, Connection};
+use crate::error::AppError;
+use rusqlite::{params, Connection, OptionalExtension};
 
-pub fn upsert(
+pub fn insert(
     conn: &Connection,
     schema: &SchemaDef,
     schema_json: &str,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO schema_registry (schema_id, version, schema_json, is_active, created_at)
-         VALUES (?1, ?2, ?3, 1, ?4)
-         ON CONFLICT(schema_id) DO UPDATE SET version=excluded.version, schema_json=excluded.schema_json, is_active=1",
+         VALUES (?1, ?2, ?3, 1, ?4)",
         params![schema.schema_id, schema.version, schema_json, now],
     )
-    .map_err(|e| format!("failed to register schema: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to register schema"))?;
     Ok(())
 }
 
-pub fn list(conn: &Connection) -> Result<Vec<(String, String, i64, String)>, String> {
+/// Returns `(version, schema_json, is_active)` of the most recently registered version.
+pub fn get_latest(
+    conn: &Connection,
+    schema_id: &str,
+) -> Result<Option<(String, String, i64)>, AppError> {
+    conn.query_row(
+        "SELECT version, schema_json, is_active FROM schema_registry
+         WHERE schema_id = ?1
+         ORDER BY created_at DESC, rowid DESC
+         LIMIT 1",
+        params![schema_id],
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+    )
+    .optional()
+    .map_err(|e| AppError::db(e, "failed to read schema"))
+}
+
+/// Returns `(schema_json, is_active)` for one registered version.
+pub fn get_version(
+    conn: &Connection,
+    schema_id: &str,
+    version: &str,
+) -> Result<Option<(String, i64)>, AppError> {
+    conn.query_row(
+        "SELECT schema_json, is_active FROM schema_registry WHERE schema_id = ?1 AND version = ?2",
+        params![schema_id, version],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| AppError::db(e, "failed to read schema version"))
+}
+
+/// Registered versions of `schema_id`, oldest first.
+pub fn versions(conn: &Connection, schema_id: &str) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT version FROM schema_registry WHERE schema_id = ?1
+             ORDER BY created_at ASC, rowid ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to list schema versions"))?;
+
+    let rows = stmt
+        .query_map(params![schema_id], |r| r.get(0))
+        .map_err(|e| AppError::db(e, "failed to read schema versions"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read schema version row"))?);
+    }
+    Ok(out)
+}
+
+/// `schema_json` of every registered version, oldest first.
+pub fn list_definitions(conn: &Connection) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare("SELECT schema_json FROM schema_registry ORDER BY created_at ASC, rowid ASC")
+        .map_err(|e| AppError::db(e, "failed to list schema definitions"))?;
+    let rows = stmt
+        .query_map([], |r| r.get(0))
+        .map_err(|e| AppError::db(e, "failed to read schema definitions"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed to read schema definition row"))
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<(String, String, i64, String)>, AppError> {
     let mut stmt = conn
-        .prepare("SELECT schema_id, version, is_active, created_at FROM schema_registry ORDER BY created_at DESC")
-        .map_err(|e| format!("failed to list schemas: {e}"))?;
+        .prepare("SELECT schema_id, version, is_active, created_at FROM schema_registry ORDER BY created_at DESC, rowid DESC")
+        .map_err(|e| AppError::db(e, "failed to list schemas"))?;
 
     let rows = stmt
         .query_map([], |r| {
@@ -31,11 +95,11 @@ pub fn list(conn: &Connection) -> Result<Vec<(String, String, i64, String)>, Str
                 r.get::<_, String>(3)?,
             ))
         })
-        .map_err(|e| format!("failed to read schemas: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to read schemas"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| e.to_string())?);
+        out.push(row.map_err(|e| AppError::db(e, "failed to read schemas"))?);
     }
     Ok(out)
 }
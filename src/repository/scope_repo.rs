@@ -1,16 +1,17 @@
-use rusqlite::{params, Connection};
+use crate::error::AppError;
+use rusqlite::{params, Connection, OptionalExtension};
 
 pub fn insert_scope(
     conn: &Connection,
     scope_id: &str,
     scope_type: &str,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO scopes (scope_id, scope_type, created_at) VALUES (?1, ?2, ?3)",
         params![scope_id, scope_type, now],
     )
-    .map_err(|e| format!("failed to create scope: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to create scope"))?;
     Ok(())
 }
 
@@ -20,19 +21,68 @@ pub fn insert_member(
     uid: &str,
     role: &str,
     now: &str,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES (?1, ?2, ?3, ?4)",
         params![scope_id, uid, role, now],
     )
-    .map_err(|e| format!("failed to add member: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to add member"))?;
     Ok(())
 }
 
-pub fn list_scopes(conn: &Connection) -> Result<Vec<(String, String)>, String> {
+pub fn exists(conn: &Connection, scope_id: &str) -> Result<bool, AppError> {
+    let n: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM scopes WHERE scope_id = ?1",
+            params![scope_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| AppError::db(e, "failed to query scope existence"))?;
+    Ok(n > 0)
+}
+
+pub fn get_member_role(
+    conn: &Connection,
+    scope_id: &str,
+    uid: &str,
+) -> Result<Option<String>, AppError> {
+    conn.prepare_cached("SELECT role FROM scope_members WHERE scope_id = ?1 AND uid = ?2")
+        .map_err(|e| AppError::db(e, "failed to prepare scope membership query"))?
+        .query_row(params![scope_id, uid], |row| row.get(0))
+        .optional()
+        .map_err(|e| AppError::db(e, "failed to query scope membership"))
+}
+
+/// Scopes `uid` belongs to in default read order: private, then shared, then global.
+pub fn list_member_scopes_in_read_order(
+    conn: &Connection,
+    uid: &str,
+) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT s.scope_id FROM scope_members m
+             JOIN scopes s ON s.scope_id = m.scope_id
+             WHERE m.uid = ?1
+             ORDER BY CASE s.scope_type WHEN 'private' THEN 0 WHEN 'global' THEN 2 ELSE 1 END,
+                      m.added_at ASC, s.scope_id ASC",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare member scopes"))?;
+
+    let rows = stmt
+        .query_map(params![uid], |row| row.get(0))
+        .map_err(|e| AppError::db(e, "failed to list member scopes"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed to read member scope row"))?);
+    }
+    Ok(out)
+}
+
+pub fn list_scopes(conn: &Connection) -> Result<Vec<(String, String)>, AppError> {
     let mut stmt = conn
         .prepare("SELECT scope_id, scope_type FROM scopes ORDER BY created_at DESC")
-        .map_err(|e| format!("failed to prepare scope list: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare scope list"))?;
 
     let rows = stmt
         .query_map([], |row| {
@@ -40,19 +90,19 @@ pub fn list_scopes(conn: &Connection) -> Result<Vec<(String, String)>, String> {
             let kind: String = row.get(1)?;
             Ok((id, kind))
         })
-        .map_err(|e| format!("failed to list scopes: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to list scopes"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed to read scope row: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed to read scope row"))?);
     }
     Ok(out)
 }
 
-pub fn list_members(conn: &Connection, scope_id: &str) -> Result<Vec<(String, String)>, String> {
+pub fn list_members(conn: &Connection, scope_id: &str) -> Result<Vec<(String, String)>, AppError> {
     let mut stmt = conn
         .prepare("SELECT uid, role FROM scope_members WHERE scope_id = ?1 ORDER BY added_at DESC")
-        .map_err(|e| format!("failed to prepare scope members: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare scope members"))?;
 
     let rows = stmt
         .query_map(params![scope_id], |row| {
@@ -60,11 +110,11 @@ pub fn list_members(conn: &Connection, scope_id: &str) -> Result<Vec<(String, St
             let role: String = row.get(1)?;
             Ok((uid, role))
         })
-        .map_err(|e| format!("failed to list scope members: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to list scope members"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed to read scope member row: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed to read scope member row"))?);
     }
     Ok(out)
 }
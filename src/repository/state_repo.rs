@@ -0,0 +1,44 @@
+use crate::error::AppError;
+use rusqlite::{params, Connection, OptionalExtension};
+
+pub fn get(
+    conn: &Connection,
+    scope_id: &str,
+    uid: &str,
+    key: &str,
+) -> Result<Option<(String, String)>, AppError> {
+    conn.prepare_cached(
+        "SELECT value_json, updated_at FROM state WHERE scope_id = ?1 AND uid = ?2 AND state_key = ?3",
+    )
+    .map_err(|e| AppError::db(e, "failed to prepare state query"))?
+    .query_row(params![scope_id, uid, key], |row| Ok((row.get(0)?, row.get(1)?)))
+    .optional()
+    .map_err(|e| AppError::db(e, "failed to query state"))
+}
+
+pub fn upsert(
+    conn: &Connection,
+    scope_id: &str,
+    uid: &str,
+    key: &str,
+    value_json: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO state (scope_id, uid, state_key, value_json, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(scope_id, uid, state_key)
+         DO UPDATE SET value_json = excluded.value_json, updated_at = excluded.updated_at",
+        params![scope_id, uid, key, value_json, now],
+    )
+    .map_err(|e| AppError::db(e, "failed to set state"))?;
+    Ok(())
+}
+
+pub fn delete(conn: &Connection, scope_id: &str, uid: &str, key: &str) -> Result<usize, AppError> {
+    conn.execute(
+        "DELETE FROM state WHERE scope_id = ?1 AND uid = ?2 AND state_key = ?3",
+        params![scope_id, uid, key],
+    )
+    .map_err(|e| AppError::db(e, "failed to delete state"))
+}
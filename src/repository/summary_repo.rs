@@ -0,0 +1,201 @@
+use crate::error::AppError;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+pub struct SummaryKey<'a> {
+    pub period: &'a str,
+    pub period_start: &'a str,
+    pub scope_id: &'a str,
+    pub uid: &'a str,
+    pub event_type: &'a str,
+    pub topic: &'a str,
+}
+
+pub struct SummaryRow {
+    pub period_start: String,
+    pub event_type: String,
+    pub topic: String,
+    pub event_count: i64,
+    pub value_sum: f64,
+    pub distinct_items: i64,
+    pub items_json: String,
+}
+
+/// A full `event_summaries` row.
+pub struct StoredSummary {
+    pub period: String,
+    pub period_start: String,
+    pub scope_id: String,
+    pub event_type: String,
+    pub topic: String,
+    pub event_count: i64,
+    pub value_sum: f64,
+    pub items_json: String,
+}
+
+pub fn get_totals(
+    tx: &Transaction<'_>,
+    key: &SummaryKey<'_>,
+) -> Result<Option<(i64, f64, String)>, AppError> {
+    tx.query_row(
+        "SELECT event_count, value_sum, items_json FROM event_summaries
+         WHERE period = ?1 AND period_start = ?2 AND scope_id = ?3 AND uid = ?4
+           AND event_type = ?5 AND topic = ?6",
+        params![
+            key.period,
+            key.period_start,
+            key.scope_id,
+            key.uid,
+            key.event_type,
+            key.topic
+        ],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()
+    .map_err(|e| AppError::db(e, "failed to query summary"))
+}
+
+pub fn upsert(
+    tx: &Transaction<'_>,
+    key: &SummaryKey<'_>,
+    event_count: i64,
+    value_sum: f64,
+    distinct_items: i64,
+    items_json: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    tx.execute(
+        "INSERT INTO event_summaries (period, period_start, scope_id, uid, event_type, topic,
+            event_count, value_sum, distinct_items, items_json, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+         ON CONFLICT(period, period_start, scope_id, uid, event_type, topic) DO UPDATE SET
+           event_count = excluded.event_count,
+           value_sum = excluded.value_sum,
+           distinct_items = excluded.distinct_items,
+           items_json = excluded.items_json,
+           updated_at = excluded.updated_at",
+        params![
+            key.period,
+            key.period_start,
+            key.scope_id,
+            key.uid,
+            key.event_type,
+            key.topic,
+            event_count,
+            value_sum,
+            distinct_items,
+            items_json,
+            now
+        ],
+    )
+    .map_err(|e| AppError::db(e, "failed to upsert summary"))?;
+    Ok(())
+}
+
+pub fn delete(tx: &Transaction<'_>, key: &SummaryKey<'_>) -> Result<(), AppError> {
+    tx.execute(
+        "DELETE FROM event_summaries
+         WHERE period = ?1 AND period_start = ?2 AND scope_id = ?3 AND uid = ?4
+           AND event_type = ?5 AND topic = ?6",
+        params![
+            key.period,
+            key.period_start,
+            key.scope_id,
+            key.uid,
+            key.event_type,
+            key.topic
+        ],
+    )
+    .map_err(|e| AppError::db(e, "failed to delete summary"))?;
+    Ok(())
+}
+
+pub fn list_for_uid(tx: &Transaction<'_>, uid: &str) -> Result<Vec<StoredSummary>, AppError> {
+    let mut stmt = tx
+        .prepare(
+            "SELECT period, period_start, scope_id, event_type, topic, event_count, value_sum, items_json
+             FROM event_summaries WHERE uid = ?1
+             ORDER BY period, period_start, scope_id, event_type, topic",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare summary list"))?;
+    let rows = stmt
+        .query_map(params![uid], |row| {
+            Ok(StoredSummary {
+                period: row.get(0)?,
+                period_start: row.get(1)?,
+                scope_id: row.get(2)?,
+                event_type: row.get(3)?,
+                topic: row.get(4)?,
+                event_count: row.get(5)?,
+                value_sum: row.get(6)?,
+                items_json: row.get(7)?,
+            })
+        })
+        .map_err(|e| AppError::db(e, "failed to list summaries"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::db(e, "failed row read"))
+}
+
+pub fn mark_compacted(tx: &Transaction<'_>, event_id: &str, now: &str) -> Result<(), AppError> {
+    tx.execute(
+        "INSERT OR IGNORE INTO compaction_ledger (event_id, compacted_at) VALUES (?1, ?2)",
+        params![event_id, now],
+    )
+    .map_err(|e| AppError::db(e, "failed to record compaction"))?;
+    Ok(())
+}
+
+/// Removes `event_id` from `compaction_ledger`; true when it had been compacted.
+pub fn unmark_compacted(tx: &Transaction<'_>, event_id: &str) -> Result<bool, AppError> {
+    let n = tx
+        .execute(
+            "DELETE FROM compaction_ledger WHERE event_id = ?1",
+            params![event_id],
+        )
+        .map_err(|e| AppError::db(e, "failed to update compaction ledger"))?;
+    Ok(n > 0)
+}
+
+pub fn query(
+    conn: &Connection,
+    scope_id: &str,
+    uid: &str,
+    period: &str,
+    event_type: Option<&str>,
+    topic: Option<&str>,
+    limit: usize,
+) -> Result<Vec<SummaryRow>, AppError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT period_start, event_type, topic, event_count, value_sum, distinct_items, items_json
+             FROM event_summaries
+             WHERE scope_id = ?1 AND uid = ?2 AND period = ?3
+               AND (?4 IS NULL OR event_type = ?4)
+               AND (?5 IS NULL OR topic = ?5)
+             ORDER BY period_start DESC, event_type ASC, topic ASC
+             LIMIT ?6",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare summary query"))?;
+
+    let rows = stmt
+        .query_map(
+            params![scope_id, uid, period, event_type, topic, limit as i64],
+            |row| {
+                Ok(SummaryRow {
+                    period_start: row.get(0)?,
+                    event_type: row.get(1)?,
+                    topic: row.get(2)?,
+                    event_count: row.get(3)?,
+                    value_sum: row.get(4)?,
+                    distinct_items: row.get(5)?,
+                    items_json: row.get(6)?,
+                })
+            },
+        )
+        .map_err(|e| AppError::db(e, "failed summary query"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed row read"))?);
+    }
+    Ok(out)
+}
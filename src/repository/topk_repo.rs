@@ -1,3 +1,4 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection, Transaction};
 
 pub struct TopkRow<'a> {
@@ -10,16 +11,48 @@ pub struct TopkRow<'a> {
     pub now: &'a str,
 }
 
-pub fn clear(tx: &Transaction<'_>, scope_id: &str, uid: &str, topic: &str) -> Result<(), String> {
+pub fn clear(tx: &Transaction<'_>, scope_id: &str, uid: &str, topic: &str) -> Result<(), AppError> {
     tx.execute(
         "DELETE FROM topk WHERE scope_id = ?1 AND uid = ?2 AND topic = ?3",
         params![scope_id, uid, topic],
     )
-    .map_err(|e| format!("failed to clear topk: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to clear topk"))?;
     Ok(())
 }
 
-pub fn insert(tx: &Transaction<'_>, row: TopkRow<'_>) -> Result<(), String> {
+pub fn clear_filtered(
+    tx: &Transaction<'_>,
+    uid: Option<&str>,
+    scope_id: Option<&str>,
+    topic: Option<&str>,
+) -> Result<usize, AppError> {
+    tx.execute(
+        "DELETE FROM topk
+         WHERE (?1 IS NULL OR uid = ?1) AND (?2 IS NULL OR scope_id = ?2) AND (?3 IS NULL OR topic = ?3)",
+        params![uid, scope_id, topic],
+    )
+    .map_err(|e| AppError::db(e, "failed to clear topk"))
+}
+
+/// Distinct `(scope_id, topic)` pairs holding ranked rows for `uid`.
+pub fn groups_for_uid(tx: &Transaction<'_>, uid: &str) -> Result<Vec<(String, String)>, AppError> {
+    let mut stmt = tx
+        .prepare(
+            "SELECT DISTINCT scope_id, topic FROM topk WHERE uid = ?1 ORDER BY scope_id, topic",
+        )
+        .map_err(|e| AppError::db(e, "failed to prepare topk group query"))?;
+    let rows = stmt
+        .query_map(params![uid], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| AppError::db(e, "failed topk group query"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.map_err(|e| AppError::db(e, "failed row read"))?);
+    }
+    Ok(out)
+}
+
+pub fn insert(tx: &Transaction<'_>, row: TopkRow<'_>) -> Result<(), AppError> {
     tx.execute(
         "INSERT INTO topk (scope_id, uid, topic, rank, item_key, weight, updated_at)
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
@@ -33,7 +66,7 @@ pub fn insert(tx: &Transaction<'_>, row: TopkRow<'_>) -> Result<(), String> {
             row.now
         ],
     )
-    .map_err(|e| format!("failed to insert topk: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to insert topk"))?;
     Ok(())
 }
 
@@ -43,15 +76,15 @@ pub fn query(
     uid: &str,
     topic: &str,
     limit: usize,
-) -> Result<Vec<(i64, String, f64)>, String> {
+) -> Result<Vec<(i64, String, f64)>, AppError> {
     let mut stmt = conn
-        .prepare(
+        .prepare_cached(
             "SELECT rank, item_key, weight FROM topk
              WHERE scope_id = ?1 AND uid = ?2 AND topic = ?3
              ORDER BY rank ASC
              LIMIT ?4",
         )
-        .map_err(|e| format!("failed to prepare topk query: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare topk query"))?;
 
     let rows = stmt
         .query_map(params![scope_id, uid, topic, limit as i64], |row| {
@@ -60,11 +93,11 @@ pub fn query(
             let weight: f64 = row.get(2)?;
             Ok((rank, item, weight))
         })
-        .map_err(|e| format!("failed topk query: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed topk query"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed row read: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed row read"))?);
     }
     Ok(out)
 }
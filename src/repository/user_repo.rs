@@ -1,18 +1,19 @@
+use crate::error::AppError;
 use rusqlite::{params, Connection, OptionalExtension};
 
-pub fn insert(conn: &Connection, uid: &str, name: &str, now: &str) -> Result<(), String> {
+pub fn insert(conn: &Connection, uid: &str, name: &str, now: &str) -> Result<(), AppError> {
     conn.execute(
         "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES (?1, ?2, 'active', ?3, ?3)",
         params![uid, name, now],
     )
-    .map_err(|e| format!("failed to create user: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to create user"))?;
     Ok(())
 }
 
-pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, String> {
+pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, AppError> {
     let mut stmt = conn
         .prepare("SELECT uid, display_name FROM users ORDER BY created_at DESC")
-        .map_err(|e| format!("failed to prepare user list: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to prepare user list"))?;
 
     let rows = stmt
         .query_map([], |row| {
@@ -20,88 +21,133 @@ pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, String> {
             let name: String = row.get(1)?;
             Ok((uid, name))
         })
-        .map_err(|e| format!("failed to list users: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to list users"))?;
 
     let mut out = Vec::new();
     for row in rows {
-        out.push(row.map_err(|e| format!("failed to read user row: {e}"))?);
+        out.push(row.map_err(|e| AppError::db(e, "failed to read user row"))?);
     }
     Ok(out)
 }
 
-pub fn get_name(conn: &Connection, uid: &str) -> Result<Option<String>, String> {
+pub fn get_name(conn: &Connection, uid: &str) -> Result<Option<String>, AppError> {
     conn.query_row(
         "SELECT display_name FROM users WHERE uid = ?1",
         params![uid],
         |row| row.get(0),
     )
     .optional()
-    .map_err(|e| format!("failed to query user: {e}"))
+    .map_err(|e| AppError::db(e, "failed to query user"))
 }
 
-pub fn update_name(conn: &Connection, uid: &str, name: &str, now: &str) -> Result<usize, String> {
+pub fn update_name(conn: &Connection, uid: &str, name: &str, now: &str) -> Result<usize, AppError> {
     conn.execute(
         "UPDATE users SET display_name = ?1, updated_at = ?2 WHERE uid = ?3",
         params![name, now, uid],
     )
-    .map_err(|e| format!("failed to update user: {e}"))
+    .map_err(|e| AppError::db(e, "failed to update user"))
 }
 
-pub fn exists(conn: &Connection, uid: &str) -> Result<bool, String> {
+pub fn exists(conn: &Connection, uid: &str) -> Result<bool, AppError> {
     let n: i64 = conn
         .query_row(
             "SELECT COUNT(1) FROM users WHERE uid = ?1",
             params![uid],
             |row| row.get(0),
         )
-        .map_err(|e| format!("failed to query user existence: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to query user existence"))?;
     Ok(n > 0)
 }
 
-pub fn get_status(conn: &Connection, uid: &str) -> Result<Option<String>, String> {
-    conn.query_row(
-        "SELECT status FROM users WHERE uid = ?1",
-        params![uid],
-        |row| row.get(0),
-    )
-    .optional()
-    .map_err(|e| format!("failed to query user status: {e}"))
-}
-
-pub fn set_status(conn: &Connection, uid: &str, status: &str, now: &str) -> Result<usize, String> {
+pub fn set_status(
+    conn: &Connection,
+    uid: &str,
+    status: &str,
+    now: &str,
+) -> Result<usize, AppError> {
     conn.execute(
         "UPDATE users SET status = ?1, updated_at = ?2 WHERE uid = ?3",
         params![status, now, uid],
     )
-    .map_err(|e| format!("failed to update user status: {e}"))
+    .map_err(|e| AppError::db(e, "failed to update user status"))
 }
 
-fn count_by_uid(conn: &Connection, table: &str, uid: &str) -> Result<i64, String> {
-    let sql = format!("SELECT COUNT(1) FROM {table} WHERE uid = ?1");
-    conn.query_row(&sql, params![uid], |row| row.get(0))
-        .map_err(|e| format!("failed to count rows in {table}: {e}"))
+/// Tables that reference a user through a `uid` column, besides `users` itself.
+pub const UID_TABLES: [&str; 6] = [
+    "user_identities",
+    "scope_members",
+    "events",
+    "state",
+    "metrics",
+    "topk",
+];
+
+pub fn count_by_uid(conn: &Connection, table: &str, uid: &str) -> Result<i64, AppError> {
+    count_where(conn, table, "uid", uid)
 }
 
-pub fn count_user_identities(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "user_identities", uid)
+/// Rows of `table` whose user-reference `column` equals `uid`.
+pub fn count_where(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    uid: &str,
+) -> Result<i64, AppError> {
+    let sql = format!("SELECT COUNT(1) FROM {table} WHERE {column} = ?1");
+    conn.query_row(&sql, params![uid], |row| row.get(0))
+        .map_err(|e| AppError::db(e, format!("failed to count rows in {table}")))
 }
 
-pub fn count_scope_members(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "scope_members", uid)
+pub fn delete_where(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    uid: &str,
+) -> Result<usize, AppError> {
+    let sql = format!("DELETE FROM {table} WHERE {column} = ?1");
+    conn.execute(&sql, params![uid])
+        .map_err(|e| AppError::db(e, format!("failed to delete rows in {table}")))
 }
 
-pub fn count_events(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "events", uid)
+/// Points rows of `table` referencing `from_uid` through `column` at `to_uid`.
+pub fn relink(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    from_uid: &str,
+    to_uid: &str,
+) -> Result<usize, AppError> {
+    let sql = format!("UPDATE {table} SET {column} = ?1 WHERE {column} = ?2");
+    conn.execute(&sql, params![to_uid, from_uid])
+        .map_err(|e| AppError::db(e, format!("failed to relink rows in {table}")))
 }
 
-pub fn count_state(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "state", uid)
+/// Drops compaction bookkeeping for the user's events; run before the events are deleted.
+pub fn delete_compaction_ledger(conn: &Connection, uid: &str) -> Result<usize, AppError> {
+    conn.execute(
+        "DELETE FROM compaction_ledger
+         WHERE event_id IN (SELECT event_id FROM events WHERE uid = ?1)",
+        params![uid],
+    )
+    .map_err(|e| AppError::db(e, "failed to delete compaction ledger rows"))
 }
 
-pub fn count_metrics(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "metrics", uid)
+pub fn delete(conn: &Connection, uid: &str) -> Result<usize, AppError> {
+    conn.execute("DELETE FROM users WHERE uid = ?1", params![uid])
+        .map_err(|e| AppError::db(e, "failed to delete user"))
 }
 
-pub fn count_topk(conn: &Connection, uid: &str) -> Result<i64, String> {
-    count_by_uid(conn, "topk", uid)
+pub fn insert_tombstone(
+    conn: &Connection,
+    uid: &str,
+    counts_json: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO user_tombstones (uid, counts_json, deleted_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(uid) DO UPDATE SET counts_json = excluded.counts_json, deleted_at = excluded.deleted_at",
+        params![uid, counts_json, now],
+    )
+    .map_err(|e| AppError::db(e, "failed to record user tombstone"))?;
+    Ok(())
 }
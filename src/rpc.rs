@@ -0,0 +1,553 @@
+//! JSON-RPC 2.0 dispatch onto one long-lived [`MemoryStore`]: one request (or batch) per
+//! line in, one response per line out. Methods mirror the CLI command groups.
+
+use crate::domain::schema::{validate_schema_def, SchemaDef};
+use crate::error::AppError;
+use crate::service::identity_service::UserRef;
+use crate::service::query_service::ScopeSelection;
+use crate::store::MemoryStore;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Why a [`call`] failed.
+pub enum RpcError {
+    /// A JSON-RPC protocol failure such as unknown method or malformed params.
+    Protocol(i64, String),
+    /// Application errors use code `-32000 - exit code`; `data` is the CLI error envelope body.
+    App(AppError),
+}
+
+impl From<AppError> for RpcError {
+    fn from(e: AppError) -> Self {
+        RpcError::App(e)
+    }
+}
+
+impl RpcError {
+    /// Protocol failures surface as `Validation` (unknown methods as `NotFound`) for
+    /// transports that speak the CLI error envelope.
+    pub fn into_app_error(self) -> AppError {
+        match self {
+            RpcError::Protocol(METHOD_NOT_FOUND, message) => AppError::not_found(message),
+            RpcError::Protocol(_, message) => AppError::validation(message),
+            RpcError::App(e) => e,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            RpcError::Protocol(code, message) => json!({"code": code, "message": message}),
+            RpcError::App(e) => json!({
+                "code": -32000 - i64::from(e.kind().exit_code()),
+                "message": e.message(),
+                "data": e.to_json()["error"],
+            }),
+        }
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => json!({"jsonrpc": "2.0", "id": id, "error": e.to_json()}),
+    }
+}
+
+/// Handles one input line; `None` when nothing is owed back (only notifications).
+pub fn handle_line(store: &mut MemoryStore, line: &str) -> Option<Value> {
+    let parsed: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            return Some(response(
+                Value::Null,
+                Err(RpcError::Protocol(PARSE_ERROR, format!("parse error: {e}"))),
+            ))
+        }
+    };
+    match parsed {
+        Value::Array(requests) if !requests.is_empty() => {
+            let responses: Vec<Value> = requests
+                .into_iter()
+                .filter_map(|r| handle_request(store, r))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => handle_request(store, request),
+    }
+}
+
+fn handle_request(store: &mut MemoryStore, request: Value) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let method = match (request.get("jsonrpc"), request.get("method")) {
+        (Some(version), Some(Value::String(method))) if version == "2.0" => method.clone(),
+        _ => {
+            return Some(response(
+                id.unwrap_or(Value::Null),
+                Err(RpcError::Protocol(
+                    INVALID_REQUEST,
+                    "invalid request: expected jsonrpc \"2.0\" and a method".to_string(),
+                )),
+            ))
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+    let result = call(store, &method, params);
+    id.map(|id| response(id, result))
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::Protocol(INVALID_PARAMS, format!("invalid params: {e}")))
+}
+
+#[derive(Deserialize)]
+struct UserParams {
+    uid: Option<String>,
+    channel: Option<String>,
+    channel_user_id: Option<String>,
+}
+
+impl UserParams {
+    fn to_ref(&self) -> Result<UserRef, RpcError> {
+        UserRef::from_parts(
+            self.uid.clone(),
+            self.channel.clone(),
+            self.channel_user_id.clone(),
+        )
+        .map_err(|e| RpcError::Protocol(INVALID_PARAMS, format!("invalid params: {e}")))
+    }
+}
+
+/// Exactly one of `scope_id`, `scopes` or `read_order: "auto"`, like the CLI flags.
+#[derive(Deserialize)]
+struct ReadScopeParams {
+    scope_id: Option<String>,
+    scopes: Option<Vec<String>>,
+    read_order: Option<String>,
+}
+
+impl ReadScopeParams {
+    fn to_selection(&self) -> Result<ScopeSelection, RpcError> {
+        match (&self.scope_id, &self.scopes, self.read_order.as_deref()) {
+            (Some(scope_id), None, None) => Ok(ScopeSelection::Single(scope_id.clone())),
+            (None, Some(scopes), None) => Ok(ScopeSelection::List(scopes.clone())),
+            (None, None, Some("auto")) => Ok(ScopeSelection::Auto),
+            _ => Err(RpcError::Protocol(
+                INVALID_PARAMS,
+                "invalid params: requires one of scope_id, scopes or read_order=auto".to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UidParams {
+    uid: String,
+}
+
+#[derive(Deserialize)]
+struct NameParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct UserNameParams {
+    uid: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MergeParams {
+    from_uid: String,
+    to_uid: String,
+}
+
+fn default_delete_mode() -> String {
+    "soft".to_string()
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    uid: String,
+    #[serde(default = "default_delete_mode")]
+    mode: String,
+    #[serde(default)]
+    force: bool,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct LinkParams {
+    uid: String,
+    channel: String,
+    channel_user_id: String,
+}
+
+#[derive(Deserialize)]
+struct IdentityParams {
+    channel: String,
+    channel_user_id: String,
+}
+
+#[derive(Deserialize)]
+struct CreateScopeParams {
+    scope_id: String,
+    scope_type: String,
+}
+
+#[derive(Deserialize)]
+struct ScopeParams {
+    scope_id: String,
+}
+
+fn default_role() -> String {
+    "member".to_string()
+}
+
+#[derive(Deserialize)]
+struct MemberParams {
+    scope_id: String,
+    uid: String,
+    #[serde(default = "default_role")]
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct SchemaParams {
+    schema: Value,
+    #[serde(default)]
+    allow_breaking: bool,
+    #[serde(default)]
+    migrate_data: bool,
+}
+
+impl SchemaParams {
+    fn to_def(&self) -> Result<SchemaDef, RpcError> {
+        let def: SchemaDef = serde_json::from_value(self.schema.clone())
+            .map_err(|e| AppError::validation(format!("invalid schema json: {e}")))?;
+        validate_schema_def(&def)?;
+        Ok(def)
+    }
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    schema_id: String,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+fn default_binding_mode() -> String {
+    "strict".to_string()
+}
+
+#[derive(Deserialize)]
+struct BindParams {
+    event_type: String,
+    schema_id: String,
+    #[serde(default = "default_binding_mode")]
+    mode: String,
+}
+
+#[derive(Deserialize)]
+struct EventTypeParams {
+    event_type: String,
+}
+
+#[derive(Deserialize)]
+struct IngestParams {
+    #[serde(flatten)]
+    user: UserParams,
+    scope_id: String,
+    event_type: String,
+    payload: Value,
+    idempotency_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LatestParams {
+    #[serde(flatten)]
+    user: UserParams,
+    #[serde(flatten)]
+    scopes: ReadScopeParams,
+}
+
+#[derive(Deserialize)]
+struct MetricParams {
+    #[serde(flatten)]
+    user: UserParams,
+    #[serde(flatten)]
+    scopes: ReadScopeParams,
+    key: Option<String>,
+    prefix: Option<String>,
+}
+
+fn default_topk_limit() -> usize {
+    3
+}
+
+#[derive(Deserialize)]
+struct TopkParams {
+    #[serde(flatten)]
+    user: UserParams,
+    #[serde(flatten)]
+    scopes: ReadScopeParams,
+    topic: String,
+    #[serde(default = "default_topk_limit")]
+    limit: usize,
+}
+
+fn default_summary_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize)]
+struct SummaryParams {
+    #[serde(flatten)]
+    user: UserParams,
+    scope_id: String,
+    period: String,
+    event_type: Option<String>,
+    topic: Option<String>,
+    #[serde(default = "default_summary_limit")]
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+struct StateParams {
+    #[serde(flatten)]
+    user: UserParams,
+    scope_id: String,
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct SetStateParams {
+    #[serde(flatten)]
+    user: UserParams,
+    scope_id: String,
+    key: String,
+    value: Value,
+}
+
+/// Runs one method against `store`; shared by the line protocol and the HTTP routes.
+pub fn call(store: &mut MemoryStore, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "user.create" => {
+            let p: NameParams = parse(params)?;
+            Ok(json!(store.create_user(&p.name)?))
+        }
+        "user.list" => Ok(json!(store.list_users()?)),
+        "user.show" => {
+            let p: UidParams = parse(params)?;
+            let user = store
+                .get_user(&p.uid)?
+                .ok_or_else(|| AppError::not_found(format!("user not found: {}", p.uid)))?;
+            Ok(json!(user))
+        }
+        "user.update" => {
+            let p: UserNameParams = parse(params)?;
+            Ok(json!(store.update_user(&p.uid, &p.name)?))
+        }
+        "user.merge" => {
+            let p: MergeParams = parse(params)?;
+            store.merge_users(&p.from_uid, &p.to_uid)?;
+            Ok(json!({"from_uid": p.from_uid, "to_uid": p.to_uid}))
+        }
+        "user.delete" => {
+            let p: DeleteParams = parse(params)?;
+            let counts = if p.dry_run {
+                Some(store.user_ref_counts(&p.uid)?)
+            } else {
+                match p.mode.as_str() {
+                    "soft" => {
+                        store.delete_user_soft(&p.uid)?;
+                        None
+                    }
+                    "hard" => Some(store.delete_user_hard(&p.uid, p.force)?),
+                    _ => {
+                        return Err(AppError::validation("invalid mode. expected: soft|hard").into())
+                    }
+                }
+            };
+            Ok(json!({"uid": p.uid, "mode": p.mode, "dry_run": p.dry_run, "counts": counts}))
+        }
+        "user.export" => {
+            let p: UidParams = parse(params)?;
+            Ok(store.export_user(&p.uid)?)
+        }
+        "identity.link" => {
+            let p: LinkParams = parse(params)?;
+            Ok(json!(store.link_identity(
+                &p.uid,
+                &p.channel,
+                &p.channel_user_id
+            )?))
+        }
+        "identity.resolve" => {
+            let p: IdentityParams = parse(params)?;
+            let identity = store
+                .resolve_identity(&p.channel, &p.channel_user_id)?
+                .ok_or_else(|| {
+                    AppError::not_found(format!(
+                        "identity not found: {}:{}",
+                        p.channel, p.channel_user_id
+                    ))
+                })?;
+            Ok(json!(identity))
+        }
+        "identity.unlink" => {
+            let p: IdentityParams = parse(params)?;
+            store.unlink_identity(&p.channel, &p.channel_user_id)?;
+            Ok(json!({"channel": p.channel, "channel_user_id": p.channel_user_id}))
+        }
+        "scope.create" => {
+            let p: CreateScopeParams = parse(params)?;
+            Ok(json!(store.create_scope(&p.scope_id, &p.scope_type)?))
+        }
+        "scope.add_member" => {
+            let p: MemberParams = parse(params)?;
+            Ok(json!(store.add_scope_member(
+                &p.scope_id,
+                &p.uid,
+                &p.role
+            )?))
+        }
+        "scope.list" => Ok(json!(store.list_scopes()?)),
+        "scope.members" => {
+            let p: ScopeParams = parse(params)?;
+            Ok(json!(store.scope_members(&p.scope_id)?))
+        }
+        "schema.validate" => {
+            let p: SchemaParams = parse(params)?;
+            let def = p.to_def()?;
+            Ok(json!({"schema_id": def.schema_id, "version": def.version, "valid": true}))
+        }
+        "schema.register" => {
+            let p: SchemaParams = parse(params)?;
+            let def = p.to_def()?;
+            let report = store.register_schema(
+                &def,
+                &p.schema.to_string(),
+                p.allow_breaking,
+                p.migrate_data,
+            )?;
+            Ok(json!({
+                "schema_id": def.schema_id,
+                "version": def.version,
+                "table": report.table,
+                "created": report.created,
+                "previous_version": report.previous_version,
+                "changes": report.changes,
+                "rows_copied": report.rows_copied,
+            }))
+        }
+        "schema.list" => Ok(json!(store.list_schemas()?)),
+        "schema.diff" => {
+            let p: DiffParams = parse(params)?;
+            let diff = store.diff_schema(&p.schema_id, p.from.as_deref(), p.to.as_deref())?;
+            let mut doc = json!(diff);
+            doc["compatible"] = json!(diff.is_compatible());
+            Ok(doc)
+        }
+        "schema.bind" => {
+            let p: BindParams = parse(params)?;
+            Ok(json!(store.bind_schema(
+                &p.event_type,
+                &p.schema_id,
+                &p.mode
+            )?))
+        }
+        "schema.unbind" => {
+            let p: EventTypeParams = parse(params)?;
+            store.unbind_schema(&p.event_type)?;
+            Ok(json!({"event_type": p.event_type}))
+        }
+        "schema.bindings" => Ok(json!(store.schema_bindings()?)),
+        "ingest.event" => {
+            let p: IngestParams = parse(params)?;
+            let outcome = store.ingest_event(
+                &p.user.to_ref()?,
+                &p.scope_id,
+                &p.event_type,
+                &p.payload,
+                p.idempotency_key.as_deref(),
+            )?;
+            Ok(json!(outcome))
+        }
+        "query.latest" => {
+            let p: LatestParams = parse(params)?;
+            Ok(json!(store.latest_event(
+                &p.user.to_ref()?,
+                &p.scopes.to_selection()?
+            )?))
+        }
+        "query.metric" => {
+            let p: MetricParams = parse(params)?;
+            if p.key.is_none() && p.prefix.is_none() {
+                return Err(
+                    AppError::validation("query.metric requires either key or prefix").into(),
+                );
+            }
+            Ok(json!(store.metrics(
+                &p.user.to_ref()?,
+                &p.scopes.to_selection()?,
+                p.key.as_deref(),
+                p.prefix.as_deref()
+            )?))
+        }
+        "query.topk" => {
+            let p: TopkParams = parse(params)?;
+            Ok(json!(store.topk(
+                &p.user.to_ref()?,
+                &p.scopes.to_selection()?,
+                &p.topic,
+                p.limit
+            )?))
+        }
+        "query.summary" => {
+            let p: SummaryParams = parse(params)?;
+            Ok(json!(store.summaries(
+                &p.user.to_ref()?,
+                &p.scope_id,
+                &p.period,
+                p.event_type.as_deref(),
+                p.topic.as_deref(),
+                p.limit
+            )?))
+        }
+        "state.get" => {
+            let p: StateParams = parse(params)?;
+            Ok(json!(store.get_state(
+                &p.user.to_ref()?,
+                &p.scope_id,
+                &p.key
+            )?))
+        }
+        "state.set" => {
+            let p: SetStateParams = parse(params)?;
+            Ok(json!(store.set_state(
+                &p.user.to_ref()?,
+                &p.scope_id,
+                &p.key,
+                &p.value
+            )?))
+        }
+        "state.delete" => {
+            let p: StateParams = parse(params)?;
+            let uid = store.delete_state(&p.user.to_ref()?, &p.scope_id, &p.key)?;
+            Ok(json!({"scope_id": p.scope_id, "uid": uid, "key": p.key}))
+        }
+        _ => Err(RpcError::Protocol(
+            METHOD_NOT_FOUND,
+            format!("method not found: {method}"),
+        )),
+    }
+}
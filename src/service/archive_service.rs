@@ -0,0 +1,347 @@
+use crate::domain::period::{month_bounds, month_of};
+use crate::error::AppError;
+use crate::repository::archive_manifest_repo::{self, ManifestEntry};
+use crate::repository::event_repo::{self, EventRow};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub struct ArchiveReport {
+    pub entries: Vec<ManifestEntry>,
+    /// Events left in place because their `event_ts` is not a unix timestamp.
+    pub rows_skipped: usize,
+}
+
+pub struct RestoreReport {
+    pub archives: usize,
+    pub rows_restored: usize,
+    pub rows_skipped: usize,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn encode_month(rows: &[EventRow]) -> Result<Vec<u8>, AppError> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    for row in rows {
+        let line = serde_json::to_string(row)
+            .map_err(|e| AppError::storage(format!("failed to encode event: {e}")))?;
+        gz.write_all(line.as_bytes())
+            .and_then(|_| gz.write_all(b"\n"))
+            .map_err(|e| AppError::io(e, "failed to compress archive"))?;
+    }
+    gz.finish()
+        .map_err(|e| AppError::io(e, "failed to compress archive"))
+}
+
+/// Moves every event before the end of `through_month` out of `events` into one gzip NDJSON
+/// file per calendar month under `dir`, recording each file in `archive_manifest`. Events
+/// whose `event_ts` is not an integer have no month; they stay in `events` and are counted.
+pub fn archive_through(
+    conn: &mut Connection,
+    through_month: &str,
+    dir: &Path,
+    archive_id_prefix: &str,
+    now: &str,
+) -> Result<ArchiveReport, AppError> {
+    let (_, end) = month_bounds(through_month)?;
+
+    let mut by_month: BTreeMap<String, Vec<EventRow>> = BTreeMap::new();
+    let mut rows_skipped = 0;
+    for row in event_repo::list_rows_before(conn, end)? {
+        match row.event_ts.parse::<i64>() {
+            Ok(ts) => by_month.entry(month_of(ts)).or_default().push(row),
+            Err(_) => rows_skipped += 1,
+        }
+    }
+    if by_month.is_empty() {
+        return Ok(ArchiveReport {
+            entries: Vec::new(),
+            rows_skipped,
+        });
+    }
+
+    fs::create_dir_all(dir).map_err(|e| {
+        AppError::io(
+            e,
+            format!("failed to create archive directory {}", dir.display()),
+        )
+    })?;
+
+    let mut written = Vec::new();
+    let result = write_and_commit(conn, &by_month, dir, archive_id_prefix, now, &mut written);
+    if result.is_err() {
+        for path in written {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result.map(|entries| ArchiveReport {
+        entries,
+        rows_skipped,
+    })
+}
+
+fn write_and_commit(
+    conn: &mut Connection,
+    by_month: &BTreeMap<String, Vec<EventRow>>,
+    dir: &Path,
+    archive_id_prefix: &str,
+    now: &str,
+    written: &mut Vec<PathBuf>,
+) -> Result<Vec<ManifestEntry>, AppError> {
+    let mut entries = Vec::new();
+    for (month, rows) in by_month {
+        let archive_id = format!("{archive_id_prefix}_{month}");
+        let path = dir.join(format!("events-{month}-{archive_id}.ndjson.gz"));
+        let bytes = encode_month(rows)?;
+        fs::write(&path, &bytes)
+            .map_err(|e| AppError::io(e, format!("failed to write archive {}", path.display())))?;
+        written.push(path.clone());
+        entries.push(ManifestEntry {
+            archive_id,
+            month: month.clone(),
+            file_path: path.to_string_lossy().to_string(),
+            row_count: rows.len() as i64,
+            min_event_ts: rows[0].event_ts.clone(),
+            max_event_ts: rows[rows.len() - 1].event_ts.clone(),
+            sha256: sha256_hex(&bytes),
+        });
+    }
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    for entry in &entries {
+        archive_manifest_repo::insert(&tx, entry, now)?;
+    }
+    for row in by_month.values().flatten() {
+        event_repo::delete_by_id(&tx, &row.event_id)?;
+    }
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit archive"))?;
+    Ok(entries)
+}
+
+fn decode_archive(entry: &ManifestEntry) -> Result<Vec<EventRow>, AppError> {
+    let bytes = fs::read(&entry.file_path)
+        .map_err(|e| AppError::io(e, format!("failed to read archive {}", entry.file_path)))?;
+    let actual = sha256_hex(&bytes);
+    if actual != entry.sha256 {
+        return Err(AppError::storage(format!(
+            "archive checksum mismatch for {}: expected {} got {actual}",
+            entry.file_path, entry.sha256
+        )));
+    }
+
+    let mut rows = Vec::new();
+    for line in BufReader::new(GzDecoder::new(bytes.as_slice())).lines() {
+        let line =
+            line.map_err(|e| AppError::io(e, format!("failed to decompress {}", entry.file_path)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(serde_json::from_str(&line).map_err(|e| {
+            AppError::storage(format!("corrupt archive line in {}: {e}", entry.file_path))
+        })?);
+    }
+    if rows.len() as i64 != entry.row_count {
+        return Err(AppError::storage(format!(
+            "archive row count mismatch for {}: expected {} got {}",
+            entry.file_path,
+            entry.row_count,
+            rows.len()
+        )));
+    }
+    Ok(rows)
+}
+
+/// Events that live only in archive files: the rows of every archive not yet restored.
+pub fn unrestored_events(conn: &Connection) -> Result<Vec<EventRow>, AppError> {
+    let mut rows = Vec::new();
+    for entry in archive_manifest_repo::list_all_unrestored(conn)? {
+        rows.extend(decode_archive(&entry)?);
+    }
+    Ok(rows)
+}
+
+/// Restores every not-yet-restored archive of `month` back into `events` after verifying
+/// its checksum and row count.
+pub fn restore(conn: &mut Connection, month: &str, now: &str) -> Result<RestoreReport, AppError> {
+    month_bounds(month)?;
+    let entries = archive_manifest_repo::list_unrestored(conn, month)?;
+    if entries.is_empty() {
+        return Err(AppError::validation(format!(
+            "no archived events found for month {month}"
+        )));
+    }
+
+    let decoded = entries
+        .iter()
+        .map(decode_archive)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let mut report = RestoreReport {
+        archives: entries.len(),
+        rows_restored: 0,
+        rows_skipped: 0,
+    };
+    for (entry, rows) in entries.iter().zip(decoded) {
+        for row in &rows {
+            if event_repo::insert_row(&tx, row)? {
+                report.rows_restored += 1;
+            } else {
+                report.rows_skipped += 1;
+            }
+        }
+        archive_manifest_repo::mark_restored(&tx, &entry.archive_id, now)?;
+    }
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit restore"))?;
+    Ok(report)
+}
+
+/// An archive file rewritten row by row (see [`plan_rewrites`]). The new content waits in a
+/// temporary file until [`apply_rewrites`] swaps it in after the database commit; an
+/// archive left with no rows is removed instead.
+pub struct ArchiveRewrite {
+    pub entry: ManifestEntry,
+    /// Rows the rewrite leaves out, as they were stored.
+    pub dropped: Vec<EventRow>,
+    tmp: Option<PathBuf>,
+}
+
+/// Events of `uid` held in archive files, restored archives included.
+pub fn count_user_events(conn: &Connection, uid: &str) -> Result<usize, AppError> {
+    let mut n = 0;
+    for entry in archive_manifest_repo::list_all(conn)? {
+        n += decode_archive(&entry)?
+            .iter()
+            .filter(|row| row.uid == uid)
+            .count();
+    }
+    Ok(n)
+}
+
+/// Writes a purged copy of every archive holding events of `uid`. Nothing is replaced yet.
+pub fn plan_user_purge(conn: &Connection, uid: &str) -> Result<Vec<ArchiveRewrite>, AppError> {
+    plan_rewrites(conn, &|row| (row.uid != uid).then(|| row.clone()))
+}
+
+/// Writes a copy of every archive, restored ones included, in which `rewrite` changed or
+/// dropped (`None`) at least one row. Nothing is replaced yet.
+pub fn plan_rewrites(
+    conn: &Connection,
+    rewrite: &dyn Fn(&EventRow) -> Option<EventRow>,
+) -> Result<Vec<ArchiveRewrite>, AppError> {
+    let mut rewrites = Vec::new();
+    let result = write_rewrites(conn, rewrite, &mut rewrites);
+    if result.is_err() {
+        discard_rewrites(&rewrites);
+    }
+    result.map(|_| rewrites)
+}
+
+fn write_rewrites(
+    conn: &Connection,
+    rewrite: &dyn Fn(&EventRow) -> Option<EventRow>,
+    rewrites: &mut Vec<ArchiveRewrite>,
+) -> Result<(), AppError> {
+    for entry in archive_manifest_repo::list_all(conn)? {
+        let rows = decode_archive(&entry)?;
+        let mut kept = Vec::with_capacity(rows.len());
+        let mut dropped = Vec::new();
+        let mut changed = false;
+        for row in rows {
+            match rewrite(&row) {
+                Some(new) => {
+                    changed |= new != row;
+                    kept.push(new);
+                }
+                None => dropped.push(row),
+            }
+        }
+        if !changed && dropped.is_empty() {
+            continue;
+        }
+        if kept.is_empty() {
+            rewrites.push(ArchiveRewrite {
+                entry: ManifestEntry {
+                    row_count: 0,
+                    ..entry
+                },
+                dropped,
+                tmp: None,
+            });
+            continue;
+        }
+        let bytes = encode_month(&kept)?;
+        let tmp = PathBuf::from(format!("{}.rewrite", entry.file_path));
+        fs::write(&tmp, &bytes)
+            .map_err(|e| AppError::io(e, format!("failed to write archive {}", tmp.display())))?;
+        rewrites.push(ArchiveRewrite {
+            entry: ManifestEntry {
+                row_count: kept.len() as i64,
+                min_event_ts: kept[0].event_ts.clone(),
+                max_event_ts: kept[kept.len() - 1].event_ts.clone(),
+                sha256: sha256_hex(&bytes),
+                ..entry
+            },
+            dropped,
+            tmp: Some(tmp),
+        });
+    }
+    Ok(())
+}
+
+/// Points `archive_manifest` at the rewritten files; run inside the purge transaction.
+pub fn record_rewrites(conn: &Connection, rewrites: &[ArchiveRewrite]) -> Result<(), AppError> {
+    for rw in rewrites {
+        match rw.tmp {
+            Some(_) => archive_manifest_repo::update_content(conn, &rw.entry)?,
+            None => archive_manifest_repo::delete(conn, &rw.entry.archive_id)?,
+        }
+    }
+    Ok(())
+}
+
+/// Replaces (or removes) the original archive files once the manifest change committed.
+pub fn apply_rewrites(rewrites: &[ArchiveRewrite]) -> Result<(), AppError> {
+    for rw in rewrites {
+        let path = Path::new(&rw.entry.file_path);
+        match &rw.tmp {
+            Some(tmp) => fs::rename(tmp, path).map_err(|e| {
+                AppError::io(e, format!("failed to replace archive {}", path.display()))
+            })?,
+            None => match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(AppError::io(
+                        e,
+                        format!("failed to remove archive {}", path.display()),
+                    ))
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+pub fn discard_rewrites(rewrites: &[ArchiveRewrite]) {
+    for tmp in rewrites.iter().filter_map(|rw| rw.tmp.as_ref()) {
+        let _ = fs::remove_file(tmp);
+    }
+}
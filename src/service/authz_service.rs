@@ -0,0 +1,141 @@
+use crate::error::AppError;
+use crate::repository::{scope_repo, user_repo};
+use rusqlite::Connection;
+use serde_json::json;
+use std::fmt;
+
+pub const ROLES: [&str; 3] = ["owner", "member", "reader"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessError {
+    UserNotFound(String),
+    ScopeNotFound(String),
+    NotMember {
+        uid: String,
+        scope_id: String,
+    },
+    RoleDenied {
+        uid: String,
+        scope_id: String,
+        role: String,
+        access: Access,
+    },
+    Storage(AppError),
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::UserNotFound(uid) => write!(f, "user not found: {uid}"),
+            AccessError::ScopeNotFound(scope_id) => write!(f, "scope not found: {scope_id}"),
+            AccessError::NotMember { uid, scope_id } => {
+                write!(
+                    f,
+                    "access denied: user {uid} is not a member of scope {scope_id}"
+                )
+            }
+            AccessError::RoleDenied {
+                uid,
+                scope_id,
+                role,
+                access,
+            } => write!(
+                f,
+                "access denied: role {role} of user {uid} in scope {scope_id} does not allow {}",
+                access.as_str()
+            ),
+            AccessError::Storage(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<AccessError> for AppError {
+    fn from(e: AccessError) -> Self {
+        let message = e.to_string();
+        match e {
+            AccessError::UserNotFound(uid) => {
+                AppError::not_found(message).with_details(json!({ "uid": uid }))
+            }
+            AccessError::ScopeNotFound(scope_id) => {
+                AppError::not_found(message).with_details(json!({ "scope_id": scope_id }))
+            }
+            AccessError::NotMember { uid, scope_id } => AppError::unauthorized(message)
+                .with_details(json!({ "uid": uid, "scope_id": scope_id })),
+            AccessError::RoleDenied {
+                uid,
+                scope_id,
+                role,
+                access,
+            } => AppError::unauthorized(message).with_details(json!({
+                "uid": uid,
+                "scope_id": scope_id,
+                "role": role,
+                "access": access.as_str(),
+            })),
+            AccessError::Storage(e) => e,
+        }
+    }
+}
+
+pub fn validate_role(role: &str) -> Result<(), AppError> {
+    if ROLES.contains(&role) {
+        Ok(())
+    } else {
+        Err(AppError::validation(format!(
+            "invalid role: {role}. expected: owner|member|reader"
+        )))
+    }
+}
+
+fn role_allows(role: &str, access: Access) -> bool {
+    match access {
+        Access::Read => ROLES.contains(&role),
+        Access::Write => role == "owner" || role == "member",
+    }
+}
+
+/// Checks that `uid` exists, `scope_id` exists and the member's role grants `access`.
+/// Returns the member's role on success.
+pub fn authorize(
+    conn: &Connection,
+    uid: &str,
+    scope_id: &str,
+    access: Access,
+) -> Result<String, AccessError> {
+    if !user_repo::exists(conn, uid).map_err(AccessError::Storage)? {
+        return Err(AccessError::UserNotFound(uid.to_string()));
+    }
+    if !scope_repo::exists(conn, scope_id).map_err(AccessError::Storage)? {
+        return Err(AccessError::ScopeNotFound(scope_id.to_string()));
+    }
+    let role = scope_repo::get_member_role(conn, scope_id, uid)
+        .map_err(AccessError::Storage)?
+        .ok_or_else(|| AccessError::NotMember {
+            uid: uid.to_string(),
+            scope_id: scope_id.to_string(),
+        })?;
+    if !role_allows(&role, access) {
+        return Err(AccessError::RoleDenied {
+            uid: uid.to_string(),
+            scope_id: scope_id.to_string(),
+            role,
+            access,
+        });
+    }
+    Ok(role)
+}
@@ -0,0 +1,337 @@
+use crate::domain::schema::SchemaDef;
+use crate::error::AppError;
+use crate::repository::bundle_repo::{self, ConflictPolicy};
+use crate::repository::dynamic_table_repo;
+use crate::service::archive_service::{self, sha256_hex};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const BUNDLE_FORMAT: &str = "agent-memory-bundle";
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Exported tables in dependency order; dynamic schema tables follow them.
+const CORE_TABLES: [&str; 12] = [
+    "users",
+    "scopes",
+    "user_identities",
+    "scope_members",
+    "schema_registry",
+    "event_schema_bindings",
+    "materializer_rules",
+    "events",
+    "state",
+    "metrics",
+    "topk",
+    "dynamic_records",
+];
+
+/// Bundle entry holding the events of unrestored archives. They are imported into `events`
+/// since the target has no archive files.
+const ARCHIVED_EVENTS: &str = "archived_events";
+
+/// Columns rewritten by uid remapping. `refuserid` is the column of the `refUserId` field
+/// of user_context schema tables.
+const UID_COLUMNS: [&str; 3] = ["uid", "ref_user_id", "refuserid"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleTable {
+    pub table: String,
+    pub file: String,
+    pub rows: usize,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: String,
+    pub format_version: u32,
+    /// Migration version of the exporting database.
+    pub schema_version: i64,
+    pub exported_at: String,
+    pub tables: Vec<BundleTable>,
+}
+
+pub fn parse_policy(raw: &str) -> Result<ConflictPolicy, AppError> {
+    match raw {
+        "skip" => Ok(ConflictPolicy::Skip),
+        "overwrite" => Ok(ConflictPolicy::Overwrite),
+        "fail" => Ok(ConflictPolicy::Fail),
+        other => Err(AppError::validation(format!(
+            "invalid --on-conflict: {other}. expected: skip|overwrite|fail"
+        ))),
+    }
+}
+
+/// Dynamic tables keyed by name, derived from `schema_registry` rows.
+fn dynamic_tables(
+    registry: &[Map<String, Value>],
+) -> Result<BTreeMap<String, SchemaDef>, AppError> {
+    let mut out = BTreeMap::new();
+    for row in registry {
+        let raw = row
+            .get("schema_json")
+            .and_then(Value::as_str)
+            .ok_or_else(|| AppError::validation("schema_registry row without schema_json"))?;
+        let def: SchemaDef = serde_json::from_str(raw)
+            .map_err(|e| AppError::storage(format!("corrupt schema json: {e}")))?;
+        out.insert(dynamic_table_repo::table_name_for(&def), def);
+    }
+    Ok(out)
+}
+
+fn encode_rows(rows: &[Map<String, Value>]) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Vec::new();
+    for row in rows {
+        let line = serde_json::to_string(row)
+            .map_err(|e| AppError::storage(format!("failed to encode row: {e}")))?;
+        bytes.extend_from_slice(line.as_bytes());
+        bytes.push(b'\n');
+    }
+    Ok(bytes)
+}
+
+/// Writes every core table, the events of unrestored archives and every existing dynamic
+/// schema table to `<table>.ndjson` under `out`, plus a manifest with row counts and checksums. Reads run in one transaction so
+/// the bundle is a consistent snapshot.
+pub fn export(
+    conn: &mut Connection,
+    out: &Path,
+    schema_version: i64,
+    now: &str,
+) -> Result<BundleManifest, AppError> {
+    if out.join(MANIFEST_FILE).exists() {
+        return Err(AppError::conflict(format!(
+            "bundle already exists at {}",
+            out.display()
+        )));
+    }
+    fs::create_dir_all(out).map_err(|e| {
+        AppError::io(
+            e,
+            format!("failed to create bundle directory {}", out.display()),
+        )
+    })?;
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let mut dumps = Vec::new();
+    for table in CORE_TABLES {
+        dumps.push((table.to_string(), bundle_repo::dump_table(&tx, table)?));
+    }
+    let mut archived = Vec::new();
+    for ev in archive_service::unrestored_events(&tx)? {
+        match serde_json::to_value(ev) {
+            Ok(Value::Object(row)) => archived.push(row),
+            _ => return Err(AppError::storage("failed to encode archived event")),
+        }
+    }
+    dumps.push((ARCHIVED_EVENTS.to_string(), archived));
+    let registry = dumps
+        .iter()
+        .find(|(table, _)| table == "schema_registry")
+        .map_or(&[][..], |(_, rows)| rows.as_slice());
+    for table in dynamic_tables(registry)?.into_keys() {
+        if bundle_repo::table_exists(&tx, &table)? {
+            let rows = bundle_repo::dump_table(&tx, &table)?;
+            dumps.push((table, rows));
+        }
+    }
+    drop(tx);
+
+    let mut manifest = BundleManifest {
+        format: BUNDLE_FORMAT.to_string(),
+        format_version: BUNDLE_FORMAT_VERSION,
+        schema_version,
+        exported_at: now.to_string(),
+        tables: Vec::with_capacity(dumps.len()),
+    };
+    for (table, rows) in dumps {
+        let file = format!("{table}.ndjson");
+        let bytes = encode_rows(&rows)?;
+        let path = out.join(&file);
+        fs::write(&path, &bytes).map_err(|e| {
+            AppError::io(e, format!("failed to write bundle file {}", path.display()))
+        })?;
+        manifest.tables.push(BundleTable {
+            table,
+            file,
+            rows: rows.len(),
+            sha256: sha256_hex(&bytes),
+        });
+    }
+
+    let raw = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| AppError::storage(format!("failed to encode manifest: {e}")))?;
+    fs::write(out.join(MANIFEST_FILE), raw)
+        .map_err(|e| AppError::io(e, "failed to write bundle manifest"))?;
+    Ok(manifest)
+}
+
+pub fn read_manifest(dir: &Path) -> Result<BundleManifest, AppError> {
+    let path = dir.join(MANIFEST_FILE);
+    let raw = fs::read_to_string(&path).map_err(|e| {
+        AppError::io(
+            e,
+            format!("failed to read bundle manifest {}", path.display()),
+        )
+    })?;
+    let manifest: BundleManifest = serde_json::from_str(&raw)
+        .map_err(|e| AppError::validation(format!("invalid bundle manifest: {e}")))?;
+    if manifest.format != BUNDLE_FORMAT {
+        return Err(AppError::validation(format!(
+            "not an {BUNDLE_FORMAT}: format={}",
+            manifest.format
+        )));
+    }
+    if manifest.format_version != BUNDLE_FORMAT_VERSION {
+        return Err(AppError::validation(format!(
+            "unsupported bundle format_version={}. expected: {BUNDLE_FORMAT_VERSION}",
+            manifest.format_version
+        )));
+    }
+    Ok(manifest)
+}
+
+fn read_rows(dir: &Path, entry: &BundleTable) -> Result<Vec<Map<String, Value>>, AppError> {
+    let path = dir.join(&entry.file);
+    let bytes = fs::read(&path)
+        .map_err(|e| AppError::io(e, format!("failed to read bundle file {}", path.display())))?;
+    let actual = sha256_hex(&bytes);
+    if actual != entry.sha256 {
+        return Err(AppError::validation(format!(
+            "bundle checksum mismatch for {}: expected {} got {actual}",
+            entry.file, entry.sha256
+        )));
+    }
+    let mut rows = Vec::with_capacity(entry.rows);
+    for line in String::from_utf8_lossy(&bytes).lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(serde_json::from_str(line).map_err(|e| {
+            AppError::storage(format!("corrupt bundle line in {}: {e}", entry.file))
+        })?);
+    }
+    if rows.len() != entry.rows {
+        return Err(AppError::validation(format!(
+            "bundle row count mismatch for {}: expected {} got {}",
+            entry.file,
+            entry.rows,
+            rows.len()
+        )));
+    }
+    Ok(rows)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableImport {
+    pub table: String,
+    pub rows: usize,
+    pub written: usize,
+    pub skipped: usize,
+}
+
+pub struct ImportOptions<'a> {
+    pub policy: ConflictPolicy,
+    /// Exported uid -> uid to store instead.
+    pub uid_map: &'a BTreeMap<String, String>,
+    /// Migration version of the target database.
+    pub schema_version: i64,
+}
+
+/// Restores a bundle in one transaction. Rows identical to existing ones are skipped; other
+/// key collisions follow `policy`, and `Fail` rolls back the whole import.
+pub fn import(
+    conn: &mut Connection,
+    dir: &Path,
+    opts: &ImportOptions<'_>,
+) -> Result<Vec<TableImport>, AppError> {
+    let manifest = read_manifest(dir)?;
+    if manifest.schema_version > opts.schema_version {
+        return Err(AppError::schema_mismatch(format!(
+            "bundle was exported at schema version {} but this database is at {}; upgrade the binary and run admin migrate",
+            manifest.schema_version, opts.schema_version
+        )));
+    }
+
+    let mut core = Vec::new();
+    let mut dynamic = Vec::new();
+    for entry in &manifest.tables {
+        let rows = read_rows(dir, entry)?;
+        let target = match entry.table.as_str() {
+            ARCHIVED_EVENTS => "events",
+            table => table,
+        };
+        match CORE_TABLES.iter().position(|t| *t == target) {
+            Some(pos) => core.push((pos, entry.table.clone(), target, rows)),
+            None => dynamic.push((entry.table.clone(), rows)),
+        }
+    }
+    core.sort_by_key(|(pos, _, _, _)| *pos);
+
+    let registry = core
+        .iter()
+        .find(|(_, table, _, _)| table == "schema_registry")
+        .map(|(_, _, _, rows)| dynamic_tables(rows))
+        .transpose()?
+        .unwrap_or_default();
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let mut report = Vec::new();
+    for (_, table, target, rows) in core {
+        report.push(TableImport {
+            table,
+            ..import_table(&tx, target, rows, opts)?
+        });
+    }
+    for (table, rows) in dynamic {
+        let def = registry.get(&table).ok_or_else(|| {
+            AppError::validation(format!(
+                "bundle table {table} is neither a core table nor a registered schema table"
+            ))
+        })?;
+        dynamic_table_repo::create_table_for_schema(&tx, def)?;
+        report.push(import_table(&tx, &table, rows, opts)?);
+    }
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit import"))?;
+    Ok(report)
+}
+
+fn import_table(
+    conn: &Connection,
+    table: &str,
+    rows: Vec<Map<String, Value>>,
+    opts: &ImportOptions<'_>,
+) -> Result<TableImport, AppError> {
+    let columns = bundle_repo::columns(conn, table)?;
+    let mut result = TableImport {
+        table: table.to_string(),
+        rows: rows.len(),
+        written: 0,
+        skipped: 0,
+    };
+    for mut row in rows {
+        for col in UID_COLUMNS {
+            if let Some(Value::String(uid)) = row.get_mut(col) {
+                if let Some(mapped) = opts.uid_map.get(uid.as_str()) {
+                    *uid = mapped.clone();
+                }
+            }
+        }
+        if bundle_repo::import_row(conn, table, &columns, &row, opts.policy)? {
+            result.written += 1;
+        } else {
+            result.skipped += 1;
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,214 @@
+use crate::domain::materializer::{Materialization, MaterializerRule};
+use crate::domain::period::{month_of, week_of};
+use crate::error::AppError;
+use crate::repository::event_repo::{self, EventRow};
+use crate::repository::summary_repo::{self, SummaryKey, SummaryRow};
+use crate::service::authz_service::{self, Access};
+use crate::service::materializer_service;
+use rusqlite::{Connection, Transaction};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub const PERIODS: [&str; 2] = ["week", "month"];
+
+#[derive(Default)]
+pub struct Agg {
+    event_count: i64,
+    value_sum: f64,
+    items: BTreeMap<String, f64>,
+}
+
+/// `(period, period_start, scope_id, uid, event_type, topic)`.
+pub type AggKey = (&'static str, String, String, String, String, String);
+
+pub struct CompactReport {
+    pub events_compacted: usize,
+    pub summaries_updated: usize,
+    /// Events left uncompacted: non-numeric `event_ts` or a payload the rules cannot derive.
+    pub rows_skipped: usize,
+}
+
+#[derive(Default)]
+pub struct Folded {
+    pub aggs: BTreeMap<AggKey, Agg>,
+    /// Ids of events that contributed nothing; see [`CompactReport::rows_skipped`].
+    pub skipped: HashSet<String>,
+}
+
+/// Folds events not yet recorded in `compaction_ledger` into weekly and monthly
+/// `event_summaries`. Each event contributes one event-level row (`topic = ''`) per period
+/// plus one row per counter/topk topic its materializer rules derive. Events that cannot be
+/// placed in a period or derived are skipped and stay out of the ledger.
+pub fn compact(conn: &mut Connection, now: &str) -> Result<CompactReport, AppError> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+
+    let events = event_repo::list_uncompacted(&tx)?;
+    let folded = fold(&tx, &events)?;
+    for ev in &events {
+        if !folded.skipped.contains(&ev.event_id) {
+            summary_repo::mark_compacted(&tx, &ev.event_id, now)?;
+        }
+    }
+    add_to_summaries(&tx, folded.aggs.iter(), 1.0, now)?;
+
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit compaction"))?;
+    Ok(CompactReport {
+        events_compacted: events.len() - folded.skipped.len(),
+        summaries_updated: folded.aggs.len(),
+        rows_skipped: folded.skipped.len(),
+    })
+}
+
+/// The summary contributions of `events`, keyed by the row each one lands in. Events with a
+/// non-numeric `event_ts` or a payload the rules cannot derive from are skipped.
+pub fn fold(tx: &Transaction<'_>, events: &[EventRow]) -> Result<Folded, AppError> {
+    let mut rules: HashMap<String, Vec<MaterializerRule>> = HashMap::new();
+    let mut folded = Folded::default();
+    let aggs = &mut folded.aggs;
+
+    for ev in events {
+        if !rules.contains_key(&ev.event_type) {
+            let loaded = materializer_service::load_rules(tx, &ev.event_type)?;
+            rules.insert(ev.event_type.clone(), loaded);
+        }
+        let derived = serde_json::from_str::<Value>(&ev.payload_json)
+            .map_err(|e| AppError::storage(e.to_string()))
+            .and_then(|payload| materializer_service::derive(&rules[&ev.event_type], &payload));
+        let (Ok(ts), Ok(derived)) = (ev.event_ts.parse::<i64>(), derived) else {
+            folded.skipped.insert(ev.event_id.clone());
+            continue;
+        };
+        for (period, start) in [("week", week_of(ts)), ("month", month_of(ts))] {
+            let key = |topic: &str| {
+                (
+                    period,
+                    start.clone(),
+                    ev.scope_id.clone(),
+                    ev.uid.clone(),
+                    ev.event_type.clone(),
+                    topic.to_string(),
+                )
+            };
+            aggs.entry(key("")).or_default().event_count += 1;
+
+            for m in &derived {
+                let (topic, item, delta) = match m {
+                    Materialization::Counter { topic, item, delta }
+                    | Materialization::Topk { topic, item, delta } => (topic, item, *delta),
+                    Materialization::State { .. } => continue,
+                };
+                let agg = aggs.entry(key(topic)).or_default();
+                agg.event_count += 1;
+                agg.value_sum += delta;
+                *agg.items.entry(item.clone()).or_default() += delta;
+            }
+        }
+    }
+    Ok(folded)
+}
+
+/// Adds `sign` (1 or -1) times each contribution to its stored summary row. A row whose
+/// count drops to zero is deleted, as is an item whose sum cancels out.
+pub fn add_to_summaries<'a>(
+    tx: &Transaction<'_>,
+    aggs: impl IntoIterator<Item = (&'a AggKey, &'a Agg)>,
+    sign: f64,
+    now: &str,
+) -> Result<(), AppError> {
+    for ((period, period_start, scope_id, uid, event_type, topic), agg) in aggs {
+        let key = SummaryKey {
+            period,
+            period_start,
+            scope_id,
+            uid,
+            event_type,
+            topic,
+        };
+        let mut event_count = sign as i64 * agg.event_count;
+        let mut value_sum = sign * agg.value_sum;
+        let mut items: BTreeMap<String, f64> = agg
+            .items
+            .iter()
+            .map(|(k, v)| (k.clone(), sign * v))
+            .collect();
+        if let Some((count, sum, items_json)) = summary_repo::get_totals(tx, &key)? {
+            event_count += count;
+            value_sum += sum;
+            let prior: BTreeMap<String, f64> = serde_json::from_str(&items_json)
+                .map_err(|e| AppError::storage(format!("corrupt summary items: {e}")))?;
+            for (item, v) in prior {
+                *items.entry(item).or_default() += v;
+            }
+        }
+        if event_count <= 0 {
+            summary_repo::delete(tx, &key)?;
+            continue;
+        }
+        items.retain(|_, v| v.abs() > f64::EPSILON);
+        let items_json = serde_json::to_string(&items)
+            .map_err(|e| AppError::storage(format!("failed to encode summary items: {e}")))?;
+        summary_repo::upsert(
+            tx,
+            &key,
+            event_count,
+            value_sum,
+            items.len() as i64,
+            &items_json,
+            now,
+        )?;
+    }
+    Ok(())
+}
+
+/// Stored summary rows of `uid`, re-keyed to `to_uid`, ready for [`add_to_summaries`].
+pub fn summaries_as_aggs(
+    tx: &Transaction<'_>,
+    uid: &str,
+    to_uid: &str,
+) -> Result<BTreeMap<AggKey, Agg>, AppError> {
+    let mut aggs = BTreeMap::new();
+    for row in summary_repo::list_for_uid(tx, uid)? {
+        let Some(period) = PERIODS.into_iter().find(|p| *p == row.period) else {
+            continue;
+        };
+        let items = serde_json::from_str(&row.items_json)
+            .map_err(|e| AppError::storage(format!("corrupt summary items: {e}")))?;
+        aggs.insert(
+            (
+                period,
+                row.period_start,
+                row.scope_id,
+                to_uid.to_string(),
+                row.event_type,
+                row.topic,
+            ),
+            Agg {
+                event_count: row.event_count,
+                value_sum: row.value_sum,
+                items,
+            },
+        );
+    }
+    Ok(aggs)
+}
+
+pub fn summary(
+    conn: &Connection,
+    uid: &str,
+    scope_id: &str,
+    period: &str,
+    event_type: Option<&str>,
+    topic: Option<&str>,
+    limit: usize,
+) -> Result<Vec<SummaryRow>, AppError> {
+    if !PERIODS.contains(&period) {
+        return Err(AppError::validation(
+            "invalid --period. expected: week|month",
+        ));
+    }
+    authz_service::authorize(conn, uid, scope_id, Access::Read)?;
+    summary_repo::query(conn, scope_id, uid, period, event_type, topic, limit)
+}
@@ -0,0 +1,172 @@
+use crate::domain::{DomainEvent, EventObserver, EVENT_NAMES};
+use crate::error::AppError;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookPhase {
+    /// Runs inside the mutation's transaction; a non-zero exit vetoes the operation.
+    Pre,
+    /// Runs after the transaction committed; failures are reported but change nothing.
+    Post,
+}
+
+impl HookPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookPhase::Pre => "pre",
+            HookPhase::Post => "post",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookDef {
+    /// Domain event name, e.g. `user.created`, or `*` for every event.
+    pub event: String,
+    pub phase: HookPhase,
+    pub command: String,
+}
+
+impl HookDef {
+    fn matches(&self, phase: HookPhase, event: &DomainEvent) -> bool {
+        self.phase == phase && (self.event == "*" || self.event == event.name())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    #[serde(default)]
+    pub hooks: Vec<HookDef>,
+}
+
+impl HookConfig {
+    pub fn load(path: &str) -> Result<Self, AppError> {
+        let raw =
+            fs::read_to_string(path).map_err(|e| AppError::io(e, "failed to read hooks file"))?;
+        let config: HookConfig = serde_json::from_str(&raw)
+            .map_err(|e| AppError::validation(format!("invalid hooks json: {e}")))?;
+        for hook in &config.hooks {
+            if hook.event != "*" && !EVENT_NAMES.contains(&hook.event.as_str()) {
+                return Err(AppError::validation(format!(
+                    "invalid hook event: {}. expected one of: *|{}",
+                    hook.event,
+                    EVENT_NAMES.join("|")
+                )));
+            }
+            if hook.command.trim().is_empty() {
+                return Err(AppError::validation(format!(
+                    "hook for event={} has an empty command",
+                    hook.event
+                )));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Runs `sh -c <command>` with the event JSON on stdin and `HOOK_EVENT`/`HOOK_PHASE` set.
+fn run_hook(hook: &HookDef, event: &DomainEvent) -> Result<(), AppError> {
+    let payload = serde_json::to_string(event)
+        .map_err(|e| AppError::storage(format!("failed to encode domain event: {e}")))?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&hook.command)
+        .env("HOOK_EVENT", event.name())
+        .env("HOOK_PHASE", hook.phase.as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::io(e, "failed to spawn hook command"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook may exit without reading stdin; its exit status is what counts.
+        let _ = writeln!(stdin, "{payload}");
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| AppError::io(e, "failed to wait for hook command"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(AppError::storage(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Wraps another observer with configured shell hooks. Pre-hooks run before the event is
+/// handed to `inner`, so a veto aborts the caller's transaction; events are then buffered
+/// until the caller invokes [`HookObserver::run_post_hooks`] after commit.
+pub struct HookObserver<O> {
+    config: HookConfig,
+    inner: O,
+    committed: RefCell<Vec<DomainEvent>>,
+}
+
+impl<O: EventObserver> HookObserver<O> {
+    pub fn new(config: HookConfig, inner: O) -> Self {
+        HookObserver {
+            config,
+            inner,
+            committed: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Runs post-hooks for every event seen so far and returns one message per failure.
+    pub fn run_post_hooks(&self) -> Vec<String> {
+        let events = self.committed.take();
+        let mut failures = Vec::new();
+        for event in &events {
+            for hook in self
+                .config
+                .hooks
+                .iter()
+                .filter(|h| h.matches(HookPhase::Post, event))
+            {
+                if let Err(e) = run_hook(hook, event) {
+                    failures.push(format!(
+                        "post hook `{}` for {} failed: {e}",
+                        hook.command,
+                        event.name()
+                    ));
+                }
+            }
+        }
+        failures
+    }
+
+    /// Drops buffered events whose transaction was rolled back.
+    pub fn discard(&self) {
+        self.committed.borrow_mut().clear();
+    }
+}
+
+impl<O: EventObserver> EventObserver for HookObserver<O> {
+    fn on_event(&self, conn: &Connection, event: &DomainEvent) -> Result<(), AppError> {
+        for hook in self
+            .config
+            .hooks
+            .iter()
+            .filter(|h| h.matches(HookPhase::Pre, event))
+        {
+            run_hook(hook, event).map_err(|e| {
+                AppError::hook_vetoed(format!("pre hook vetoed {}: {e}", event.name()))
+                    .with_details(json!({ "event": event.name(), "command": hook.command }))
+            })?;
+        }
+        self.inner.on_event(conn, event)?;
+        self.committed.borrow_mut().push(event.clone());
+        Ok(())
+    }
+}
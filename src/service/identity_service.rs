@@ -1,7 +1,52 @@
 use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
 use crate::repository::{identity_repo, user_repo};
 use rusqlite::Connection;
 
+/// A user addressed either by canonical uid or by a linked `(channel, channel_user_id)`.
+#[derive(Debug, Clone)]
+pub enum UserRef {
+    Uid(String),
+    Channel {
+        channel: String,
+        channel_user_id: String,
+    },
+}
+
+impl UserRef {
+    /// Either `uid` alone or `channel` with `channel_user_id`, as sent by batch lines and RPC
+    /// params.
+    pub fn from_parts(
+        uid: Option<String>,
+        channel: Option<String>,
+        channel_user_id: Option<String>,
+    ) -> Result<Self, AppError> {
+        match (uid, channel, channel_user_id) {
+            (Some(uid), None, None) => Ok(UserRef::Uid(uid)),
+            (None, Some(channel), Some(channel_user_id)) => Ok(UserRef::Channel {
+                channel,
+                channel_user_id,
+            }),
+            _ => Err(AppError::validation(
+                "requires either uid or channel + channel_user_id",
+            )),
+        }
+    }
+
+    /// Resolves to the canonical uid; pass a transaction to resolve inside it.
+    pub fn resolve(&self, conn: &Connection) -> Result<String, AppError> {
+        match self {
+            UserRef::Uid(uid) => Ok(uid.clone()),
+            UserRef::Channel {
+                channel,
+                channel_user_id,
+            } => resolve(conn, channel, channel_user_id)?.ok_or_else(|| {
+                AppError::not_found(format!("identity not found: {channel}:{channel_user_id}"))
+            }),
+        }
+    }
+}
+
 pub fn link(
     conn: &Connection,
     identity_id: &str,
@@ -10,15 +55,19 @@ pub fn link(
     channel_user_id: &str,
     now: &str,
     observer: &dyn EventObserver,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     if user_repo::get_name(conn, uid)?.is_none() {
-        return Err(format!("user not found: {uid}"));
+        return Err(AppError::not_found(format!("user not found: {uid}")));
     }
     identity_repo::insert(conn, identity_id, uid, channel, channel_user_id, now)?;
-    observer.on_event(&DomainEvent::IdentityLinked {
-        uid: uid.to_string(),
-        channel: channel.to_string(),
-    })?;
+    observer.on_event(
+        conn,
+        &DomainEvent::IdentityLinked {
+            uid: uid.to_string(),
+            channel: channel.to_string(),
+            channel_user_id: channel_user_id.to_string(),
+        },
+    )?;
     Ok(())
 }
 
@@ -26,10 +75,27 @@ pub fn resolve(
     conn: &Connection,
     channel: &str,
     channel_user_id: &str,
-) -> Result<Option<String>, String> {
+) -> Result<Option<String>, AppError> {
     identity_repo::resolve_uid(conn, channel, channel_user_id)
 }
 
-pub fn unlink(conn: &Connection, channel: &str, channel_user_id: &str) -> Result<usize, String> {
-    identity_repo::delete(conn, channel, channel_user_id)
+pub fn unlink(
+    conn: &Connection,
+    channel: &str,
+    channel_user_id: &str,
+    observer: &dyn EventObserver,
+) -> Result<usize, AppError> {
+    let Some(uid) = resolve(conn, channel, channel_user_id)? else {
+        return Ok(0);
+    };
+    let n = identity_repo::delete(conn, channel, channel_user_id)?;
+    observer.on_event(
+        conn,
+        &DomainEvent::IdentityUnlinked {
+            uid,
+            channel: channel.to_string(),
+            channel_user_id: channel_user_id.to_string(),
+        },
+    )?;
+    Ok(n)
 }
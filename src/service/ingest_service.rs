@@ -1,9 +1,15 @@
-use crate::repository::{event_repo, metric_repo, topk_repo};
-use rusqlite::Connection;
+use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
+use crate::repository::event_repo;
+use crate::service::authz_service::{self, Access};
+use crate::service::identity_service::UserRef;
+use crate::service::{materializer_service, schema_service};
+use rusqlite::{Connection, Transaction};
+use serde::Serialize;
 use serde_json::Value;
 
 pub struct IngestInput<'a> {
-    pub uid: &'a str,
+    pub user: &'a UserRef,
     pub scope_id: &'a str,
     pub event_type: &'a str,
     pub payload: &'a Value,
@@ -12,54 +18,35 @@ pub struct IngestInput<'a> {
     pub now: &'a str,
 }
 
-pub enum MaterializedCounter {
-    FoodPref(String),
-    SpendCategory(String),
-    RequestPattern(String),
-}
-
-pub fn derive(event_type: &str, payload: &Value) -> Result<Option<MaterializedCounter>, String> {
-    match event_type {
-        "meal.rated" => {
-            let cuisine = payload
-                .get("cuisine")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| "meal.rated requires string field: cuisine".to_string())?;
-            Ok(Some(MaterializedCounter::FoodPref(cuisine.to_string())))
-        }
-        "expense.logged" => {
-            let category = payload
-                .get("category")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| "expense.logged requires string field: category".to_string())?;
-            Ok(Some(MaterializedCounter::SpendCategory(
-                category.to_string(),
-            )))
-        }
-        "request.logged" => {
-            let pattern = payload
-                .get("pattern")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| "request.logged requires string field: pattern".to_string())?;
-            Ok(Some(MaterializedCounter::RequestPattern(
-                pattern.to_string(),
-            )))
-        }
-        _ => Ok(None),
-    }
-}
-
-pub fn ingest(conn: &mut Connection, input: IngestInput<'_>) -> Result<IngestOutcome, String> {
-    let derived = derive(input.event_type, input.payload)?;
-
+pub fn ingest(
+    conn: &mut Connection,
+    input: IngestInput<'_>,
+    observer: &dyn EventObserver,
+) -> Result<IngestOutcome, AppError> {
     let tx = conn
         .transaction()
-        .map_err(|e| format!("failed to begin tx: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let outcome = ingest_in_tx(&tx, input, observer)?;
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+    Ok(outcome)
+}
+
+/// Runs the ingest steps against an already open transaction; the caller owns commit/rollback.
+pub fn ingest_in_tx(
+    tx: &Transaction<'_>,
+    input: IngestInput<'_>,
+    observer: &dyn EventObserver,
+) -> Result<IngestOutcome, AppError> {
+    let uid = input.user.resolve(tx)?;
+    let uid = uid.as_str();
+    authz_service::authorize(tx, uid, input.scope_id, Access::Write)?;
+    let payload = schema_service::validate_event(tx, input.event_type, input.payload)?;
+    let rules = materializer_service::load_rules(tx, input.event_type)?;
+    let derived = materializer_service::derive(&rules, &payload)?;
 
     if let Some(key) = input.idempotency_key {
-        if event_repo::idempotency_exists(&tx, input.scope_id, input.uid, key)? {
-            tx.commit()
-                .map_err(|e| format!("failed to commit tx: {e}"))?;
+        if event_repo::idempotency_exists(tx, input.scope_id, uid, key)? {
             return Ok(IngestOutcome::Duplicate {
                 idempotency_key: key.to_string(),
             });
@@ -67,70 +54,135 @@ pub fn ingest(conn: &mut Connection, input: IngestInput<'_>) -> Result<IngestOut
     }
 
     event_repo::insert(
-        &tx,
+        tx,
         event_repo::NewEvent {
             event_id: input.event_id,
-            uid: input.uid,
+            uid,
             scope_id: input.scope_id,
             event_type: input.event_type,
             event_ts: input.now,
-            payload_json: &input.payload.to_string(),
+            payload_json: &payload.to_string(),
             idempotency_key: input.idempotency_key,
         },
     )?;
 
-    if let Some(counter) = derived {
-        let (topic, item) = match counter {
-            MaterializedCounter::FoodPref(v) => ("food_pref", v),
-            MaterializedCounter::SpendCategory(v) => ("spend_category", v),
-            MaterializedCounter::RequestPattern(v) => ("request_pattern", v),
-        };
-
-        metric_repo::upsert_counter(&tx, input.scope_id, input.uid, topic, &item, 1.0, input.now)?;
-        rebuild_topk(&tx, input.scope_id, input.uid, topic, input.now)?;
-    }
-
-    tx.commit()
-        .map_err(|e| format!("failed to commit tx: {e}"))?;
+    materializer_service::apply(tx, input.scope_id, uid, &derived, input.now)?;
+    observer.on_event(
+        tx,
+        &DomainEvent::EventIngested {
+            event_id: input.event_id.to_string(),
+            uid: uid.to_string(),
+            scope_id: input.scope_id.to_string(),
+            event_type: input.event_type.to_string(),
+        },
+    )?;
 
     Ok(IngestOutcome::Inserted {
+        uid: uid.to_string(),
         event_id: input.event_id.to_string(),
         event_type: input.event_type.to_string(),
     })
 }
 
-fn rebuild_topk(
-    tx: &rusqlite::Transaction<'_>,
-    scope_id: &str,
-    uid: &str,
-    topic: &str,
+pub struct BatchEvent {
+    pub user: UserRef,
+    pub scope_id: String,
+    pub event_type: String,
+    pub payload: Value,
+    pub idempotency_key: Option<String>,
+    pub event_id: String,
+}
+
+pub struct BatchLine {
+    pub line: usize,
+    pub parsed: Result<BatchEvent, AppError>,
+}
+
+pub enum BatchLineOutcome {
+    Ingested(IngestOutcome),
+    Failed(AppError),
+    RolledBack,
+}
+
+/// Ingests parsed NDJSON lines. With `atomic`, every line shares one transaction and any
+/// failure rolls back the whole batch; otherwise each line commits on its own.
+pub fn ingest_batch(
+    conn: &mut Connection,
+    lines: Vec<BatchLine>,
+    atomic: bool,
     now: &str,
-) -> Result<(), String> {
-    topk_repo::clear(tx, scope_id, uid, topic)?;
-    let rows = metric_repo::topk_source(tx, scope_id, uid, topic)?;
-    for (idx, (key, score)) in rows.into_iter().enumerate() {
-        let item_key = key.splitn(3, ':').nth(2).unwrap_or_default().to_string();
-        topk_repo::insert(
-            tx,
-            topk_repo::TopkRow {
-                scope_id,
-                uid,
-                topic,
-                rank: (idx + 1) as i64,
-                item_key: &item_key,
-                weight: score,
-                now,
+    observer: &dyn EventObserver,
+) -> Result<Vec<(usize, BatchLineOutcome)>, AppError> {
+    let mut results = Vec::with_capacity(lines.len());
+
+    if !atomic {
+        for l in lines {
+            let outcome = match l.parsed {
+                Ok(ev) => match ingest(conn, ev.as_input(now), observer) {
+                    Ok(o) => BatchLineOutcome::Ingested(o),
+                    Err(e) => BatchLineOutcome::Failed(e),
+                },
+                Err(e) => BatchLineOutcome::Failed(e),
+            };
+            results.push((l.line, outcome));
+        }
+        return Ok(results);
+    }
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let mut failed = false;
+    for l in lines {
+        let outcome = match l.parsed {
+            Ok(ev) => match ingest_in_tx(&tx, ev.as_input(now), observer) {
+                Ok(o) => BatchLineOutcome::Ingested(o),
+                Err(e) => BatchLineOutcome::Failed(e),
             },
-        )?;
+            Err(e) => BatchLineOutcome::Failed(e),
+        };
+        failed |= matches!(outcome, BatchLineOutcome::Failed(_));
+        results.push((l.line, outcome));
+    }
+
+    if failed {
+        tx.rollback()
+            .map_err(|e| AppError::db(e, "failed to rollback tx"))?;
+        for (_, outcome) in results.iter_mut() {
+            if matches!(outcome, BatchLineOutcome::Ingested(_)) {
+                *outcome = BatchLineOutcome::RolledBack;
+            }
+        }
+    } else {
+        tx.commit()
+            .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+    }
+    Ok(results)
+}
+
+impl BatchEvent {
+    fn as_input<'a>(&'a self, now: &'a str) -> IngestInput<'a> {
+        IngestInput {
+            user: &self.user,
+            scope_id: &self.scope_id,
+            event_type: &self.event_type,
+            payload: &self.payload,
+            idempotency_key: self.idempotency_key.as_deref(),
+            event_id: &self.event_id,
+            now,
+        }
     }
-    Ok(())
 }
 
+/// Serializes as `{"status":"inserted",..}` or `{"status":"duplicate",..}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum IngestOutcome {
     Duplicate {
         idempotency_key: String,
     },
     Inserted {
+        uid: String,
         event_id: String,
         event_type: String,
     },
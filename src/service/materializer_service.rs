@@ -0,0 +1,95 @@
+use crate::domain::materializer::{evaluate, validate_rule, Materialization, MaterializerRule};
+use crate::error::AppError;
+use crate::repository::{materializer_rule_repo, metric_repo, state_repo, topk_repo};
+use rusqlite::{Connection, Transaction};
+use serde_json::Value;
+
+pub fn register(conn: &Connection, rule: &MaterializerRule, now: &str) -> Result<(), AppError> {
+    validate_rule(rule)?;
+    let rule_json = serde_json::to_string(rule)
+        .map_err(|e| AppError::storage(format!("failed to encode rule: {e}")))?;
+    materializer_rule_repo::upsert(conn, rule, &rule_json, now)
+}
+
+pub fn disable(conn: &Connection, rule_id: &str, now: &str) -> Result<usize, AppError> {
+    materializer_rule_repo::set_active(conn, rule_id, false, now)
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<(MaterializerRule, bool, String)>, AppError> {
+    let mut out = Vec::new();
+    for (rule_id, rule_json, is_active, updated_at) in materializer_rule_repo::list(conn)? {
+        let rule: MaterializerRule = serde_json::from_str(&rule_json)
+            .map_err(|e| AppError::storage(format!("corrupt materializer rule {rule_id}: {e}")))?;
+        out.push((rule, is_active == 1, updated_at));
+    }
+    Ok(out)
+}
+
+pub fn load_rules(conn: &Connection, event_type: &str) -> Result<Vec<MaterializerRule>, AppError> {
+    materializer_rule_repo::active_for_event_type(conn, event_type)?
+        .into_iter()
+        .map(|raw| {
+            serde_json::from_str(&raw).map_err(|e| {
+                AppError::storage(format!("corrupt materializer rule for {event_type}: {e}"))
+            })
+        })
+        .collect()
+}
+
+pub fn derive(
+    rules: &[MaterializerRule],
+    payload: &Value,
+) -> Result<Vec<Materialization>, AppError> {
+    rules.iter().map(|rule| evaluate(rule, payload)).collect()
+}
+
+pub fn apply(
+    tx: &Transaction<'_>,
+    scope_id: &str,
+    uid: &str,
+    derived: &[Materialization],
+    now: &str,
+) -> Result<(), AppError> {
+    for m in derived {
+        match m {
+            Materialization::Counter { topic, item, delta } => {
+                metric_repo::upsert_counter(tx, scope_id, uid, topic, item, *delta, now)?;
+            }
+            Materialization::Topk { topic, item, delta } => {
+                metric_repo::upsert_counter(tx, scope_id, uid, topic, item, *delta, now)?;
+                rebuild_topk(tx, scope_id, uid, topic, now)?;
+            }
+            Materialization::State { key, value } => {
+                state_repo::upsert(tx, scope_id, uid, key, &value.to_string(), now)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn rebuild_topk(
+    tx: &Transaction<'_>,
+    scope_id: &str,
+    uid: &str,
+    topic: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    topk_repo::clear(tx, scope_id, uid, topic)?;
+    let rows = metric_repo::topk_source(tx, scope_id, uid, topic)?;
+    for (idx, (key, score)) in rows.into_iter().enumerate() {
+        let item_key = key.splitn(3, ':').nth(2).unwrap_or_default().to_string();
+        topk_repo::insert(
+            tx,
+            topk_repo::TopkRow {
+                scope_id,
+                uid,
+                topic,
+                rank: (idx + 1) as i64,
+                item_key: &item_key,
+                weight: score,
+                now,
+            },
+        )?;
+    }
+    Ok(())
+}
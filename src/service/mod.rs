@@ -1,19 +1,33 @@
+pub mod archive_service;
+pub mod authz_service;
+pub mod bundle_service;
+pub mod compact_service;
+pub mod hook_service;
 pub mod identity_service;
 pub mod ingest_service;
+pub mod materializer_service;
+pub mod outbox_service;
 pub mod query_service;
+pub mod record_service;
+pub mod reindex_service;
+pub mod schema_service;
 pub mod scope_service;
+pub mod state_service;
 pub mod user_service;
 
 #[cfg(test)]
 mod tests {
+    use super::authz_service::{self, Access, AccessError};
+    use super::outbox_service::{self, OutboxObserver};
     use super::{identity_service, scope_service, user_service};
+    use crate::db::migrate;
     use crate::domain::NoopObserver;
     use rusqlite::Connection;
 
     fn setup_conn() -> Connection {
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute_batch(include_str!("../../specs/SCHEMA_SQLITE_V01.sql"))
-            .unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        let pending = migrate::plan(&conn, None).unwrap();
+        migrate::apply(&mut conn, &pending, "0").unwrap();
         conn
     }
 
@@ -26,7 +40,7 @@ mod tests {
         let name = user_service::show(&conn, "u_1").unwrap();
         assert_eq!(name.as_deref(), Some("Yongseong"));
 
-        user_service::update(&conn, "u_1", "Yong", "101").unwrap();
+        user_service::update(&conn, "u_1", "Yong", "101", &observer).unwrap();
         let name = user_service::show(&conn, "u_1").unwrap();
         assert_eq!(name.as_deref(), Some("Yong"));
 
@@ -62,7 +76,7 @@ mod tests {
         let observer = NoopObserver;
 
         user_service::create(&conn, "u_1", "Yongseong", "100", &observer).unwrap();
-        scope_service::create(&conn, "shared:couple", "shared", "101").unwrap();
+        scope_service::create(&conn, "shared:couple", "shared", "101", &observer).unwrap();
         scope_service::add_member(&conn, "shared:couple", "u_1", "member", "102", &observer)
             .unwrap();
 
@@ -74,4 +88,75 @@ mod tests {
         assert_eq!(members.len(), 1);
         assert_eq!(members[0].0, "u_1");
     }
+
+    #[test]
+    fn authz_service_checks_membership_and_role() {
+        let conn = setup_conn();
+        let observer = NoopObserver;
+
+        user_service::create(&conn, "u_1", "Owner", "100", &observer).unwrap();
+        user_service::create(&conn, "u_2", "Reader", "100", &observer).unwrap();
+        user_service::create(&conn, "u_3", "Outsider", "100", &observer).unwrap();
+        scope_service::create(&conn, "shared:couple", "shared", "101", &observer).unwrap();
+        scope_service::add_member(&conn, "shared:couple", "u_1", "owner", "102", &observer)
+            .unwrap();
+        scope_service::add_member(&conn, "shared:couple", "u_2", "reader", "102", &observer)
+            .unwrap();
+        assert!(scope_service::add_member(
+            &conn,
+            "shared:couple",
+            "u_3",
+            "admin",
+            "102",
+            &observer
+        )
+        .is_err());
+
+        let role = authz_service::authorize(&conn, "u_1", "shared:couple", Access::Write).unwrap();
+        assert_eq!(role, "owner");
+        authz_service::authorize(&conn, "u_2", "shared:couple", Access::Read).unwrap();
+        assert!(matches!(
+            authz_service::authorize(&conn, "u_2", "shared:couple", Access::Write),
+            Err(AccessError::RoleDenied { .. })
+        ));
+        assert!(matches!(
+            authz_service::authorize(&conn, "u_3", "shared:couple", Access::Read),
+            Err(AccessError::NotMember { .. })
+        ));
+        assert!(matches!(
+            authz_service::authorize(&conn, "u_1", "shared:missing", Access::Read),
+            Err(AccessError::ScopeNotFound(_))
+        ));
+        assert!(matches!(
+            authz_service::authorize(&conn, "u_9", "shared:couple", Access::Read),
+            Err(AccessError::UserNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn outbox_observer_writes_in_callers_transaction() {
+        let mut conn = setup_conn();
+        let observer = OutboxObserver::new("100");
+
+        let tx = conn.transaction().unwrap();
+        user_service::create(&tx, "u_1", "Dropped", "100", &observer).unwrap();
+        tx.rollback().unwrap();
+        assert!(outbox_service::list(&conn, None, None, 10)
+            .unwrap()
+            .is_empty());
+
+        let tx = conn.transaction().unwrap();
+        user_service::create(&tx, "u_1", "Kept", "100", &observer).unwrap();
+        scope_service::create(&tx, "private:u_1", "private", "100", &observer).unwrap();
+        tx.commit().unwrap();
+
+        let rows = outbox_service::list(&conn, None, None, 10).unwrap();
+        let keys: Vec<_> = rows.iter().map(|r| r.item_key.as_str()).collect();
+        assert_eq!(keys, ["user.created", "scope.created"]);
+        assert_eq!(rows[0].stream, "user");
+        assert_eq!(
+            rows[0].payload_json,
+            r#"{"event":"user.created","uid":"u_1"}"#
+        );
+    }
 }
@@ -0,0 +1,267 @@
+use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
+use crate::repository::projection_outbox_repo::{self, OutboxRow};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+/// Persists every domain event as a pending outbox row on the connection the mutation ran
+/// on, so the row commits or rolls back together with the change it describes.
+pub struct OutboxObserver {
+    now: String,
+    seq: Cell<u64>,
+}
+
+impl OutboxObserver {
+    pub fn new(now: &str) -> Self {
+        OutboxObserver {
+            now: now.to_string(),
+            seq: Cell::new(0),
+        }
+    }
+
+    fn next_id(&self) -> String {
+        let n = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let seq = self.seq.get();
+        self.seq.set(seq + 1);
+        format!("outbox_{n}_{seq}")
+    }
+}
+
+impl EventObserver for OutboxObserver {
+    fn on_event(&self, conn: &Connection, event: &DomainEvent) -> Result<(), AppError> {
+        let payload = serde_json::to_string(event)
+            .map_err(|e| AppError::storage(format!("failed to encode domain event: {e}")))?;
+        projection_outbox_repo::enqueue(
+            conn,
+            &self.next_id(),
+            event.stream(),
+            event.name(),
+            &payload,
+            &self.now,
+        )
+    }
+}
+
+/// Downstream target for outbox rows. `deliver` must only return `Ok` once the row is durably
+/// accepted; the dispatcher then marks it delivered, so a crash in between redelivers it.
+pub trait OutboxSink {
+    fn deliver(&mut self, row: &OutboxRow) -> Result<(), AppError>;
+}
+
+fn envelope(row: &OutboxRow) -> Value {
+    let payload: Value =
+        serde_json::from_str(&row.payload_json).unwrap_or(Value::String(row.payload_json.clone()));
+    json!({
+        "outbox_id": row.outbox_id,
+        "stream": row.stream,
+        "key": row.item_key,
+        "payload": payload,
+        "created_at": row.created_at,
+        "attempt": row.attempts + 1,
+    })
+}
+
+/// Appends one NDJSON line per row.
+pub struct FileSink {
+    pub path: String,
+}
+
+impl OutboxSink for FileSink {
+    fn deliver(&mut self, row: &OutboxRow) -> Result<(), AppError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| AppError::io(e, format!("failed to open sink file {}", self.path)))?;
+        writeln!(file, "{}", envelope(row))
+            .and_then(|_| file.sync_data())
+            .map_err(|e| AppError::io(e, format!("failed to write sink file {}", self.path)))
+    }
+}
+
+/// Runs `sh -c <command>` per row with the JSON envelope on stdin; non-zero exit is a failure.
+pub struct ExecSink {
+    pub command: String,
+}
+
+impl OutboxSink for ExecSink {
+    fn deliver(&mut self, row: &OutboxRow) -> Result<(), AppError> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("OUTBOX_ID", &row.outbox_id)
+            .env("OUTBOX_STREAM", &row.stream)
+            .env("OUTBOX_KEY", &row.item_key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| AppError::io(e, "failed to spawn sink command"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A sink may exit without reading stdin; its exit status is what counts.
+            let _ = writeln!(stdin, "{}", envelope(row));
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| AppError::io(e, "failed to wait for sink command"))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(AppError::storage(format!(
+                "sink command exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+}
+
+/// POSTs the JSON envelope; any 2xx response is a delivery. The outbox id is sent as
+/// `Idempotency-Key` so receivers can drop redeliveries.
+pub struct HttpSink {
+    pub url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpSink {
+    pub fn new(url: &str) -> Self {
+        HttpSink {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+        }
+    }
+}
+
+impl OutboxSink for HttpSink {
+    fn deliver(&mut self, row: &OutboxRow) -> Result<(), AppError> {
+        self.agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .set("Idempotency-Key", &row.outbox_id)
+            .send_string(&envelope(row).to_string())
+            .map(|_| ())
+            .map_err(|e| AppError::storage(format!("http sink {} failed: {e}", self.url)))
+    }
+}
+
+pub fn sink_for(kind: &str, target: &str) -> Result<Box<dyn OutboxSink>, AppError> {
+    match kind {
+        "file" => Ok(Box::new(FileSink {
+            path: target.to_string(),
+        })),
+        "exec" => Ok(Box::new(ExecSink {
+            command: target.to_string(),
+        })),
+        "http" if target.starts_with("http://") || target.starts_with("https://") => {
+            Ok(Box::new(HttpSink::new(target)))
+        }
+        "http" => Err(AppError::validation(format!(
+            "invalid --target for http sink: {target}. expected an http:// or https:// URL"
+        ))),
+        other => Err(AppError::validation(format!(
+            "invalid --sink: {other}. expected: file|exec|http"
+        ))),
+    }
+}
+
+pub struct DrainOptions<'a> {
+    pub stream: Option<&'a str>,
+    pub limit: usize,
+    pub max_attempts: i64,
+    pub backoff_secs: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct DrainReport {
+    pub delivered: usize,
+    pub retrying: usize,
+    pub dead: usize,
+}
+
+/// Delay before retry number `attempts` (1-based): `base * 2^(attempts-1)`, capped at an hour.
+fn backoff(base: i64, attempts: i64) -> i64 {
+    let shift = (attempts - 1).clamp(0, 20) as u32;
+    base.saturating_mul(1 << shift).min(MAX_BACKOFF_SECS)
+}
+
+/// Delivers due rows to `sink` oldest first. Each row's outcome is committed on its own, so a
+/// failure never blocks later rows; rows reaching `max_attempts` failures are dead-lettered.
+pub fn drain(
+    conn: &Connection,
+    sink: &mut dyn OutboxSink,
+    opts: &DrainOptions<'_>,
+    now: &str,
+) -> Result<DrainReport, AppError> {
+    if opts.max_attempts < 1 {
+        return Err(AppError::validation("--max-attempts must be at least 1"));
+    }
+    let now_secs: i64 = now
+        .parse()
+        .map_err(|e| AppError::validation(format!("invalid timestamp {now}: {e}")))?;
+
+    let mut report = DrainReport::default();
+    for row in projection_outbox_repo::due(conn, opts.stream, now, opts.limit)? {
+        match sink.deliver(&row) {
+            Ok(()) => {
+                projection_outbox_repo::mark_delivered(conn, &row.outbox_id, now)?;
+                report.delivered += 1;
+            }
+            Err(e) => {
+                let attempts = row.attempts + 1;
+                if attempts >= opts.max_attempts {
+                    projection_outbox_repo::record_failure(
+                        conn,
+                        &row.outbox_id,
+                        e.message(),
+                        None,
+                        Some(now),
+                    )?;
+                    report.dead += 1;
+                } else {
+                    let next = (now_secs + backoff(opts.backoff_secs, attempts)).to_string();
+                    projection_outbox_repo::record_failure(
+                        conn,
+                        &row.outbox_id,
+                        e.message(),
+                        Some(&next),
+                        None,
+                    )?;
+                    report.retrying += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+pub fn list(
+    conn: &Connection,
+    status: Option<&str>,
+    stream: Option<&str>,
+    limit: usize,
+) -> Result<Vec<OutboxRow>, AppError> {
+    if let Some(status) = status {
+        if !["pending", "delivered", "dead"].contains(&status) {
+            return Err(AppError::validation(format!(
+                "invalid --status: {status}. expected: pending|delivered|dead"
+            )));
+        }
+    }
+    projection_outbox_repo::list(conn, status, stream, limit)
+}
+
+pub fn requeue(conn: &Connection, outbox_id: Option<&str>) -> Result<usize, AppError> {
+    projection_outbox_repo::requeue_dead(conn, outbox_id)
+}
@@ -1,11 +1,58 @@
-use crate::repository::{event_repo, metric_repo, topk_repo};
+use crate::error::AppError;
+use crate::repository::{event_repo, metric_repo, scope_repo, topk_repo};
+use crate::service::authz_service::{self, Access};
 use rusqlite::Connection;
+use std::collections::HashSet;
+
+/// Which scopes a query reads, and in what order.
+pub enum ScopeSelection {
+    Single(String),
+    List(Vec<String>),
+    /// Requestor private scope, then shared scopes, then global scopes the user belongs to.
+    Auto,
+}
+
+impl ScopeSelection {
+    pub fn is_single(&self) -> bool {
+        matches!(self, ScopeSelection::Single(_))
+    }
+}
+
+pub fn read_order(
+    conn: &Connection,
+    uid: &str,
+    selection: &ScopeSelection,
+) -> Result<Vec<String>, AppError> {
+    let scopes = match selection {
+        ScopeSelection::Single(scope_id) => vec![scope_id.clone()],
+        ScopeSelection::List(scopes) => {
+            let mut seen = HashSet::new();
+            scopes
+                .iter()
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty() && seen.insert(*s))
+                .map(str::to_string)
+                .collect()
+        }
+        ScopeSelection::Auto => scope_repo::list_member_scopes_in_read_order(conn, uid)?,
+    };
+    if scopes.is_empty() {
+        return Err(AppError::unauthorized(format!(
+            "no readable scopes for user {uid}"
+        )));
+    }
+    for scope_id in &scopes {
+        authz_service::authorize(conn, uid, scope_id, Access::Read)?;
+    }
+    Ok(scopes)
+}
 
 pub fn latest(
     conn: &Connection,
     uid: &str,
     scope_id: &str,
-) -> Result<Option<(String, String, String)>, String> {
+) -> Result<Option<(String, String, String)>, AppError> {
+    authz_service::authorize(conn, uid, scope_id, Access::Read)?;
     event_repo::latest(conn, uid, scope_id)
 }
 
@@ -15,10 +62,13 @@ pub fn metric(
     scope_id: &str,
     key: Option<&str>,
     prefix: Option<&str>,
-) -> Result<Vec<(String, f64, String)>, String> {
+) -> Result<Vec<(String, f64, String)>, AppError> {
     if key.is_none() && prefix.is_none() {
-        return Err("query metric requires either --key or --prefix".to_string());
+        return Err(AppError::validation(
+            "query metric requires either --key or --prefix",
+        ));
     }
+    authz_service::authorize(conn, uid, scope_id, Access::Read)?;
 
     let mut out = Vec::new();
     if let Some(key) = key {
@@ -38,6 +88,65 @@ pub fn topk(
     scope_id: &str,
     topic: &str,
     limit: usize,
-) -> Result<Vec<(i64, String, f64)>, String> {
+) -> Result<Vec<(i64, String, f64)>, AppError> {
+    authz_service::authorize(conn, uid, scope_id, Access::Read)?;
     topk_repo::query(conn, scope_id, uid, topic, limit)
 }
+
+/// First scope in `scopes` holding an event wins; returns `(scope_id, event_id, event_type, event_ts)`.
+pub fn latest_in_order(
+    conn: &Connection,
+    uid: &str,
+    scopes: &[String],
+) -> Result<Option<(String, String, String, String)>, AppError> {
+    for scope_id in scopes {
+        if let Some((event_id, event_type, event_ts)) = latest(conn, uid, scope_id)? {
+            return Ok(Some((scope_id.clone(), event_id, event_type, event_ts)));
+        }
+    }
+    Ok(None)
+}
+
+/// Falls through `scopes` per metric key; a key found in an earlier scope shadows later ones.
+pub fn metric_in_order(
+    conn: &Connection,
+    uid: &str,
+    scopes: &[String],
+    key: Option<&str>,
+    prefix: Option<&str>,
+) -> Result<Vec<(String, String, f64, String)>, AppError> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for scope_id in scopes {
+        for (k, v, j) in metric(conn, uid, scope_id, key, prefix)? {
+            if seen.insert(k.clone()) {
+                out.push((scope_id.clone(), k, v, j));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Fills up to `limit` items from `scopes` in order, skipping items already taken from an
+/// earlier scope, and re-ranks the merged list; returns `(scope_id, rank, item, weight)`.
+pub fn topk_in_order(
+    conn: &Connection,
+    uid: &str,
+    scopes: &[String],
+    topic: &str,
+    limit: usize,
+) -> Result<Vec<(String, i64, String, f64)>, AppError> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for scope_id in scopes {
+        for (_, item, weight) in topk(conn, uid, scope_id, topic, limit)? {
+            if out.len() == limit {
+                return Ok(out);
+            }
+            if seen.insert(item.clone()) {
+                out.push((scope_id.clone(), out.len() as i64 + 1, item, weight));
+            }
+        }
+    }
+    Ok(out)
+}
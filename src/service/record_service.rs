@@ -0,0 +1,152 @@
+use crate::domain::schema::{validate_record, SchemaClass};
+use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
+use crate::repository::dynamic_table_repo::{self, DynamicRecord, DynamicRow};
+use crate::repository::user_repo;
+use crate::service::authz_service::{self, Access};
+use crate::service::schema_service::load_schema_version;
+use rusqlite::Connection;
+use serde_json::Value;
+
+pub struct PutRecord<'a> {
+    pub schema_id: &'a str,
+    /// Latest registered version when `None`.
+    pub version: Option<&'a str>,
+    pub record_id: &'a str,
+    pub entity_key: Option<&'a str>,
+    pub payload: &'a Value,
+    pub now: &'a str,
+}
+
+/// Validates the payload against the registered schema and upserts it into the schema's
+/// physical table. Returns the table name.
+pub fn put(
+    conn: &Connection,
+    input: PutRecord<'_>,
+    observer: &dyn EventObserver,
+) -> Result<String, AppError> {
+    let def = load_schema_version(conn, input.schema_id, input.version)?;
+    let fields = validate_record(&def, input.payload)?;
+
+    let (ref_user_id, ref_scope_id) = match def.class {
+        SchemaClass::Domain => {
+            if input.entity_key.is_none() {
+                return Err(AppError::validation(format!(
+                    "record put for domain schema_id={} requires --key",
+                    def.schema_id
+                )));
+            }
+            (None, None)
+        }
+        SchemaClass::UserContext => {
+            let uid = fields
+                .get("refUserId")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    AppError::validation("record validation failed: refUserId must be a string")
+                })?;
+            if !user_repo::exists(conn, uid)? {
+                return Err(AppError::not_found(format!("user not found: {uid}")));
+            }
+            let scope_id = fields.get("refScopeId").and_then(Value::as_str);
+            if let Some(scope_id) = scope_id {
+                authz_service::authorize(conn, uid, scope_id, Access::Write)?;
+            }
+            (Some(uid), scope_id)
+        }
+    };
+
+    dynamic_table_repo::upsert_row(
+        conn,
+        &def,
+        DynamicRow {
+            record_id: input.record_id,
+            entity_key: input.entity_key,
+            ref_user_id,
+            ref_scope_id,
+            fields: &fields,
+            now: input.now,
+        },
+    )?;
+    let table = dynamic_table_repo::table_name_for(&def);
+    observer.on_event(
+        conn,
+        &DomainEvent::RecordStored {
+            schema_id: def.schema_id.clone(),
+            version: def.version.clone(),
+            record_id: input.record_id.to_string(),
+            table: table.clone(),
+        },
+    )?;
+    Ok(table)
+}
+
+/// Applies `put`'s check to a stored row: a user_context record with a `refScopeId` needs
+/// `access` for its `refUserId` in that scope.
+fn authorize_row(conn: &Connection, row: &DynamicRecord, access: Access) -> Result<(), AppError> {
+    if let (Some(uid), Some(scope_id)) = (&row.ref_user_id, &row.ref_scope_id) {
+        authz_service::authorize(conn, uid, scope_id, access)?;
+    }
+    Ok(())
+}
+
+pub fn get(
+    conn: &Connection,
+    schema_id: &str,
+    version: Option<&str>,
+    record_id: &str,
+) -> Result<Option<DynamicRecord>, AppError> {
+    let def = load_schema_version(conn, schema_id, version)?;
+    let row = dynamic_table_repo::get_row(conn, &def, record_id)?;
+    if let Some(row) = &row {
+        authorize_row(conn, row, Access::Read)?;
+    }
+    Ok(row)
+}
+
+pub fn list(
+    conn: &Connection,
+    schema_id: &str,
+    version: Option<&str>,
+    entity_key: Option<&str>,
+    uid: Option<&str>,
+    limit: usize,
+) -> Result<Vec<DynamicRecord>, AppError> {
+    let def = load_schema_version(conn, schema_id, version)?;
+    if uid.is_some() && def.class == SchemaClass::Domain {
+        return Err(AppError::validation(format!(
+            "--uid filter requires a user_context schema; schema_id={schema_id} is domain"
+        )));
+    }
+    let rows = dynamic_table_repo::list_rows(conn, &def, entity_key, uid, limit)?;
+    for row in &rows {
+        authorize_row(conn, row, Access::Read)?;
+    }
+    Ok(rows)
+}
+
+pub fn delete(
+    conn: &Connection,
+    schema_id: &str,
+    version: Option<&str>,
+    record_id: &str,
+    observer: &dyn EventObserver,
+) -> Result<usize, AppError> {
+    let def = load_schema_version(conn, schema_id, version)?;
+    if let Some(row) = dynamic_table_repo::get_row(conn, &def, record_id)? {
+        authorize_row(conn, &row, Access::Write)?;
+    }
+    let n = dynamic_table_repo::delete_row(conn, &def, record_id)?;
+    if n > 0 {
+        observer.on_event(
+            conn,
+            &DomainEvent::RecordDeleted {
+                schema_id: def.schema_id.clone(),
+                version: def.version.clone(),
+                record_id: record_id.to_string(),
+                table: dynamic_table_repo::table_name_for(&def),
+            },
+        )?;
+    }
+    Ok(n)
+}
@@ -0,0 +1,124 @@
+use crate::domain::materializer::{Materialization, MaterializerRule};
+use crate::error::AppError;
+use crate::repository::event_repo::{self, EventRow};
+use crate::repository::{metric_repo, topk_repo};
+use crate::service::{archive_service, materializer_service};
+use rusqlite::{Connection, Transaction};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+pub struct ReindexFilter<'a> {
+    pub uid: Option<&'a str>,
+    pub scope_id: Option<&'a str>,
+    pub topic: Option<&'a str>,
+}
+
+#[derive(Debug, Default)]
+pub struct ReindexReport {
+    pub events_scanned: usize,
+    /// Of `events_scanned`, those read from archives that were not restored.
+    pub archived_events: usize,
+    pub events_skipped: usize,
+    pub metrics_cleared: usize,
+    pub topk_cleared: usize,
+    pub counters_applied: usize,
+    pub topk_rebuilt: usize,
+}
+
+/// Clears derived `counter:*` metrics and `topk` rows matching the filter and replays the
+/// matching events, archived ones included, through the active materializer rules. `state`
+/// targets are not replayed so that directly written state is never clobbered by historical
+/// events.
+pub fn reindex(
+    conn: &mut Connection,
+    filter: ReindexFilter<'_>,
+    now: &str,
+) -> Result<ReindexReport, AppError> {
+    let archived: Vec<EventRow> = archive_service::unrestored_events(conn)?
+        .into_iter()
+        .filter(|ev| {
+            filter.uid.is_none_or(|uid| ev.uid == uid)
+                && filter
+                    .scope_id
+                    .is_none_or(|scope_id| ev.scope_id == scope_id)
+        })
+        .collect();
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+    let mut report = ReindexReport {
+        metrics_cleared: metric_repo::clear_counters(
+            &tx,
+            filter.uid,
+            filter.scope_id,
+            filter.topic,
+        )?,
+        topk_cleared: topk_repo::clear_filtered(&tx, filter.uid, filter.scope_id, filter.topic)?,
+        archived_events: archived.len(),
+        ..Default::default()
+    };
+
+    let live = event_repo::list_for_replay(&tx, filter.uid, filter.scope_id)?;
+    let topk_groups = replay(&tx, archived.iter().chain(&live), filter.topic, &mut report)?;
+    for (scope_id, uid, topic) in &topk_groups {
+        materializer_service::rebuild_topk(&tx, scope_id, uid, topic, now)?;
+    }
+    report.topk_rebuilt = topk_groups.len();
+
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit reindex"))?;
+    Ok(report)
+}
+
+/// Applies the counters `events` derive (only `topic` when set) on top of the current
+/// metrics. Returns the `(scope_id, uid, topic)` groups whose topk needs a rebuild.
+pub fn replay<'a>(
+    tx: &Transaction<'_>,
+    events: impl IntoIterator<Item = &'a EventRow>,
+    topic_filter: Option<&str>,
+    report: &mut ReindexReport,
+) -> Result<BTreeSet<(String, String, String)>, AppError> {
+    let mut rules: HashMap<String, Vec<MaterializerRule>> = HashMap::new();
+    let mut topk_groups = BTreeSet::new();
+
+    for ev in events {
+        report.events_scanned += 1;
+        if !rules.contains_key(&ev.event_type) {
+            let loaded = materializer_service::load_rules(tx, &ev.event_type)?;
+            rules.insert(ev.event_type.clone(), loaded);
+        }
+        let derived = serde_json::from_str::<Value>(&ev.payload_json)
+            .map_err(|e| AppError::storage(e.to_string()))
+            .and_then(|payload| materializer_service::derive(&rules[&ev.event_type], &payload));
+        let Ok(derived) = derived else {
+            report.events_skipped += 1;
+            continue;
+        };
+
+        for m in derived {
+            let (topic, item, delta, ranked) = match &m {
+                Materialization::Counter { topic, item, delta } => (topic, item, *delta, false),
+                Materialization::Topk { topic, item, delta } => (topic, item, *delta, true),
+                Materialization::State { .. } => continue,
+            };
+            if topic_filter.is_some_and(|t| t != topic) {
+                continue;
+            }
+            metric_repo::upsert_counter(
+                tx,
+                &ev.scope_id,
+                &ev.uid,
+                topic,
+                item,
+                delta,
+                &ev.event_ts,
+            )?;
+            report.counters_applied += 1;
+            if ranked {
+                topk_groups.insert((ev.scope_id.clone(), ev.uid.clone(), topic.clone()));
+            }
+        }
+    }
+    Ok(topk_groups)
+}
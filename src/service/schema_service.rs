@@ -0,0 +1,286 @@
+use crate::domain::schema::{
+    diff_schemas, format_field_errors, validate_payload, FieldError, SchemaChange, SchemaDef,
+};
+use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
+use crate::repository::{dynamic_table_repo, event_schema_binding_repo, schema_registry_repo};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::fmt;
+
+pub const BINDING_MODES: [&str; 2] = ["strict", "lenient"];
+
+fn parse_schema(schema_id: &str, raw: &str) -> Result<SchemaDef, AppError> {
+    serde_json::from_str(raw)
+        .map_err(|e| AppError::storage(format!("corrupt schema json for {schema_id}: {e}")))
+}
+
+/// Every registered version of every schema, oldest first.
+pub fn all_versions(conn: &Connection) -> Result<Vec<SchemaDef>, AppError> {
+    schema_registry_repo::list_definitions(conn)?
+        .iter()
+        .map(|raw| parse_schema("schema_registry", raw))
+        .collect()
+}
+
+/// Loads the most recently registered version of `schema_id`.
+pub fn load_schema(conn: &Connection, schema_id: &str) -> Result<SchemaDef, AppError> {
+    let (_, raw, is_active) = schema_registry_repo::get_latest(conn, schema_id)?
+        .ok_or_else(|| AppError::not_found(format!("schema not found: {schema_id}")))?;
+    if is_active != 1 {
+        return Err(AppError::validation(format!(
+            "schema is not active: {schema_id}"
+        )));
+    }
+    parse_schema(schema_id, &raw)
+}
+
+/// Loads `version` of `schema_id`, or the latest version when `None`.
+pub fn load_schema_version(
+    conn: &Connection,
+    schema_id: &str,
+    version: Option<&str>,
+) -> Result<SchemaDef, AppError> {
+    let Some(version) = version else {
+        return load_schema(conn, schema_id);
+    };
+    let (raw, is_active) = schema_registry_repo::get_version(conn, schema_id, version)?
+        .ok_or_else(|| {
+            AppError::not_found(format!("schema not found: {schema_id} version={version}"))
+        })?;
+    if is_active != 1 {
+        return Err(AppError::validation(format!(
+            "schema is not active: {schema_id} version={version}"
+        )));
+    }
+    parse_schema(schema_id, &raw)
+}
+
+pub struct RegisterReport {
+    pub table: String,
+    /// False when the identical version was already registered.
+    pub created: bool,
+    pub previous_version: Option<String>,
+    pub changes: Vec<SchemaChange>,
+    pub rows_copied: usize,
+}
+
+/// Registers a new version of a schema and creates its physical table. A version with
+/// breaking changes against the previous one is refused unless `allow_breaking`; with
+/// `migrate_data`, rows of the previous version's table are copied into the new table.
+pub fn register(
+    conn: &mut Connection,
+    def: &SchemaDef,
+    raw: &str,
+    allow_breaking: bool,
+    migrate_data: bool,
+    now: &str,
+    observer: &dyn EventObserver,
+) -> Result<RegisterReport, AppError> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+
+    if let Some((existing, _)) =
+        schema_registry_repo::get_version(&tx, &def.schema_id, &def.version)?
+    {
+        let existing = parse_schema(&def.schema_id, &existing)?;
+        if !diff_schemas(&existing, def).is_empty() {
+            return Err(AppError::conflict(format!(
+                "schema {} version={} is already registered with a different definition; register a new version",
+                def.schema_id, def.version
+            )));
+        }
+        let table = dynamic_table_repo::create_table_for_schema(&tx, def)?;
+        tx.commit()
+            .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+        return Ok(RegisterReport {
+            table,
+            created: false,
+            previous_version: None,
+            changes: Vec::new(),
+            rows_copied: 0,
+        });
+    }
+
+    let previous = match schema_registry_repo::get_latest(&tx, &def.schema_id)? {
+        Some((version, raw, _)) => Some((version, parse_schema(&def.schema_id, &raw)?)),
+        None => None,
+    };
+    let changes = previous
+        .as_ref()
+        .map(|(_, old)| diff_schemas(old, def))
+        .unwrap_or_default();
+    let breaking: Vec<String> = changes
+        .iter()
+        .filter(|c| c.is_breaking())
+        .map(|c| c.to_string())
+        .collect();
+    if !breaking.is_empty() && !allow_breaking {
+        return Err(AppError::validation(format!(
+            "schema {} version={} has breaking changes ({}); pass --allow-breaking to register anyway",
+            def.schema_id,
+            def.version,
+            breaking.join("; ")
+        )));
+    }
+
+    schema_registry_repo::insert(&tx, def, raw, now)?;
+    let table = dynamic_table_repo::create_table_for_schema(&tx, def)?;
+
+    let mut rows_copied = 0;
+    if migrate_data {
+        let (_, old) = previous.as_ref().ok_or_else(|| {
+            AppError::validation(format!(
+                "--migrate-data requires a previous version of {}",
+                def.schema_id
+            ))
+        })?;
+        if changes.iter().any(|c| {
+            matches!(
+                c,
+                SchemaChange::ClassChanged { .. } | SchemaChange::FieldRetyped { .. }
+            )
+        }) {
+            return Err(AppError::validation(
+                "--migrate-data cannot copy rows across class or field type changes",
+            ));
+        }
+        rows_copied = dynamic_table_repo::copy_rows(&tx, old, def)?;
+    }
+
+    observer.on_event(
+        &tx,
+        &DomainEvent::SchemaRegistered {
+            schema_id: def.schema_id.clone(),
+            version: def.version.clone(),
+            table: table.clone(),
+        },
+    )?;
+
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+    Ok(RegisterReport {
+        table,
+        created: true,
+        previous_version: previous.map(|(v, _)| v),
+        changes,
+        rows_copied,
+    })
+}
+
+/// Changes going from `from` to `to`. `to` defaults to the latest version and `from` to
+/// the version registered just before `to`.
+pub fn diff(
+    conn: &Connection,
+    schema_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<(String, String, Vec<SchemaChange>), AppError> {
+    let versions = schema_registry_repo::versions(conn, schema_id)?;
+    let to = match to {
+        Some(v) => v.to_string(),
+        None => versions
+            .last()
+            .cloned()
+            .ok_or_else(|| AppError::not_found(format!("schema not found: {schema_id}")))?,
+    };
+    let from = match from {
+        Some(v) => v.to_string(),
+        None => {
+            let idx = versions.iter().position(|v| *v == to).ok_or_else(|| {
+                AppError::not_found(format!("schema not found: {schema_id} version={to}"))
+            })?;
+            idx.checked_sub(1)
+                .map(|i| versions[i].clone())
+                .ok_or_else(|| {
+                    AppError::validation(format!(
+                        "schema {schema_id} version={to} has no previous version"
+                    ))
+                })?
+        }
+    };
+    let old = load_schema_version(conn, schema_id, Some(&from))?;
+    let new = load_schema_version(conn, schema_id, Some(&to))?;
+    Ok((from, to, diff_schemas(&old, &new)))
+}
+
+pub fn bind(
+    conn: &Connection,
+    event_type: &str,
+    schema_id: &str,
+    mode: &str,
+    now: &str,
+) -> Result<(), AppError> {
+    if !BINDING_MODES.contains(&mode) {
+        return Err(AppError::validation(format!(
+            "invalid --mode: {mode}. expected: strict|lenient"
+        )));
+    }
+    if event_type.trim().is_empty() {
+        return Err(AppError::validation("event type must not be empty"));
+    }
+    load_schema(conn, schema_id)?;
+    event_schema_binding_repo::upsert(conn, event_type, schema_id, mode, now)
+}
+
+pub fn unbind(conn: &Connection, event_type: &str) -> Result<usize, AppError> {
+    event_schema_binding_repo::delete(conn, event_type)
+}
+
+pub fn bindings(conn: &Connection) -> Result<Vec<(String, String, String, String)>, AppError> {
+    event_schema_binding_repo::list(conn)
+}
+
+/// A payload rejected by the schema bound to its event type.
+#[derive(Debug, Clone)]
+pub struct PayloadError {
+    pub event_type: String,
+    pub schema_id: String,
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "payload validation failed event_type={} schema_id={}: {}",
+            self.event_type,
+            self.schema_id,
+            format_field_errors(&self.errors)
+        )
+    }
+}
+
+impl From<PayloadError> for AppError {
+    fn from(e: PayloadError) -> Self {
+        AppError::validation(e.to_string()).with_details(json!({
+            "event_type": e.event_type,
+            "schema_id": e.schema_id,
+            "field_errors": e.errors,
+        }))
+    }
+}
+
+/// Validates an event payload against the schema bound to `event_type` and returns the
+/// payload to store, with defaults filled in. Unbound event types pass through unchanged.
+pub fn validate_event(
+    conn: &Connection,
+    event_type: &str,
+    payload: &Value,
+) -> Result<Value, AppError> {
+    let Some((schema_id, mode)) = event_schema_binding_repo::get(conn, event_type)? else {
+        return Ok(payload.clone());
+    };
+    let def = load_schema(conn, &schema_id)?;
+    validate_payload(&def, payload, mode == "strict")
+        .map(Value::Object)
+        .map_err(|errors| {
+            PayloadError {
+                event_type: event_type.to_string(),
+                schema_id,
+                errors,
+            }
+            .into()
+        })
+}
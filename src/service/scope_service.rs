@@ -1,5 +1,7 @@
 use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
 use crate::repository::scope_repo;
+use crate::service::authz_service;
 use rusqlite::Connection;
 
 pub fn create(
@@ -7,8 +9,17 @@ pub fn create(
     scope_id: &str,
     scope_type: &str,
     now: &str,
-) -> Result<(), String> {
-    scope_repo::insert_scope(conn, scope_id, scope_type, now)
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
+    scope_repo::insert_scope(conn, scope_id, scope_type, now)?;
+    observer.on_event(
+        conn,
+        &DomainEvent::ScopeCreated {
+            scope_id: scope_id.to_string(),
+            scope_type: scope_type.to_string(),
+        },
+    )?;
+    Ok(())
 }
 
 pub fn add_member(
@@ -18,19 +29,24 @@ pub fn add_member(
     role: &str,
     now: &str,
     observer: &dyn EventObserver,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    authz_service::validate_role(role)?;
     scope_repo::insert_member(conn, scope_id, uid, role, now)?;
-    observer.on_event(&DomainEvent::ScopeMemberAdded {
-        scope_id: scope_id.to_string(),
-        uid: uid.to_string(),
-    })?;
+    observer.on_event(
+        conn,
+        &DomainEvent::ScopeMemberAdded {
+            scope_id: scope_id.to_string(),
+            uid: uid.to_string(),
+            role: role.to_string(),
+        },
+    )?;
     Ok(())
 }
 
-pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, String> {
+pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, AppError> {
     scope_repo::list_scopes(conn)
 }
 
-pub fn members(conn: &Connection, scope_id: &str) -> Result<Vec<(String, String)>, String> {
+pub fn members(conn: &Connection, scope_id: &str) -> Result<Vec<(String, String)>, AppError> {
     scope_repo::list_members(conn, scope_id)
 }
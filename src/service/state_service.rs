@@ -0,0 +1,70 @@
+use crate::domain::{DomainEvent, EventObserver};
+use crate::error::AppError;
+use crate::repository::state_repo;
+use crate::service::authz_service::{self, Access};
+use rusqlite::Connection;
+use serde_json::Value;
+
+pub fn get(
+    conn: &Connection,
+    uid: &str,
+    scope_id: &str,
+    key: &str,
+) -> Result<Option<(Value, String)>, AppError> {
+    authz_service::authorize(conn, uid, scope_id, Access::Read)?;
+    match state_repo::get(conn, scope_id, uid, key)? {
+        Some((raw, updated_at)) => {
+            let value: Value = serde_json::from_str(&raw).map_err(|e| {
+                AppError::storage(format!("corrupt state value for key={key}: {e}"))
+            })?;
+            Ok(Some((value, updated_at)))
+        }
+        None => Ok(None),
+    }
+}
+
+pub fn set(
+    conn: &Connection,
+    uid: &str,
+    scope_id: &str,
+    key: &str,
+    value: &Value,
+    now: &str,
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
+    if key.trim().is_empty() {
+        return Err(AppError::validation("state key must not be empty"));
+    }
+    authz_service::authorize(conn, uid, scope_id, Access::Write)?;
+    state_repo::upsert(conn, scope_id, uid, key, &value.to_string(), now)?;
+    observer.on_event(
+        conn,
+        &DomainEvent::StateSet {
+            scope_id: scope_id.to_string(),
+            uid: uid.to_string(),
+            key: key.to_string(),
+        },
+    )
+}
+
+pub fn delete(
+    conn: &Connection,
+    uid: &str,
+    scope_id: &str,
+    key: &str,
+    observer: &dyn EventObserver,
+) -> Result<usize, AppError> {
+    authz_service::authorize(conn, uid, scope_id, Access::Write)?;
+    let n = state_repo::delete(conn, scope_id, uid, key)?;
+    if n > 0 {
+        observer.on_event(
+            conn,
+            &DomainEvent::StateDeleted {
+                scope_id: scope_id.to_string(),
+                uid: uid.to_string(),
+                key: key.to_string(),
+            },
+        )?;
+    }
+    Ok(n)
+}
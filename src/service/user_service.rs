@@ -1,7 +1,20 @@
+use crate::domain::schema::SchemaClass;
 use crate::domain::{DomainEvent, EventObserver};
-use crate::repository::user_repo;
+use crate::error::AppError;
+use crate::repository::event_repo::{self, EventRow};
+use crate::repository::{
+    bundle_repo, dynamic_table_repo, metric_repo, summary_repo, topk_repo, user_repo,
+};
+use crate::service::reindex_service::{self, ReindexReport};
+use crate::service::{archive_service, compact_service, materializer_service, schema_service};
 use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashSet};
 
+/// Rows referencing a user, per table group. `dynamic_rows` covers user_context schema
+/// tables and `archived_events` the events inside archive files.
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct UserRefCounts {
     pub identities: i64,
     pub scope_members: i64,
@@ -9,6 +22,28 @@ pub struct UserRefCounts {
     pub state: i64,
     pub metrics: i64,
     pub topk: i64,
+    pub summaries: i64,
+    pub dynamic_records: i64,
+    pub dynamic_rows: i64,
+    pub archived_events: i64,
+}
+
+impl UserRefCounts {
+    /// `(name, count)` pairs in report order.
+    pub fn fields(&self) -> [(&'static str, i64); 10] {
+        [
+            ("identities", self.identities),
+            ("scope_members", self.scope_members),
+            ("events", self.events),
+            ("state", self.state),
+            ("metrics", self.metrics),
+            ("topk", self.topk),
+            ("summaries", self.summaries),
+            ("dynamic_records", self.dynamic_records),
+            ("dynamic_rows", self.dynamic_rows),
+            ("archived_events", self.archived_events),
+        ]
+    }
 }
 
 pub fn create(
@@ -17,43 +52,138 @@ pub fn create(
     name: &str,
     now: &str,
     observer: &dyn EventObserver,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     user_repo::insert(conn, uid, name, now)?;
-    observer.on_event(&DomainEvent::UserCreated {
-        uid: uid.to_string(),
-    })?;
+    observer.on_event(
+        conn,
+        &DomainEvent::UserCreated {
+            uid: uid.to_string(),
+        },
+    )?;
     Ok(())
 }
 
-pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, String> {
+pub fn list(conn: &Connection) -> Result<Vec<(String, String)>, AppError> {
     user_repo::list(conn)
 }
 
-pub fn show(conn: &Connection, uid: &str) -> Result<Option<String>, String> {
+pub fn show(conn: &Connection, uid: &str) -> Result<Option<String>, AppError> {
     user_repo::get_name(conn, uid)
 }
 
-pub fn update(conn: &Connection, uid: &str, name: &str, now: &str) -> Result<usize, String> {
-    user_repo::update_name(conn, uid, name, now)
+pub fn update(
+    conn: &Connection,
+    uid: &str,
+    name: &str,
+    now: &str,
+    observer: &dyn EventObserver,
+) -> Result<usize, AppError> {
+    let n = user_repo::update_name(conn, uid, name, now)?;
+    if n > 0 {
+        observer.on_event(
+            conn,
+            &DomainEvent::UserUpdated {
+                uid: uid.to_string(),
+            },
+        )?;
+    }
+    Ok(n)
 }
 
-pub fn merge(conn: &mut Connection, from_uid: &str, to_uid: &str, now: &str) -> Result<(), String> {
+/// Moves everything of `from_uid` to `to_uid` in one transaction, archived events included.
+/// Events of `from_uid` whose idempotency key `to_uid` already used in the same scope are
+/// dropped. Counters and topk of the affected scopes are rebuilt by replaying the merged
+/// events, and summaries are folded into `to_uid`'s after removing the dropped events.
+pub fn merge(
+    conn: &mut Connection,
+    from_uid: &str,
+    to_uid: &str,
+    now: &str,
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
     if !user_repo::exists(conn, from_uid)? {
-        return Err(format!("user not found: {from_uid}"));
+        return Err(AppError::not_found(format!("user not found: {from_uid}")));
     }
     if !user_repo::exists(conn, to_uid)? {
-        return Err(format!("user not found: {to_uid}"));
+        return Err(AppError::not_found(format!("user not found: {to_uid}")));
+    }
+
+    let archived = archive_service::unrestored_events(conn)?;
+    let mut taken = HashSet::new();
+    for ev in event_repo::list_for_replay(conn, Some(to_uid), None)?
+        .iter()
+        .chain(archived.iter().filter(|ev| ev.uid == to_uid))
+    {
+        if let Some(key) = &ev.idempotency_key {
+            taken.insert((ev.scope_id.clone(), key.clone()));
+        }
     }
+    let relink = |row: &EventRow| -> Option<EventRow> {
+        if row.uid != from_uid {
+            return Some(row.clone());
+        }
+        if let Some(key) = &row.idempotency_key {
+            if taken.contains(&(row.scope_id.clone(), key.clone())) {
+                return None;
+            }
+        }
+        Some(EventRow {
+            uid: to_uid.to_string(),
+            ..row.clone()
+        })
+    };
 
+    let rewrites = archive_service::plan_rewrites(conn, &relink)?;
+    let ctx = MergeContext {
+        from_uid,
+        to_uid,
+        now,
+        taken: &taken,
+        archived_scopes: archived
+            .iter()
+            .filter(|ev| ev.uid == from_uid)
+            .map(|ev| ev.scope_id.clone())
+            .collect(),
+        archived: archived.iter().filter_map(relink).collect(),
+        rewrites: &rewrites,
+    };
+    match merge_rows(conn, &ctx, observer) {
+        Ok(()) => archive_service::apply_rewrites(&rewrites),
+        Err(e) => {
+            archive_service::discard_rewrites(&rewrites);
+            Err(e)
+        }
+    }
+}
+
+struct MergeContext<'a> {
+    from_uid: &'a str,
+    to_uid: &'a str,
+    now: &'a str,
+    /// `(scope_id, idempotency_key)` pairs `to_uid` already holds.
+    taken: &'a HashSet<(String, String)>,
+    /// Scopes of the source's archived events.
+    archived_scopes: BTreeSet<String>,
+    /// Unrestored archived events as they read after the merge.
+    archived: Vec<EventRow>,
+    rewrites: &'a [archive_service::ArchiveRewrite],
+}
+
+fn merge_rows(
+    conn: &mut Connection,
+    ctx: &MergeContext<'_>,
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
+    let (from_uid, to_uid, now) = (ctx.from_uid, ctx.to_uid, ctx.now);
     let tx = conn
         .transaction()
-        .map_err(|e| format!("failed to start tx: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to start tx"))?;
 
     tx.execute(
         "UPDATE user_identities SET uid = ?1, updated_at = ?2 WHERE uid = ?3",
         params![to_uid, now, from_uid],
     )
-    .map_err(|e| format!("failed to migrate identities: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to migrate identities"))?;
 
     tx.execute(
         "DELETE FROM scope_members
@@ -62,33 +192,53 @@ pub fn merge(conn: &mut Connection, from_uid: &str, to_uid: &str, now: &str) ->
          )",
         params![from_uid, to_uid],
     )
-    .map_err(|e| format!("failed to dedupe scope members: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to dedupe scope members"))?;
 
     tx.execute(
         "UPDATE scope_members SET uid = ?1 WHERE uid = ?2",
         params![to_uid, from_uid],
     )
-    .map_err(|e| format!("failed to migrate scope members: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to migrate scope members"))?;
 
-    tx.execute(
-        "DELETE FROM events
-         WHERE uid = ?1
-           AND idempotency_key IS NOT NULL
-           AND EXISTS (
-             SELECT 1 FROM events t
-             WHERE t.uid = ?2
-               AND t.scope_id = events.scope_id
-               AND t.idempotency_key = events.idempotency_key
-           )",
-        params![from_uid, to_uid],
-    )
-    .map_err(|e| format!("failed to dedupe events: {e}"))?;
+    // Counters change wherever the source has counters or events, archived or not.
+    let mut scopes = ctx.archived_scopes.clone();
+    scopes.extend(metric_repo::counter_scopes(&tx, from_uid)?);
+
+    // Dropped duplicates leave the source's summaries before those move to the target.
+    let mut dropped = Vec::new();
+    for ev in event_repo::list_for_replay(&tx, Some(from_uid), None)? {
+        scopes.insert(ev.scope_id.clone());
+        let duplicate = ev
+            .idempotency_key
+            .as_ref()
+            .is_some_and(|key| ctx.taken.contains(&(ev.scope_id.clone(), key.clone())));
+        if duplicate {
+            event_repo::delete_by_id(&tx, &ev.event_id)?;
+            dropped.push(ev);
+        }
+    }
+    dropped.extend(
+        ctx.rewrites
+            .iter()
+            .flat_map(|rw| rw.dropped.iter().cloned()),
+    );
+    let mut compacted = Vec::new();
+    for ev in dropped {
+        if summary_repo::unmark_compacted(&tx, &ev.event_id)? {
+            compacted.push(ev);
+        }
+    }
+    let removed = compact_service::fold(&tx, &compacted)?.aggs;
+    compact_service::add_to_summaries(&tx, &removed, -1.0, now)?;
+    let moved = compact_service::summaries_as_aggs(&tx, from_uid, to_uid)?;
+    compact_service::add_to_summaries(&tx, &moved, 1.0, now)?;
+    user_repo::delete_where(&tx, "event_summaries", "uid", from_uid)?;
 
     tx.execute(
         "UPDATE events SET uid = ?1 WHERE uid = ?2",
         params![to_uid, from_uid],
     )
-    .map_err(|e| format!("failed to migrate events: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to migrate events"))?;
 
     tx.execute(
         "INSERT INTO state (scope_id, uid, state_key, value_json, updated_at)
@@ -104,14 +254,16 @@ pub fn merge(conn: &mut Connection, from_uid: &str, to_uid: &str, now: &str) ->
            END",
         params![to_uid, from_uid],
     )
-    .map_err(|e| format!("failed to migrate state: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to migrate state"))?;
 
     tx.execute("DELETE FROM state WHERE uid = ?1", params![from_uid])
-        .map_err(|e| format!("failed to cleanup state: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to cleanup state"))?;
 
+    // Non-counter metrics keep the most recent value; counters are rebuilt below.
     tx.execute(
         "INSERT INTO metrics (scope_id, uid, metric_key, metric_value, metric_json, updated_at)
-         SELECT scope_id, ?1, metric_key, metric_value, metric_json, updated_at FROM metrics WHERE uid = ?2
+         SELECT scope_id, ?1, metric_key, metric_value, metric_json, updated_at FROM metrics
+         WHERE uid = ?2 AND metric_key NOT LIKE 'counter:%'
          ON CONFLICT(scope_id, uid, metric_key) DO UPDATE SET
            metric_value = CASE
              WHEN excluded.updated_at >= metrics.updated_at THEN excluded.metric_value
@@ -127,75 +279,244 @@ pub fn merge(conn: &mut Connection, from_uid: &str, to_uid: &str, now: &str) ->
            END",
         params![to_uid, from_uid],
     )
-    .map_err(|e| format!("failed to migrate metrics: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to migrate metrics"))?;
 
     tx.execute("DELETE FROM metrics WHERE uid = ?1", params![from_uid])
-        .map_err(|e| format!("failed to cleanup metrics: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to cleanup metrics"))?;
 
-    tx.execute(
-        "INSERT INTO topk (scope_id, uid, topic, rank, item_key, weight, updated_at)
-         SELECT scope_id, ?1, topic, rank, item_key, weight, updated_at FROM topk WHERE uid = ?2
-         ON CONFLICT(scope_id, uid, topic, rank) DO UPDATE SET
-           item_key = CASE
-             WHEN excluded.updated_at >= topk.updated_at THEN excluded.item_key
-             ELSE topk.item_key
-           END,
-           weight = CASE
-             WHEN excluded.updated_at >= topk.updated_at THEN excluded.weight
-             ELSE topk.weight
-           END,
-           updated_at = CASE
-             WHEN excluded.updated_at >= topk.updated_at THEN excluded.updated_at
-             ELSE topk.updated_at
-           END",
-        params![to_uid, from_uid],
-    )
-    .map_err(|e| format!("failed to migrate topk: {e}"))?;
+    let mut topk_groups: BTreeSet<(String, String)> = topk_repo::groups_for_uid(&tx, from_uid)?
+        .into_iter()
+        .chain(
+            topk_repo::groups_for_uid(&tx, to_uid)?
+                .into_iter()
+                .filter(|(scope_id, _)| scopes.contains(scope_id)),
+        )
+        .collect();
+    topk_repo::clear_filtered(&tx, Some(from_uid), None, None)?;
+    let mut report = ReindexReport::default();
+    for scope_id in &scopes {
+        metric_repo::clear_counters(&tx, Some(to_uid), Some(scope_id), None)?;
+        let live = event_repo::list_for_replay(&tx, Some(to_uid), Some(scope_id))?;
+        let archived = ctx
+            .archived
+            .iter()
+            .filter(|ev| ev.uid == to_uid && &ev.scope_id == scope_id);
+        for (scope_id, _, topic) in
+            reindex_service::replay(&tx, archived.chain(&live), None, &mut report)?
+        {
+            topk_groups.insert((scope_id, topic));
+        }
+    }
+    for (scope_id, topic) in &topk_groups {
+        materializer_service::rebuild_topk(&tx, scope_id, to_uid, topic, now)?;
+    }
 
-    tx.execute("DELETE FROM topk WHERE uid = ?1", params![from_uid])
-        .map_err(|e| format!("failed to cleanup topk: {e}"))?;
+    user_repo::relink(&tx, "dynamic_records", "uid", from_uid, to_uid)?;
+    for table in user_context_tables(&tx)? {
+        user_repo::relink(&tx, &table, "ref_user_id", from_uid, to_uid)?;
+        if bundle_repo::columns(&tx, &table)?
+            .iter()
+            .any(|c| c == "refuserid")
+        {
+            user_repo::relink(&tx, &table, "refuserid", from_uid, to_uid)?;
+        }
+    }
+    archive_service::record_rewrites(&tx, ctx.rewrites)?;
 
     tx.execute(
         "UPDATE users SET status = 'merged', updated_at = ?1 WHERE uid = ?2",
         params![now, from_uid],
     )
-    .map_err(|e| format!("failed to mark source user as merged: {e}"))?;
+    .map_err(|e| AppError::db(e, "failed to mark source user as merged"))?;
+
+    observer.on_event(
+        &tx,
+        &DomainEvent::UsersMerged {
+            from_uid: from_uid.to_string(),
+            to_uid: to_uid.to_string(),
+        },
+    )?;
 
     tx.commit()
-        .map_err(|e| format!("failed to commit merge: {e}"))?;
+        .map_err(|e| AppError::db(e, "failed to commit merge"))?;
     Ok(())
 }
 
-pub fn ref_counts(conn: &Connection, uid: &str) -> Result<UserRefCounts, String> {
+/// Physical tables of every registered user_context schema version.
+fn user_context_tables(conn: &Connection) -> Result<Vec<String>, AppError> {
+    let mut tables = Vec::new();
+    for def in schema_service::all_versions(conn)? {
+        if def.class != SchemaClass::UserContext {
+            continue;
+        }
+        let table = dynamic_table_repo::table_name_for(&def);
+        if bundle_repo::table_exists(conn, &table)? && !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    Ok(tables)
+}
+
+pub fn ref_counts(conn: &Connection, uid: &str) -> Result<UserRefCounts, AppError> {
+    let mut counts = [0; user_repo::UID_TABLES.len()];
+    for (count, table) in counts.iter_mut().zip(user_repo::UID_TABLES) {
+        *count = user_repo::count_by_uid(conn, table, uid)?;
+    }
+    let [identities, scope_members, events, state, metrics, topk] = counts;
+    let mut dynamic_rows = 0;
+    for table in user_context_tables(conn)? {
+        dynamic_rows += user_repo::count_where(conn, &table, "ref_user_id", uid)?;
+    }
     Ok(UserRefCounts {
-        identities: user_repo::count_user_identities(conn, uid)?,
-        scope_members: user_repo::count_scope_members(conn, uid)?,
-        events: user_repo::count_events(conn, uid)?,
-        state: user_repo::count_state(conn, uid)?,
-        metrics: user_repo::count_metrics(conn, uid)?,
-        topk: user_repo::count_topk(conn, uid)?,
+        identities,
+        scope_members,
+        events,
+        state,
+        metrics,
+        topk,
+        summaries: user_repo::count_by_uid(conn, "event_summaries", uid)?,
+        dynamic_records: user_repo::count_by_uid(conn, "dynamic_records", uid)?,
+        dynamic_rows,
+        archived_events: archive_service::count_user_events(conn, uid)? as i64,
     })
 }
 
-pub fn delete_soft(conn: &Connection, uid: &str, now: &str) -> Result<(), String> {
+/// Every stored row that references `uid`, grouped by table: the `users` row, the
+/// [`user_repo::UID_TABLES`], `event_summaries`, legacy `dynamic_records`, user_context schema
+/// tables (matched on `ref_user_id`) and, as `archived_events`, the user's events inside
+/// unrestored archive files. Empty tables are included so the document is exhaustive.
+pub fn export(conn: &Connection, uid: &str, now: &str) -> Result<Value, AppError> {
+    let user = bundle_repo::dump_rows_where(conn, "users", "uid", uid)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::not_found(format!("user not found: {uid}")))?;
+
+    let mut tables = Map::new();
+    for table in user_repo::UID_TABLES
+        .into_iter()
+        .chain(["event_summaries", "dynamic_records"])
+    {
+        let rows = bundle_repo::dump_rows_where(conn, table, "uid", uid)?;
+        tables.insert(table.to_string(), json!(rows));
+    }
+    for table in user_context_tables(conn)? {
+        let rows = bundle_repo::dump_rows_where(conn, &table, "ref_user_id", uid)?;
+        tables.insert(table, json!(rows));
+    }
+    let archived: Vec<EventRow> = archive_service::unrestored_events(conn)?
+        .into_iter()
+        .filter(|ev| ev.uid == uid)
+        .collect();
+    tables.insert("archived_events".to_string(), json!(archived));
+
+    Ok(json!({
+        "uid": uid,
+        "exported_at": now,
+        "user": user,
+        "tables": tables,
+    }))
+}
+
+fn emit_deleted(
+    conn: &Connection,
+    uid: &str,
+    mode: &str,
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
+    observer.on_event(
+        conn,
+        &DomainEvent::UserDeleted {
+            uid: uid.to_string(),
+            mode: mode.to_string(),
+        },
+    )
+}
+
+pub fn delete_soft(
+    conn: &Connection,
+    uid: &str,
+    now: &str,
+    observer: &dyn EventObserver,
+) -> Result<(), AppError> {
     if !user_repo::exists(conn, uid)? {
-        return Err(format!("user not found: {uid}"));
+        return Err(AppError::not_found(format!("user not found: {uid}")));
     }
     user_repo::set_status(conn, uid, "deleted", now)?;
-    Ok(())
+    emit_deleted(conn, uid, "soft", observer)
 }
 
-pub fn delete_hard(conn: &Connection, uid: &str, now: &str, force: bool) -> Result<(), String> {
+/// Purges `uid` from every tier: identities, memberships, events, state, metrics, topk,
+/// summaries, dynamic records, user_context schema rows and archived events. The `users` row
+/// is replaced by a tombstone and the deletion is published through `observer`, all in one
+/// transaction; rewritten archive files are swapped in only after it commits.
+pub fn delete_hard(
+    conn: &mut Connection,
+    uid: &str,
+    now: &str,
+    force: bool,
+    observer: &dyn EventObserver,
+) -> Result<UserRefCounts, AppError> {
     if !force {
-        return Err("hard delete requires --force".to_string());
+        return Err(AppError::validation("hard delete requires --force"));
     }
     if !user_repo::exists(conn, uid)? {
-        return Err(format!("user not found: {uid}"));
+        return Err(AppError::not_found(format!("user not found: {uid}")));
     }
-    let status = user_repo::get_status(conn, uid)?.unwrap_or_default();
-    if status != "merged" {
-        return Err("hard delete is allowed only for users with status=merged".to_string());
+    let rewrites = archive_service::plan_user_purge(conn, uid)?;
+    match purge(conn, uid, now, &rewrites, observer) {
+        Ok(counts) => {
+            archive_service::apply_rewrites(&rewrites)?;
+            Ok(counts)
+        }
+        Err(e) => {
+            archive_service::discard_rewrites(&rewrites);
+            Err(e)
+        }
     }
-    user_repo::set_status(conn, uid, "deleted", now)?;
-    Ok(())
+}
+
+fn purge(
+    conn: &mut Connection,
+    uid: &str,
+    now: &str,
+    rewrites: &[archive_service::ArchiveRewrite],
+    observer: &dyn EventObserver,
+) -> Result<UserRefCounts, AppError> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| AppError::db(e, "failed to start tx"))?;
+
+    user_repo::delete_compaction_ledger(&tx, uid)?;
+    let mut counts = [0; user_repo::UID_TABLES.len()];
+    for (count, table) in counts.iter_mut().zip(user_repo::UID_TABLES) {
+        *count = user_repo::delete_where(&tx, table, "uid", uid)? as i64;
+    }
+    let [identities, scope_members, events, state, metrics, topk] = counts;
+    let mut dynamic_rows = 0;
+    for table in user_context_tables(&tx)? {
+        dynamic_rows += user_repo::delete_where(&tx, &table, "ref_user_id", uid)? as i64;
+    }
+    archive_service::record_rewrites(&tx, rewrites)?;
+    let counts = UserRefCounts {
+        identities,
+        scope_members,
+        events,
+        state,
+        metrics,
+        topk,
+        summaries: user_repo::delete_where(&tx, "event_summaries", "uid", uid)? as i64,
+        dynamic_records: user_repo::delete_where(&tx, "dynamic_records", "uid", uid)? as i64,
+        dynamic_rows,
+        archived_events: rewrites.iter().map(|rw| rw.dropped.len() as i64).sum(),
+    };
+
+    user_repo::delete(&tx, uid)?;
+    let counts_json = serde_json::to_string(&counts)
+        .map_err(|e| AppError::storage(format!("failed to encode counts: {e}")))?;
+    user_repo::insert_tombstone(&tx, uid, &counts_json, now)?;
+    emit_deleted(&tx, uid, "hard", observer)?;
+
+    tx.commit()
+        .map_err(|e| AppError::db(e, "failed to commit hard delete"))?;
+    Ok(counts)
 }
@@ -0,0 +1,697 @@
+use crate::db::{self, migrate};
+use crate::domain::schema::{SchemaChange, SchemaDef};
+use crate::domain::EventObserver;
+use crate::error::AppError;
+use crate::repository::schema_registry_repo;
+use crate::service::hook_service::{HookConfig, HookObserver};
+use crate::service::identity_service::UserRef;
+use crate::service::ingest_service::{BatchLine, BatchLineOutcome, IngestInput, IngestOutcome};
+use crate::service::outbox_service::OutboxObserver;
+use crate::service::query_service::ScopeSelection;
+use crate::service::schema_service::RegisterReport;
+use crate::service::user_service::UserRefCounts;
+use crate::service::{
+    compact_service, identity_service, ingest_service, query_service, schema_service,
+    scope_service, state_service, user_service,
+};
+use rusqlite::{Connection, Transaction};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Unix seconds, the timestamp format stored in every table.
+pub fn now_ts() -> String {
+    let n = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    n.to_string()
+}
+
+pub fn new_id(prefix: &str) -> String {
+    let n = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{prefix}_{n}")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct User {
+    pub uid: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identity {
+    pub uid: String,
+    pub channel: String,
+    pub channel_user_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Scope {
+    pub scope_id: String,
+    pub scope_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScopeMember {
+    pub scope_id: String,
+    pub uid: String,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatestEvent {
+    pub scope_id: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub event_ts: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metric {
+    pub scope_id: String,
+    pub key: String,
+    pub value: f64,
+    pub json: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopkItem {
+    pub scope_id: String,
+    pub rank: i64,
+    pub item: String,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub period: String,
+    pub period_start: String,
+    pub event_type: String,
+    pub topic: String,
+    pub count: i64,
+    pub sum: f64,
+    pub distinct_items: i64,
+    pub items: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateEntry {
+    pub scope_id: String,
+    pub uid: String,
+    pub key: String,
+    pub value: Value,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaVersion {
+    pub schema_id: String,
+    pub version: String,
+    pub active: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaDiff {
+    pub schema_id: String,
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<SchemaChange>,
+}
+
+impl SchemaDiff {
+    pub fn is_compatible(&self) -> bool {
+        !self.changes.iter().any(|c| c.is_breaking())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaBinding {
+    pub event_type: String,
+    pub schema_id: String,
+    pub mode: String,
+    pub updated_at: String,
+}
+
+/// A handle on one memory db. Mutations run in a transaction with an observer that writes
+/// the projection outbox and runs the configured hooks; post-hook failures are collected for
+/// [`MemoryStore::take_hook_warnings`].
+pub struct MemoryStore {
+    conn: Connection,
+    hooks: HookConfig,
+    hook_warnings: Vec<String>,
+}
+
+type StoreObserver = HookObserver<OutboxObserver>;
+
+impl MemoryStore {
+    /// Opens a db already migrated to the latest schema version.
+    pub fn open(db_path: &str) -> Result<Self, AppError> {
+        Ok(Self::from_connection(db::open_checked(db_path)?))
+    }
+
+    /// Creates the db if needed and applies every pending migration before opening it.
+    pub fn open_and_migrate(db_path: &str) -> Result<Self, AppError> {
+        db::ensure_parent_dir(db_path)?;
+        let mut conn = db::connect(db_path)?;
+        let pending = migrate::plan(&conn, None)?;
+        migrate::apply(&mut conn, &pending, &now_ts())?;
+        Ok(Self::from_connection(conn))
+    }
+
+    fn from_connection(conn: Connection) -> Self {
+        MemoryStore {
+            conn,
+            hooks: HookConfig::default(),
+            hook_warnings: Vec::new(),
+        }
+    }
+
+    pub fn with_hooks(mut self, hooks: HookConfig) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Post-hook failures since the last call; they never undo the committed mutation.
+    pub fn take_hook_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.hook_warnings)
+    }
+
+    /// The underlying connection, for service calls this API does not wrap.
+    pub fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn conn_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Runs `f` with a fresh observer, then the post-hooks once `f` has succeeded.
+    fn observed<T>(
+        &mut self,
+        f: impl FnOnce(&mut Connection, &StoreObserver, &str) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let now = now_ts();
+        let observer = HookObserver::new(self.hooks.clone(), OutboxObserver::new(&now));
+        let out = f(&mut self.conn, &observer, &now)?;
+        self.hook_warnings.extend(observer.run_post_hooks());
+        Ok(out)
+    }
+
+    /// Runs a mutation in one transaction so the outbox rows its observer writes commit with
+    /// it; `f` receives the transaction, the observer and the mutation timestamp.
+    pub fn write<T>(
+        &mut self,
+        f: impl FnOnce(&Transaction<'_>, &dyn EventObserver, &str) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        self.observed(|conn, observer, now| {
+            let tx = conn
+                .transaction()
+                .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+            let out = f(&tx, observer, now)?;
+            tx.commit()
+                .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+            Ok(out)
+        })
+    }
+
+    /// Runs reads in one transaction so a channel identity resolved by `f` and the rows read
+    /// for it come from the same snapshot.
+    fn read<T>(
+        &self,
+        f: impl FnOnce(&Transaction<'_>) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| AppError::db(e, "failed to begin tx"))?;
+        let out = f(&tx)?;
+        tx.commit()
+            .map_err(|e| AppError::db(e, "failed to commit tx"))?;
+        Ok(out)
+    }
+
+    pub fn create_user(&mut self, name: &str) -> Result<User, AppError> {
+        let uid = new_id("u");
+        self.write(|tx, observer, now| user_service::create(tx, &uid, name, now, observer))?;
+        Ok(User {
+            uid,
+            name: name.to_string(),
+        })
+    }
+
+    pub fn list_users(&self) -> Result<Vec<User>, AppError> {
+        Ok(user_service::list(&self.conn)?
+            .into_iter()
+            .map(|(uid, name)| User { uid, name })
+            .collect())
+    }
+
+    pub fn get_user(&self, uid: &str) -> Result<Option<User>, AppError> {
+        Ok(user_service::show(&self.conn, uid)?.map(|name| User {
+            uid: uid.to_string(),
+            name,
+        }))
+    }
+
+    pub fn update_user(&mut self, uid: &str, name: &str) -> Result<User, AppError> {
+        let n =
+            self.write(|tx, observer, now| user_service::update(tx, uid, name, now, observer))?;
+        if n == 0 {
+            return Err(AppError::not_found(format!("user not found: {uid}")));
+        }
+        Ok(User {
+            uid: uid.to_string(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Moves everything `from_uid` owns onto `to_uid`; see the merge contract in the spec.
+    pub fn merge_users(&mut self, from_uid: &str, to_uid: &str) -> Result<(), AppError> {
+        if from_uid == to_uid {
+            return Err(AppError::validation(
+                "--from and --to must be different users",
+            ));
+        }
+        self.observed(|conn, observer, now| {
+            user_service::merge(conn, from_uid, to_uid, now, observer)
+        })
+    }
+
+    /// Rows referencing `uid` in every tier, as purged by a hard delete.
+    pub fn user_ref_counts(&self, uid: &str) -> Result<UserRefCounts, AppError> {
+        user_service::ref_counts(&self.conn, uid)
+    }
+
+    pub fn delete_user_soft(&mut self, uid: &str) -> Result<(), AppError> {
+        self.write(|tx, observer, now| user_service::delete_soft(tx, uid, now, observer))
+    }
+
+    /// Purges `uid` from every tier and leaves a tombstone; refused unless `force`.
+    pub fn delete_user_hard(&mut self, uid: &str, force: bool) -> Result<UserRefCounts, AppError> {
+        self.observed(|conn, observer, now| {
+            user_service::delete_hard(conn, uid, now, force, observer)
+        })
+    }
+
+    /// Subject-access export of everything stored about `uid`.
+    pub fn export_user(&self, uid: &str) -> Result<Value, AppError> {
+        user_service::export(&self.conn, uid, &now_ts())
+    }
+
+    pub fn link_identity(
+        &mut self,
+        uid: &str,
+        channel: &str,
+        channel_user_id: &str,
+    ) -> Result<Identity, AppError> {
+        let identity_id = new_id("ident");
+        self.write(|tx, observer, now| {
+            identity_service::link(
+                tx,
+                &identity_id,
+                uid,
+                channel,
+                channel_user_id,
+                now,
+                observer,
+            )
+        })?;
+        Ok(Identity {
+            uid: uid.to_string(),
+            channel: channel.to_string(),
+            channel_user_id: channel_user_id.to_string(),
+        })
+    }
+
+    pub fn resolve_identity(
+        &self,
+        channel: &str,
+        channel_user_id: &str,
+    ) -> Result<Option<Identity>, AppError> {
+        Ok(
+            identity_service::resolve(&self.conn, channel, channel_user_id)?.map(|uid| Identity {
+                uid,
+                channel: channel.to_string(),
+                channel_user_id: channel_user_id.to_string(),
+            }),
+        )
+    }
+
+    pub fn unlink_identity(
+        &mut self,
+        channel: &str,
+        channel_user_id: &str,
+    ) -> Result<(), AppError> {
+        let n = self.write(|tx, observer, _| {
+            identity_service::unlink(tx, channel, channel_user_id, observer)
+        })?;
+        if n == 0 {
+            return Err(AppError::not_found(format!(
+                "identity not found: {channel}:{channel_user_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn create_scope(&mut self, scope_id: &str, scope_type: &str) -> Result<Scope, AppError> {
+        self.write(|tx, observer, now| {
+            scope_service::create(tx, scope_id, scope_type, now, observer)
+        })?;
+        Ok(Scope {
+            scope_id: scope_id.to_string(),
+            scope_type: scope_type.to_string(),
+        })
+    }
+
+    pub fn add_scope_member(
+        &mut self,
+        scope_id: &str,
+        uid: &str,
+        role: &str,
+    ) -> Result<ScopeMember, AppError> {
+        self.write(|tx, observer, now| {
+            scope_service::add_member(tx, scope_id, uid, role, now, observer)
+        })?;
+        Ok(ScopeMember {
+            scope_id: scope_id.to_string(),
+            uid: uid.to_string(),
+            role: role.to_string(),
+        })
+    }
+
+    pub fn list_scopes(&self) -> Result<Vec<Scope>, AppError> {
+        Ok(scope_service::list(&self.conn)?
+            .into_iter()
+            .map(|(scope_id, scope_type)| Scope {
+                scope_id,
+                scope_type,
+            })
+            .collect())
+    }
+
+    pub fn scope_members(&self, scope_id: &str) -> Result<Vec<ScopeMember>, AppError> {
+        Ok(scope_service::members(&self.conn, scope_id)?
+            .into_iter()
+            .map(|(uid, role)| ScopeMember {
+                scope_id: scope_id.to_string(),
+                uid,
+                role,
+            })
+            .collect())
+    }
+
+    /// Validates, stores and materializes one event; a repeated `idempotency_key` is a no-op.
+    pub fn ingest_event(
+        &mut self,
+        user: &UserRef,
+        scope_id: &str,
+        event_type: &str,
+        payload: &Value,
+        idempotency_key: Option<&str>,
+    ) -> Result<IngestOutcome, AppError> {
+        let event_id = new_id("evt");
+        self.observed(|conn, observer, now| {
+            ingest_service::ingest(
+                conn,
+                IngestInput {
+                    user,
+                    scope_id,
+                    event_type,
+                    payload,
+                    idempotency_key,
+                    event_id: &event_id,
+                    now,
+                },
+                observer,
+            )
+        })
+    }
+
+    /// Ingests parsed batch lines; with `atomic`, one failure rolls back every line and no
+    /// post-hooks run.
+    pub fn ingest_batch(
+        &mut self,
+        lines: Vec<BatchLine>,
+        atomic: bool,
+    ) -> Result<Vec<(usize, BatchLineOutcome)>, AppError> {
+        self.observed(|conn, observer, now| {
+            let results = ingest_service::ingest_batch(conn, lines, atomic, now, observer)?;
+            if results
+                .iter()
+                .any(|(_, o)| matches!(o, BatchLineOutcome::RolledBack))
+            {
+                observer.discard();
+            }
+            Ok(results)
+        })
+    }
+
+    /// The most recent event in the first scope of the read order that has one.
+    pub fn latest_event(
+        &self,
+        user: &UserRef,
+        scopes: &ScopeSelection,
+    ) -> Result<Option<LatestEvent>, AppError> {
+        self.read(|tx| {
+            let uid = user.resolve(tx)?;
+            let order = query_service::read_order(tx, &uid, scopes)?;
+            Ok(query_service::latest_in_order(tx, &uid, &order)?.map(
+                |(scope_id, event_id, event_type, event_ts)| LatestEvent {
+                    scope_id,
+                    event_id,
+                    event_type,
+                    event_ts,
+                },
+            ))
+        })
+    }
+
+    pub fn metrics(
+        &self,
+        user: &UserRef,
+        scopes: &ScopeSelection,
+        key: Option<&str>,
+        prefix: Option<&str>,
+    ) -> Result<Vec<Metric>, AppError> {
+        self.read(|tx| {
+            let uid = user.resolve(tx)?;
+            let order = query_service::read_order(tx, &uid, scopes)?;
+            Ok(
+                query_service::metric_in_order(tx, &uid, &order, key, prefix)?
+                    .into_iter()
+                    .map(|(scope_id, key, value, json)| Metric {
+                        scope_id,
+                        key,
+                        value,
+                        json,
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    pub fn topk(
+        &self,
+        user: &UserRef,
+        scopes: &ScopeSelection,
+        topic: &str,
+        limit: usize,
+    ) -> Result<Vec<TopkItem>, AppError> {
+        self.read(|tx| {
+            let uid = user.resolve(tx)?;
+            let order = query_service::read_order(tx, &uid, scopes)?;
+            Ok(
+                query_service::topk_in_order(tx, &uid, &order, topic, limit)?
+                    .into_iter()
+                    .map(|(scope_id, rank, item, weight)| TopkItem {
+                        scope_id,
+                        rank,
+                        item,
+                        weight,
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// Weekly or monthly summaries built by `admin compact`.
+    pub fn summaries(
+        &self,
+        user: &UserRef,
+        scope_id: &str,
+        period: &str,
+        event_type: Option<&str>,
+        topic: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Summary>, AppError> {
+        self.read(|tx| {
+            let uid = user.resolve(tx)?;
+            compact_service::summary(tx, &uid, scope_id, period, event_type, topic, limit)
+        })?
+        .into_iter()
+        .map(|r| {
+            let items = serde_json::from_str(&r.items_json)
+                .map_err(|e| AppError::storage(format!("corrupt summary items: {e}")))?;
+            Ok(Summary {
+                period: period.to_string(),
+                period_start: r.period_start,
+                event_type: r.event_type,
+                topic: r.topic,
+                count: r.event_count,
+                sum: r.value_sum,
+                distinct_items: r.distinct_items,
+                items,
+            })
+        })
+        .collect()
+    }
+
+    /// One state entry; a missing entry is `not_found` naming the resolved uid.
+    pub fn get_state(
+        &self,
+        user: &UserRef,
+        scope_id: &str,
+        key: &str,
+    ) -> Result<StateEntry, AppError> {
+        self.read(|tx| {
+            let uid = user.resolve(tx)?;
+            let Some((value, updated_at)) = state_service::get(tx, &uid, scope_id, key)? else {
+                return Err(AppError::not_found(format!(
+                    "state not found: scope_id={scope_id} uid={uid} key={key}"
+                )));
+            };
+            Ok(StateEntry {
+                scope_id: scope_id.to_string(),
+                uid,
+                key: key.to_string(),
+                value,
+                updated_at,
+            })
+        })
+    }
+
+    pub fn set_state(
+        &mut self,
+        user: &UserRef,
+        scope_id: &str,
+        key: &str,
+        value: &Value,
+    ) -> Result<StateEntry, AppError> {
+        let (uid, updated_at) = self.write(|tx, observer, now| {
+            let uid = user.resolve(tx)?;
+            state_service::set(tx, &uid, scope_id, key, value, now, observer)?;
+            Ok((uid, now.to_string()))
+        })?;
+        Ok(StateEntry {
+            scope_id: scope_id.to_string(),
+            uid,
+            key: key.to_string(),
+            value: value.clone(),
+            updated_at,
+        })
+    }
+
+    /// Deletes one state entry; returns the resolved uid.
+    pub fn delete_state(
+        &mut self,
+        user: &UserRef,
+        scope_id: &str,
+        key: &str,
+    ) -> Result<String, AppError> {
+        let (uid, n) = self.write(|tx, observer, _| {
+            let uid = user.resolve(tx)?;
+            let n = state_service::delete(tx, &uid, scope_id, key, observer)?;
+            Ok((uid, n))
+        })?;
+        if n == 0 {
+            return Err(AppError::not_found(format!(
+                "state not found: scope_id={scope_id} uid={uid} key={key}"
+            )));
+        }
+        Ok(uid)
+    }
+
+    /// Registers `def` (already validated) with its raw JSON; see `schema_service::register`.
+    pub fn register_schema(
+        &mut self,
+        def: &SchemaDef,
+        raw: &str,
+        allow_breaking: bool,
+        migrate_data: bool,
+    ) -> Result<RegisterReport, AppError> {
+        self.observed(|conn, observer, now| {
+            schema_service::register(conn, def, raw, allow_breaking, migrate_data, now, observer)
+        })
+    }
+
+    pub fn list_schemas(&self) -> Result<Vec<SchemaVersion>, AppError> {
+        Ok(schema_registry_repo::list(&self.conn)?
+            .into_iter()
+            .map(
+                |(schema_id, version, is_active, created_at)| SchemaVersion {
+                    schema_id,
+                    version,
+                    active: is_active == 1,
+                    created_at,
+                },
+            )
+            .collect())
+    }
+
+    pub fn diff_schema(
+        &self,
+        schema_id: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<SchemaDiff, AppError> {
+        let (from, to, changes) = schema_service::diff(&self.conn, schema_id, from, to)?;
+        Ok(SchemaDiff {
+            schema_id: schema_id.to_string(),
+            from,
+            to,
+            changes,
+        })
+    }
+
+    pub fn bind_schema(
+        &mut self,
+        event_type: &str,
+        schema_id: &str,
+        mode: &str,
+    ) -> Result<SchemaBinding, AppError> {
+        let now = now_ts();
+        schema_service::bind(&self.conn, event_type, schema_id, mode, &now)?;
+        Ok(SchemaBinding {
+            event_type: event_type.to_string(),
+            schema_id: schema_id.to_string(),
+            mode: mode.to_string(),
+            updated_at: now,
+        })
+    }
+
+    pub fn unbind_schema(&mut self, event_type: &str) -> Result<(), AppError> {
+        if schema_service::unbind(&self.conn, event_type)? == 0 {
+            return Err(AppError::not_found(format!(
+                "schema binding not found: event_type={event_type}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn schema_bindings(&self) -> Result<Vec<SchemaBinding>, AppError> {
+        Ok(schema_service::bindings(&self.conn)?
+            .into_iter()
+            .map(|(event_type, schema_id, mode, updated_at)| SchemaBinding {
+                event_type,
+                schema_id,
+                mode,
+                updated_at,
+            })
+            .collect())
+    }
+}
@@ -618,6 +618,21 @@ fn ingest_meal_rated_updates_food_topk() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("meal.json");
     fs::write(&event_file, r#"{"cuisine":"korean"}"#).unwrap();
     let event_file_str = event_file.to_string_lossy().to_string();
@@ -725,6 +740,21 @@ fn ingest_meal_rated_requires_cuisine() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("meal-no-cuisine.json");
     fs::write(&event_file, r#"{"rating":5}"#).unwrap();
     let event_file_str = event_file.to_string_lossy().to_string();
@@ -784,6 +814,21 @@ fn ingest_expense_logged_requires_category() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("expense-no-category.json");
     fs::write(&event_file, r#"{"amount":12000}"#).unwrap();
     let event_file_str = event_file.to_string_lossy().to_string();
@@ -910,6 +955,21 @@ fn topk_orders_by_frequency() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let e1 = dir.path().join("m1.json");
     let e2 = dir.path().join("m2.json");
     let e3 = dir.path().join("m3.json");
@@ -993,6 +1053,21 @@ fn query_latest_returns_most_recent_event() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let meal = dir.path().join("meal.json");
     let exp = dir.path().join("exp.json");
     fs::write(&meal, r#"{"cuisine":"korean"}"#).unwrap();
@@ -1082,6 +1157,21 @@ fn query_topk_respects_limit() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     for (name, cuisine) in [("a", "korean"), ("b", "japanese"), ("c", "thai")] {
         let f = dir.path().join(format!("{name}.json"));
         fs::write(&f, format!("{{\"cuisine\":\"{cuisine}\"}}")).unwrap();
@@ -1157,6 +1247,21 @@ fn ingest_with_same_idempotency_key_is_ignored() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("meal.json");
     fs::write(&event_file, r#"{"cuisine":"korean"}"#).unwrap();
     let event_file_str = event_file.to_string_lossy().to_string();
@@ -1303,6 +1408,21 @@ fn ingest_expense_logged_updates_spend_category_topk() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("expense.json");
     fs::write(&event_file, r#"{"category":"coffee"}"#).unwrap();
 
@@ -1378,6 +1498,21 @@ fn query_metric_by_key_and_prefix() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("meal.json");
     fs::write(&event_file, r#"{"cuisine":"korean"}"#).unwrap();
 
@@ -1481,6 +1616,21 @@ fn ingest_request_logged_updates_request_pattern_topk() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("request.json");
     fs::write(&event_file, r#"{"pattern":"restaurant_reco"}"#).unwrap();
 
@@ -1556,6 +1706,21 @@ fn ingest_request_logged_requires_pattern() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let event_file = dir.path().join("request-bad.json");
     fs::write(&event_file, r#"{"foo":"bar"}"#).unwrap();
 
@@ -1634,6 +1799,21 @@ fn query_topk_supports_json_output() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let f = dir.path().join("m.json");
     fs::write(&f, r#"{"cuisine":"korean"}"#).unwrap();
     let mut ingest = bin();
@@ -1710,6 +1890,21 @@ fn query_latest_supports_json_output() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let f = dir.path().join("meal.json");
     fs::write(&f, r#"{"cuisine":"korean"}"#).unwrap();
     let mut ingest = bin();
@@ -1785,6 +1980,21 @@ fn query_metric_supports_json_key_output() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let f = dir.path().join("meal.json");
     fs::write(&f, r#"{"cuisine":"korean"}"#).unwrap();
     let mut ingest = bin();
@@ -1861,6 +2071,21 @@ fn query_metric_supports_json_prefix_output() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let f = dir.path().join("expense.json");
     fs::write(&f, r#"{"category":"coffee"}"#).unwrap();
     let mut ingest = bin();
@@ -1934,6 +2159,21 @@ fn query_topk_json_empty_returns_array() {
         .assert()
         .success();
 
+    let mut add_member = bin();
+    add_member
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "private:test",
+            "--uid",
+            &uid,
+        ])
+        .assert()
+        .success();
+
     let mut topk = bin();
     topk.args([
         "--db",
@@ -2087,13 +2327,19 @@ fn user_merge_moves_relations_and_marks_source_merged() {
     .unwrap();
     conn.execute(
         "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, idempotency_key, created_at)
-         VALUES ('evt_from_dup', 'u_from', 'shared:couple', 'meal.rated', '10', '{}', 'dup-key', '10')",
+         VALUES ('evt_from_dup', 'u_from', 'shared:couple', 'meal.rated', '10', '{\"cuisine\":\"korean\"}', 'dup-key', '10')",
         [],
     )
     .unwrap();
     conn.execute(
         "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, idempotency_key, created_at)
-         VALUES ('evt_to_dup', 'u_to', 'shared:couple', 'meal.rated', '11', '{}', 'dup-key', '11')",
+         VALUES ('evt_from_2', 'u_from', 'shared:couple', 'meal.rated', '12', '{\"cuisine\":\"korean\"}', NULL, '12')",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, idempotency_key, created_at)
+         VALUES ('evt_to_dup', 'u_to', 'shared:couple', 'meal.rated', '11', '{\"cuisine\":\"korean\"}', 'dup-key', '11')",
         [],
     )
     .unwrap();
@@ -2105,7 +2351,8 @@ fn user_merge_moves_relations_and_marks_source_merged() {
     .unwrap();
     conn.execute(
         "INSERT INTO metrics (scope_id, uid, metric_key, metric_value, metric_json, updated_at)
-         VALUES ('shared:couple', 'u_from', 'counter:food_pref:korean', 2.0, NULL, '100')",
+         VALUES ('shared:couple', 'u_from', 'counter:food_pref:korean', 2.0, NULL, '100'),
+                ('shared:couple', 'u_to', 'counter:food_pref:korean', 1.0, NULL, '100')",
         [],
     )
     .unwrap();
@@ -2170,14 +2417,15 @@ fn user_merge_moves_relations_and_marks_source_merged() {
         .unwrap();
     assert_eq!(state_uid, "u_to");
 
-    let metric_uid: String = conn
+    // The duplicate event counts once: evt_to_dup plus evt_from_2.
+    let (metric_uid, korean): (String, f64) = conn
         .query_row(
-            "SELECT uid FROM metrics WHERE scope_id='shared:couple' AND metric_key='counter:food_pref:korean'",
+            "SELECT uid, metric_value FROM metrics WHERE scope_id='shared:couple' AND metric_key='counter:food_pref:korean'",
             [],
-            |r| r.get(0),
+            |r| Ok((r.get(0)?, r.get(1)?)),
         )
         .unwrap();
-    assert_eq!(metric_uid, "u_to");
+    assert_eq!((metric_uid.as_str(), korean), ("u_to", 2.0));
 
     let topk_uid: String = conn
         .query_row(
@@ -2195,3 +2443,3554 @@ fn user_merge_moves_relations_and_marks_source_merged() {
         .unwrap();
     assert_eq!(from_status, "merged");
 }
+
+#[test]
+fn state_set_get_delete_flow() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("state-crud.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES ('u_state', 'State', 'active', '1', '1')",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scopes (scope_id, scope_type, created_at) VALUES ('shared:couple', 'shared', '1')",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES ('shared:couple', 'u_state', 'member', '1')",
+        [],
+    )
+    .unwrap();
+
+    let mut set = bin();
+    set.args([
+        "--db",
+        &db_str,
+        "state",
+        "set",
+        "--uid",
+        "u_state",
+        "--scope",
+        "shared:couple",
+        "--key",
+        "travel_food_style",
+        "--value",
+        r#"{"spicy":0.7}"#,
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("set state"));
+
+    let mut get = bin();
+    get.args([
+        "--db",
+        &db_str,
+        "--json",
+        "state",
+        "get",
+        "--uid",
+        "u_state",
+        "--scope",
+        "shared:couple",
+        "--key",
+        "travel_food_style",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(r#""value":{"spicy":0.7}"#));
+
+    let mut delete = bin();
+    delete
+        .args([
+            "--db",
+            &db_str,
+            "state",
+            "delete",
+            "--uid",
+            "u_state",
+            "--scope",
+            "shared:couple",
+            "--key",
+            "travel_food_style",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("deleted state"));
+
+    let mut missing = bin();
+    missing
+        .args([
+            "--db",
+            &db_str,
+            "state",
+            "get",
+            "--uid",
+            "u_state",
+            "--scope",
+            "shared:couple",
+            "--key",
+            "travel_food_style",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("state not found"));
+}
+
+#[test]
+fn state_set_rejects_invalid_json_and_non_members() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("state-guard.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES ('u_out', 'Out', 'active', '1', '1')",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scopes (scope_id, scope_type, created_at) VALUES ('shared:couple', 'shared', '1')",
+        [],
+    )
+    .unwrap();
+
+    let mut bad_json = bin();
+    bad_json
+        .args([
+            "--db",
+            &db_str,
+            "state",
+            "set",
+            "--uid",
+            "u_out",
+            "--scope",
+            "shared:couple",
+            "--key",
+            "k",
+            "--value",
+            "{not-json}",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid json value"));
+
+    let mut non_member = bin();
+    non_member
+        .args([
+            "--db",
+            &db_str,
+            "state",
+            "set",
+            "--uid",
+            "u_out",
+            "--scope",
+            "shared:couple",
+            "--key",
+            "k",
+            "--value",
+            "1",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not a member"));
+}
+
+fn seed_user_and_scope(db_path: &std::path::Path, uid: &str, scope_id: &str) {
+    let conn = Connection::open(db_path).unwrap();
+    conn.execute(
+        "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES (?1, ?1, 'active', '1', '1')",
+        [uid],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scopes (scope_id, scope_type, created_at) VALUES (?1, 'private', '1')",
+        [scope_id],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES (?1, ?2, 'owner', '1')",
+        [scope_id, uid],
+    )
+    .unwrap();
+}
+
+#[test]
+fn ingest_batch_reports_per_line_results() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("batch.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let batch = dir.path().join("events.ndjson");
+    fs::write(
+        &batch,
+        concat!(
+            r#"{"uid":"u_b","scope":"private:u_b","type":"meal.rated","payload":{"cuisine":"korean"},"idempotency_key":"k1"}"#,
+            "\n",
+            r#"{"uid":"u_b","scope":"private:u_b","type":"meal.rated","payload":{"cuisine":"korean"},"idempotency_key":"k1"}"#,
+            "\n\n",
+            r#"{"uid":"u_b","scope":"private:u_b","type":"meal.rated","payload":{}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = bin();
+    cmd.args([
+        "--db",
+        &db_str,
+        "ingest",
+        "batch",
+        "--file",
+        batch.to_string_lossy().as_ref(),
+    ])
+    .assert()
+    .failure()
+    .stdout(
+        predicate::str::contains("line=1 status=inserted")
+            .and(predicate::str::contains("line=2 status=duplicate"))
+            .and(predicate::str::contains("line=4 status=error"))
+            .and(predicate::str::contains("inserted=1 duplicate=1 errors=1")),
+    );
+
+    let conn = Connection::open(&db_path).unwrap();
+    let event_count: i64 = conn
+        .query_row("SELECT COUNT(1) FROM events", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(event_count, 1);
+}
+
+#[test]
+fn ingest_batch_atomic_rolls_back_on_error_and_reads_stdin() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("batch-atomic.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let good = r#"{"uid":"u_b","scope_id":"private:u_b","event_type":"expense.logged","payload":{"category":"food"}}"#;
+    let bad = "{not-json}";
+
+    let mut failing = bin();
+    failing
+        .args([
+            "--db", &db_str, "ingest", "batch", "--file", "-", "--atomic",
+        ])
+        .write_stdin(format!("{good}\n{bad}\n"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("line=1 status=rolled_back"))
+        .stderr(predicate::str::contains("batch rolled back"));
+
+    let conn = Connection::open(&db_path).unwrap();
+    let event_count: i64 = conn
+        .query_row("SELECT COUNT(1) FROM events", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(event_count, 0);
+
+    let mut ok = bin();
+    ok.args([
+        "--db", &db_str, "--json", "ingest", "batch", "--file", "-", "--atomic",
+    ])
+    .write_stdin(format!("{good}\n{good}\n"))
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(r#""inserted":2"#));
+
+    let weight: f64 = conn
+        .query_row(
+            "SELECT weight FROM topk WHERE uid='u_b' AND topic='spend_category' AND rank=1",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(weight, 2.0);
+}
+
+#[test]
+fn materializer_register_applies_rules_at_ingest() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("materializer.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_m", "private:u_m");
+
+    let counter_rule = dir.path().join("movie-counter.json");
+    fs::write(
+        &counter_rule,
+        r#"{"rule_id":"movie.genre","event_type":"movie.watched","item_path":"$.movie.genre","topic":"movie_genre","delta":"$.minutes","target":"topk"}"#,
+    )
+    .unwrap();
+    let state_rule = dir.path().join("movie-state.json");
+    fs::write(
+        &state_rule,
+        r#"{"rule_id":"movie.last","event_type":"movie.watched","item_path":"$.movie","topic":"last_movie","target":"state"}"#,
+    )
+    .unwrap();
+
+    for rule in [&counter_rule, &state_rule] {
+        let mut register = bin();
+        register
+            .args([
+                "--db",
+                &db_str,
+                "materializer",
+                "register",
+                "--file",
+                rule.to_string_lossy().as_ref(),
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("registered materializer"));
+    }
+
+    let mut list = bin();
+    list.args(["--db", &db_str, "materializer", "list"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("rule_id=movie.genre").and(predicate::str::contains(
+                "rule_id=builtin.meal_rated.food_pref",
+            )),
+        );
+
+    let batch = concat!(
+        r#"{"uid":"u_m","scope":"private:u_m","type":"movie.watched","payload":{"movie":{"title":"Up","genre":"animation"},"minutes":96}}"#,
+        "\n",
+        r#"{"uid":"u_m","scope":"private:u_m","type":"movie.watched","payload":{"movie":{"title":"Alien","genre":"scifi"},"minutes":117}}"#,
+        "\n",
+    );
+    let mut ingest = bin();
+    ingest
+        .args(["--db", &db_str, "ingest", "batch", "--file", "-"])
+        .write_stdin(batch)
+        .assert()
+        .success();
+
+    let mut topk = bin();
+    topk.args([
+        "--db",
+        &db_str,
+        "query",
+        "topk",
+        "--uid",
+        "u_m",
+        "--scope",
+        "private:u_m",
+        "--topic",
+        "movie_genre",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("rank=1 item=scifi weight=117"));
+
+    let conn = Connection::open(&db_path).unwrap();
+    let last: String = conn
+        .query_row(
+            "SELECT value_json FROM state WHERE uid='u_m' AND state_key='last_movie'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert!(last.contains("Alien"));
+
+    let missing = dir.path().join("missing.json");
+    fs::write(&missing, r#"{"movie":{"title":"NoGenre"},"minutes":5}"#).unwrap();
+    let mut rejected = bin();
+    rejected
+        .args([
+            "--db",
+            &db_str,
+            "ingest",
+            "event",
+            "--uid",
+            "u_m",
+            "--scope",
+            "private:u_m",
+            "--type",
+            "movie.watched",
+            "--file",
+            missing.to_string_lossy().as_ref(),
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "movie.watched requires string field: movie.genre",
+        ));
+}
+
+#[test]
+fn materializer_disable_stops_builtin_rule() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("materializer-disable.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_m", "private:u_m");
+
+    let mut disable = bin();
+    disable
+        .args([
+            "--db",
+            &db_str,
+            "materializer",
+            "disable",
+            "--id",
+            "builtin.meal_rated.food_pref",
+        ])
+        .assert()
+        .success();
+
+    let mut ingest = bin();
+    ingest
+        .args(["--db", &db_str, "ingest", "batch", "--file", "-"])
+        .write_stdin(
+            r#"{"uid":"u_m","scope":"private:u_m","type":"meal.rated","payload":{"note":"no cuisine"}}"#,
+        )
+        .assert()
+        .success();
+
+    // Re-running migrate must not re-enable the seeded rule.
+    migrate_db(&db_str);
+    let conn = Connection::open(&db_path).unwrap();
+    let active: i64 = conn
+        .query_row(
+            "SELECT is_active FROM materializer_rules WHERE rule_id='builtin.meal_rated.food_pref'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(active, 0);
+    let topk_rows: i64 = conn
+        .query_row("SELECT COUNT(1) FROM topk", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(topk_rows, 0);
+}
+
+#[test]
+fn admin_reindex_rebuilds_counters_and_topk_from_events() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("reindex.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_r", "private:u_r");
+
+    let batch = concat!(
+        r#"{"uid":"u_r","scope":"private:u_r","type":"meal.rated","payload":{"cuisine":"korean"}}"#,
+        "\n",
+        r#"{"uid":"u_r","scope":"private:u_r","type":"meal.rated","payload":{"cuisine":"korean"}}"#,
+        "\n",
+        r#"{"uid":"u_r","scope":"private:u_r","type":"meal.rated","payload":{"cuisine":"thai"}}"#,
+        "\n",
+    );
+    let mut ingest = bin();
+    ingest
+        .args(["--db", &db_str, "ingest", "batch", "--file", "-"])
+        .write_stdin(batch)
+        .assert()
+        .success();
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "UPDATE metrics SET metric_value = 99 WHERE metric_key = 'counter:food_pref:thai';
+         DELETE FROM topk;
+         INSERT INTO metrics (scope_id, uid, metric_key, metric_value, metric_json, updated_at)
+         VALUES ('private:u_r', 'u_r', 'invest_style', NULL, '{\"style\":\"growth\"}', '1');",
+    )
+    .unwrap();
+
+    let mut reindex = bin();
+    reindex
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "reindex",
+            "--uid",
+            "u_r",
+            "--topic",
+            "food_pref",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "reindexed events=3 skipped=0 metrics_cleared=2 topk_cleared=0 counters=3 topk_rebuilt=1 archived=0",
+        ));
+
+    let mut topk = bin();
+    topk.args([
+        "--db",
+        &db_str,
+        "query",
+        "topk",
+        "--uid",
+        "u_r",
+        "--scope",
+        "private:u_r",
+        "--topic",
+        "food_pref",
+    ])
+    .assert()
+    .success()
+    .stdout(
+        predicate::str::contains("rank=1 item=korean weight=2")
+            .and(predicate::str::contains("rank=2 item=thai weight=1")),
+    );
+
+    let non_counter: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM metrics WHERE metric_key = 'invest_style'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(non_counter, 1);
+}
+
+#[test]
+fn admin_reindex_topic_filter_treats_underscore_literally() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("reindex-like.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_l", "private:u_l");
+
+    let rule = dir.path().join("snack-rule.json");
+    fs::write(
+        &rule,
+        r#"{"rule_id":"snack.kind","event_type":"snack.rated","item_path":"$.kind","topic":"foodXpref","target":"counter"}"#,
+    )
+    .unwrap();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "materializer",
+            "register",
+            "--file",
+            rule.to_string_lossy().as_ref(),
+        ])
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "ingest", "batch", "--file", "-"])
+        .write_stdin(concat!(
+            r#"{"uid":"u_l","scope":"private:u_l","type":"snack.rated","payload":{"kind":"chips"}}"#,
+            "\n",
+            r#"{"uid":"u_l","scope":"private:u_l","type":"meal.rated","payload":{"cuisine":"thai"}}"#,
+            "\n",
+        ))
+        .assert()
+        .success();
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "reindex",
+            "--uid",
+            "u_l",
+            "--topic",
+            "food_pref",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("metrics_cleared=1 "));
+
+    let conn = Connection::open(&db_path).unwrap();
+    let chips: f64 = conn
+        .query_row(
+            "SELECT metric_value FROM metrics WHERE metric_key = 'counter:foodXpref:chips'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(chips, 1.0);
+    let topk_items: Vec<String> = conn
+        .prepare("SELECT item_key FROM topk WHERE uid = 'u_l' AND topic = 'food_pref'")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(topk_items, ["thai"]);
+}
+
+#[test]
+fn admin_archive_skips_non_numeric_ts_and_reindex_replays_archives() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("archive-reindex.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    let archive_str = dir.path().join("cold").to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_z", "private:u_z");
+
+    let conn = Connection::open(&db_path).unwrap();
+    for (id, ts, cuisine) in [
+        ("evt_jan", "1768435200", "thai"),
+        ("evt_bad", "yesterday", "korean"),
+    ] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, 'u_z', 'private:u_z', 'meal.rated', ?2, ?3, ?2)",
+            [id, ts, &format!(r#"{{"cuisine":"{cuisine}"}}"#)],
+        )
+        .unwrap();
+    }
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-01",
+            "--dir",
+            &archive_str,
+        ])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("archived month=2026-01 rows=1")
+                .and(predicate::str::contains("1970").not()),
+        )
+        .stderr(predicate::str::contains(
+            "warning: skipped 1 events with a non-numeric event_ts",
+        ));
+    let remaining: String = conn
+        .query_row("SELECT event_id FROM events", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(remaining, "evt_bad");
+
+    bin()
+        .args(["--db", &db_str, "admin", "reindex", "--uid", "u_z"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "reindexed events=2 skipped=0 metrics_cleared=0 topk_cleared=0 counters=2 topk_rebuilt=1 archived=1",
+        ));
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "query",
+            "topk",
+            "--uid",
+            "u_z",
+            "--scope",
+            "private:u_z",
+            "--topic",
+            "food_pref",
+        ])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("item=thai weight=1")
+                .and(predicate::str::contains("item=korean weight=1")),
+        );
+}
+
+#[test]
+fn admin_archive_moves_months_to_gzip_and_restores() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("archive.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    let archive_dir = dir.path().join("cold");
+    let archive_str = archive_dir.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+
+    let conn = Connection::open(&db_path).unwrap();
+    for (id, ts) in [
+        ("evt_jan", "1768435200"),
+        ("evt_feb_1", "1770681600"),
+        ("evt_feb_2", "1772236800"),
+        ("evt_mar", "1772323200"),
+    ] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, 'u_a', 'private:u_a', 'note.added', ?2, '{\"n\":1}', ?2)",
+            [id, ts],
+        )
+        .unwrap();
+    }
+
+    let mut archive = bin();
+    archive
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-02",
+            "--dir",
+            &archive_str,
+        ])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("archived month=2026-01 rows=1")
+                .and(predicate::str::contains("archived month=2026-02 rows=2")),
+        );
+
+    let remaining: Vec<String> = conn
+        .prepare("SELECT event_id FROM events")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(remaining, vec!["evt_mar".to_string()]);
+
+    let (file, rows): (String, i64) = conn
+        .query_row(
+            "SELECT file_path, row_count FROM archive_manifest WHERE month='2026-02'",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(rows, 2);
+    assert!(file.ends_with(".ndjson.gz"));
+    assert!(std::path::Path::new(&file).exists());
+
+    let mut restore = bin();
+    restore
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-02",
+            "--restore",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "restored month=2026-02 archives=1 rows=2 skipped=0",
+        ));
+
+    let restored: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM events WHERE event_id IN ('evt_feb_1', 'evt_feb_2')",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(restored, 2);
+
+    let mut again = bin();
+    again
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-02",
+            "--restore",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no archived events"));
+}
+
+#[test]
+fn admin_archive_restore_rejects_tampered_file() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("archive-tamper.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+         VALUES ('evt_jan', 'u_a', 'private:u_a', 'note.added', '1768435200', '{}', '1768435200')",
+        [],
+    )
+    .unwrap();
+
+    let mut archive = bin();
+    archive
+        .args(["--db", &db_str, "admin", "archive", "--month", "2026-01"])
+        .assert()
+        .success();
+
+    let file: String = conn
+        .query_row("SELECT file_path FROM archive_manifest", [], |r| r.get(0))
+        .unwrap();
+    assert!(file.starts_with(dir.path().join("archive").to_string_lossy().as_ref()));
+    fs::write(&file, b"tampered").unwrap();
+
+    let mut restore = bin();
+    restore
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-01",
+            "--restore",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("checksum mismatch"));
+}
+
+#[test]
+fn admin_compact_skips_events_with_non_numeric_ts() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("compact-bad-ts.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_c", "private:u_c");
+
+    let conn = Connection::open(&db_path).unwrap();
+    for (id, ts) in [("evt_ok", "1769990400"), ("evt_bad", "yesterday")] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, 'u_c', 'private:u_c', 'meal.rated', ?2, '{\"cuisine\":\"thai\"}', '1')",
+            [id, ts],
+        )
+        .unwrap();
+    }
+    for _ in 0..2 {
+        bin()
+            .args(["--db", &db_str, "admin", "compact"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("skipped=1"));
+    }
+
+    let ledger: Vec<String> = conn
+        .prepare("SELECT event_id FROM compaction_ledger ORDER BY event_id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(ledger, vec!["evt_ok".to_string()]);
+    let months: Vec<(String, i64)> = conn
+        .prepare(
+            "SELECT period_start, event_count FROM event_summaries
+             WHERE period = 'month' AND topic = ''",
+        )
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(months, vec![("2026-02".to_string(), 1)]);
+}
+
+#[test]
+fn admin_compact_builds_incremental_week_and_month_summaries() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("compact.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_c", "private:u_c");
+
+    let conn = Connection::open(&db_path).unwrap();
+    let insert = |id: &str, ts: &str, cuisine: &str| {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, 'u_c', 'private:u_c', 'meal.rated', ?2, ?3, ?2)",
+            [id, ts, &format!("{{\"cuisine\":\"{cuisine}\"}}")],
+        )
+        .unwrap();
+    };
+    insert("evt_1", "1769990400", "korean");
+    insert("evt_2", "1770076800", "thai");
+
+    let mut first = bin();
+    first
+        .args(["--db", &db_str, "admin", "compact"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("compacted events=2 summaries=4"));
+
+    insert("evt_3", "1770681600", "korean");
+    let mut second = bin();
+    second
+        .args(["--db", &db_str, "admin", "compact"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("compacted events=1 summaries=4"));
+
+    let mut month = bin();
+    month
+        .args([
+            "--db",
+            &db_str,
+            "query",
+            "summary",
+            "--uid",
+            "u_c",
+            "--scope",
+            "private:u_c",
+            "--period",
+            "month",
+            "--topic",
+            "food_pref",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "period=month start=2026-02 type=meal.rated topic=food_pref count=3 sum=3 distinct=2",
+        ));
+
+    let mut week = bin();
+    week.args([
+        "--db",
+        &db_str,
+        "--json",
+        "query",
+        "summary",
+        "--uid",
+        "u_c",
+        "--scope",
+        "private:u_c",
+        "--period",
+        "week",
+        "--topic",
+        "food_pref",
+    ])
+    .assert()
+    .success()
+    .stdout(
+        predicate::str::contains(r#""period_start":"2026-02-09""#)
+            .and(predicate::str::contains(r#""period_start":"2026-02-02""#))
+            .and(predicate::str::contains(
+                r#""items":{"korean":1.0,"thai":1.0}"#,
+            )),
+    );
+
+    let mut invalid = bin();
+    invalid
+        .args([
+            "--db",
+            &db_str,
+            "query",
+            "summary",
+            "--uid",
+            "u_c",
+            "--scope",
+            "private:u_c",
+            "--period",
+            "year",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected: week|month"));
+}
+
+#[test]
+fn admin_migrate_supports_status_to_and_dry_run() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("migrate-versions.db");
+    let db_str = db_path.to_string_lossy().to_string();
+
+    let mut partial = bin();
+    partial
+        .args(["--db", &db_str, "admin", "migrate", "--to", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("version=1 applied=1"));
+
+    let mut status = bin();
+    status
+        .args(["--db", &db_str, "admin", "migrate", "--status"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("schema_version=1")
+                .and(predicate::str::contains(
+                    "version=1 name=core_schema status=applied",
+                ))
+                .and(predicate::str::contains(
+                    "version=2 name=materializer_rules status=pending",
+                )),
+        );
+
+    let mut behind = bin();
+    behind
+        .args(["--db", &db_str, "user", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "run: agent-memory-cli admin migrate",
+        ));
+
+    let mut dry_run = bin();
+    dry_run
+        .args(["--db", &db_str, "admin", "migrate", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("-- migration 2 (materializer_rules)").and(
+                predicate::str::contains("CREATE TABLE IF NOT EXISTS materializer_rules"),
+            ),
+        );
+
+    let conn = Connection::open(&db_path).unwrap();
+    let rules_table: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM sqlite_master WHERE name='materializer_rules'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(rules_table, 0);
+
+    migrate_db(&db_str);
+    let mut down = bin();
+    down.args(["--db", &db_str, "admin", "migrate", "--to", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("downgrades are not supported"));
+}
+
+#[test]
+fn commands_refuse_db_newer_than_binary() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("migrate-newer.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES (9999, 'future', 'x', '1')",
+        [],
+    )
+    .unwrap();
+
+    for args in [vec!["user", "list"], vec!["admin", "migrate"]] {
+        let mut cmd = bin();
+        cmd.args(["--db", &db_str])
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("newer than this binary supports"));
+    }
+}
+
+#[test]
+fn admin_migrate_upgrades_legacy_db_without_migration_table() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("migrate-legacy.db");
+    let db_str = db_path.to_string_lossy().to_string();
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(include_str!("../specs/SCHEMA_SQLITE_V01.sql"))
+        .unwrap();
+    conn.execute(
+        "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES ('u_old', 'Old', 'active', '1', '1')",
+        [],
+    )
+    .unwrap();
+
+    migrate_db(&db_str);
+
+    let mut list = bin();
+    list.args(["--db", &db_str, "user", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("uid=u_old"));
+}
+
+#[test]
+fn channel_identity_can_replace_uid_for_ingest_query_and_state() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("channel-ref.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_ch", "private:u_ch");
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "identity",
+            "link",
+            "--uid",
+            "u_ch",
+            "--channel",
+            "telegram",
+            "--channel-user-id",
+            "555",
+        ])
+        .assert()
+        .success();
+
+    let event = dir.path().join("meal.json");
+    fs::write(&event, r#"{"cuisine":"thai","rating":5}"#).unwrap();
+    let channel_args = ["--channel", "telegram", "--channel-user-id", "555"];
+
+    bin()
+        .args(["--db", &db_str, "ingest", "event"])
+        .args(channel_args)
+        .args(["--scope", "private:u_ch", "--type", "meal.rated", "--file"])
+        .arg(&event)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("uid=u_ch"));
+
+    let batch = dir.path().join("events.ndjson");
+    fs::write(
+        &batch,
+        concat!(
+            r#"{"channel":"telegram","channel_user_id":"555","scope":"private:u_ch","type":"meal.rated","payload":{"cuisine":"thai"}}"#,
+            "\n",
+            r#"{"channel":"telegram","scope":"private:u_ch","type":"meal.rated","payload":{"cuisine":"thai"}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "ingest", "batch", "--file"])
+        .arg(&batch)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("line=1 status=inserted"))
+        .stdout(predicate::str::contains(
+            "line requires either uid or channel + channel_user_id",
+        ));
+
+    bin()
+        .args(["--db", &db_str, "query", "topk"])
+        .args(channel_args)
+        .args(["--scope", "private:u_ch", "--topic", "food_pref", "--json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""item":"thai""#))
+        .stdout(predicate::str::contains(r#""weight":2.0"#));
+
+    bin()
+        .args(["--db", &db_str, "state", "set"])
+        .args(channel_args)
+        .args([
+            "--scope",
+            "private:u_ch",
+            "--key",
+            "mood",
+            "--value",
+            "\"calm\"",
+        ])
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "state", "get", "--uid", "u_ch"])
+        .args(["--scope", "private:u_ch", "--key", "mood"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("calm"));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest"])
+        .args(["--channel", "telegram", "--channel-user-id", "999"])
+        .args(["--scope", "private:u_ch"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("identity not found"));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_ch"])
+        .args(channel_args)
+        .args(["--scope", "private:u_ch"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn reader_role_can_query_but_not_write() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("authz.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_owner", "shared:team");
+    let conn = Connection::open(&db_path).unwrap();
+    for uid in ["u_reader", "u_out"] {
+        conn.execute(
+            "INSERT INTO users (uid, display_name, status, created_at, updated_at) VALUES (?1, ?1, 'active', '1', '1')",
+            [uid],
+        )
+        .unwrap();
+    }
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "shared:team",
+        ])
+        .args(["--uid", "u_reader", "--role", "reader"])
+        .assert()
+        .success();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "shared:team",
+        ])
+        .args(["--uid", "u_out", "--role", "admin"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid role: admin"));
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "scope",
+            "add-member",
+            "--id",
+            "shared:team",
+        ])
+        .args(["--uid", "u_ghost"])
+        .assert()
+        .failure();
+
+    let event = dir.path().join("meal.json");
+    fs::write(&event, r#"{"cuisine":"thai"}"#).unwrap();
+    bin()
+        .args(["--db", &db_str, "ingest", "event", "--uid", "u_owner"])
+        .args(["--scope", "shared:team", "--type", "meal.rated", "--file"])
+        .arg(&event)
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "ingest", "event", "--uid", "u_reader"])
+        .args(["--scope", "shared:team", "--type", "meal.rated", "--file"])
+        .arg(&event)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("access denied: role reader"));
+    bin()
+        .args(["--db", &db_str, "ingest", "event", "--uid", "u_owner"])
+        .args([
+            "--scope",
+            "shared:nowhere",
+            "--type",
+            "meal.rated",
+            "--file",
+        ])
+        .arg(&event)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("scope not found: shared:nowhere"));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_reader"])
+        .args(["--scope", "shared:team"])
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "state", "set", "--uid", "u_reader"])
+        .args(["--scope", "shared:team", "--key", "k", "--value", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("access denied"));
+    bin()
+        .args(["--db", &db_str, "query", "topk", "--uid", "u_out"])
+        .args(["--scope", "shared:team", "--topic", "food_pref"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "access denied: user u_out is not a member of scope shared:team",
+        ));
+}
+
+#[test]
+fn query_read_order_auto_falls_through_private_then_shared() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("read-order.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_ro", "private:u_ro");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO scopes (scope_id, scope_type, created_at) VALUES ('shared:couple', 'shared', '1')",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES ('shared:couple', 'u_ro', 'member', '2')",
+        [],
+    )
+    .unwrap();
+
+    let ingest = |scope: &str, cuisine: &str| {
+        let event = dir.path().join("meal.json");
+        fs::write(&event, format!(r#"{{"cuisine":"{cuisine}"}}"#)).unwrap();
+        bin()
+            .args(["--db", &db_str, "ingest", "event", "--uid", "u_ro"])
+            .args(["--scope", scope, "--type", "meal.rated", "--file"])
+            .arg(&event)
+            .assert()
+            .success();
+    };
+    ingest("private:u_ro", "thai");
+    ingest("private:u_ro", "thai");
+    ingest("shared:couple", "korean");
+    ingest("shared:couple", "thai");
+
+    bin()
+        .args(["--db", &db_str, "query", "topk", "--uid", "u_ro"])
+        .args(["--read-order", "auto", "--topic", "food_pref"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "rank=1 scope=private:u_ro item=thai weight=2",
+        ))
+        .stdout(predicate::str::contains(
+            "rank=2 scope=shared:couple item=korean weight=1",
+        ));
+
+    bin()
+        .args([
+            "--db", &db_str, "--json", "query", "latest", "--uid", "u_ro",
+        ])
+        .args(["--read-order", "auto"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""scope_id":"private:u_ro""#));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_ro"])
+        .args(["--scopes", "shared:couple,private:u_ro"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("latest scope=shared:couple"));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_ro"])
+        .args(["--scopes", "private:u_ro,shared:other"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("scope not found: shared:other"));
+
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_ro"])
+        .args(["--scope", "private:u_ro", "--read-order", "auto"])
+        .assert()
+        .failure();
+    bin()
+        .args(["--db", &db_str, "query", "latest", "--uid", "u_ro"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn record_put_get_list_delete_against_dynamic_schema() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("records.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_rec", "private:u_rec");
+
+    let schema_path = dir.path().join("rating.schema.json");
+    fs::write(
+        &schema_path,
+        r#"{
+  "schema_id": "restaurant.rating",
+  "version": "1",
+  "class": "user_context",
+  "fields": [
+    {"name":"refUserId","type":"string"},
+    {"name":"refScopeId","type":"string","nullable":true},
+    {"name":"restaurantId","type":"string"},
+    {"name":"score","type":"number"},
+    {"name":"revisit","type":"boolean","default":false}
+  ]
+}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema_path)
+        .assert()
+        .success();
+
+    let record = dir.path().join("record.json");
+    fs::write(
+        &record,
+        r#"{"refUserId":"u_rec","refScopeId":"private:u_rec","restaurantId":"r_1","score":4.5}"#,
+    )
+    .unwrap();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "record",
+            "put",
+            "--schema",
+            "restaurant.rating",
+        ])
+        .args(["--id", "rec_1", "--key", "r_1", "--file"])
+        .arg(&record)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "stored record schema_id=restaurant.rating id=rec_1 table=dyn_restaurant_rating_v1",
+        ));
+
+    bin()
+        .args(["--db", &db_str, "--json", "record", "get"])
+        .args(["--schema", "restaurant.rating", "--id", "rec_1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""score":4.5"#))
+        .stdout(predicate::str::contains(r#""revisit":false"#))
+        .stdout(predicate::str::contains(r#""ref_user_id":"u_rec""#));
+
+    let bad = dir.path().join("bad.json");
+    for (payload, expected) in [
+        (
+            r#"{"refUserId":"u_rec","score":1}"#,
+            "field='restaurantId': missing required field",
+        ),
+        (
+            r#"{"refUserId":"u_rec","restaurantId":"r_2","score":"high"}"#,
+            "field='score': expects type=number",
+        ),
+        (
+            r#"{"refUserId":"u_rec","restaurantId":"r_2","score":1,"extra":true}"#,
+            "field='extra': unknown field",
+        ),
+        (
+            r#"{"refUserId":"u_none","restaurantId":"r_2","score":1}"#,
+            "user not found: u_none",
+        ),
+    ] {
+        fs::write(&bad, payload).unwrap();
+        bin()
+            .args([
+                "--db",
+                &db_str,
+                "record",
+                "put",
+                "--schema",
+                "restaurant.rating",
+            ])
+            .arg("--file")
+            .arg(&bad)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "record",
+            "list",
+            "--schema",
+            "restaurant.rating",
+        ])
+        .args(["--uid", "u_rec"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("record id=rec_1 entity_key=r_1"));
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "record",
+            "delete",
+            "--schema",
+            "restaurant.rating",
+        ])
+        .args(["--id", "rec_1"])
+        .assert()
+        .success();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "record",
+            "get",
+            "--schema",
+            "restaurant.rating",
+        ])
+        .args(["--id", "rec_1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("record not found"));
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "record",
+            "get",
+            "--schema",
+            "missing.schema",
+        ])
+        .args(["--id", "rec_1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("schema not found: missing.schema"));
+}
+
+#[test]
+fn record_get_list_delete_check_ref_scope_access() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("records-authz.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_r", "shared:club");
+
+    let schema_path = dir.path().join("visit.schema.json");
+    fs::write(
+        &schema_path,
+        r#"{"schema_id":"visit","version":"1","class":"user_context","fields":[{"name":"refUserId","type":"string"},{"name":"refScopeId","type":"string","nullable":true},{"name":"place","type":"string"}]}"#,
+    )
+    .unwrap();
+    let record = dir.path().join("visit.json");
+    fs::write(
+        &record,
+        r#"{"refUserId":"u_r","refScopeId":"shared:club","place":"Mingles"}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema_path)
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "record", "put", "--schema", "visit"])
+        .args(["--id", "v_1", "--file"])
+        .arg(&record)
+        .assert()
+        .success();
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "UPDATE scope_members SET role = 'reader' WHERE uid = 'u_r'",
+        [],
+    )
+    .unwrap();
+    let get = [
+        "--db", &db_str, "record", "get", "--schema", "visit", "--id", "v_1",
+    ];
+    bin().args(get).assert().success();
+    bin()
+        .args(["--db", &db_str, "record", "delete", "--schema", "visit"])
+        .args(["--id", "v_1"])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "role reader of user u_r in scope shared:club does not allow write",
+        ));
+
+    conn.execute("DELETE FROM scope_members WHERE uid = 'u_r'", [])
+        .unwrap();
+    bin()
+        .args(get)
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "user u_r is not a member of scope shared:club",
+        ));
+    bin()
+        .args(["--db", &db_str, "record", "list", "--schema", "visit"])
+        .args(["--uid", "u_r"])
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn ingest_validates_payload_against_bound_schema() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("bound.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_v", "private:u_v");
+
+    let schema_path = dir.path().join("meal.schema.json");
+    fs::write(
+        &schema_path,
+        r#"{
+  "schema_id": "meal.rated",
+  "version": "1",
+  "class": "domain",
+  "fields": [
+    {"name":"cuisine","type":"string"},
+    {"name":"rating","type":"integer"},
+    {"name":"spicy","type":"boolean","default":false}
+  ]
+}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema_path)
+        .assert()
+        .success();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "schema",
+            "bind",
+            "--event-type",
+            "meal.rated",
+        ])
+        .args(["--schema", "meal.rated"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "bound event_type=meal.rated schema_id=meal.rated mode=strict",
+        ));
+
+    let event = dir.path().join("meal.json");
+    let ingest = || {
+        let mut cmd = bin();
+        cmd.args(["--db", &db_str, "ingest", "event", "--uid", "u_v"])
+            .args(["--scope", "private:u_v", "--type", "meal.rated", "--file"])
+            .arg(&event);
+        cmd
+    };
+
+    fs::write(&event, r#"{"rating":"great","note":"x"}"#).unwrap();
+    ingest()
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "payload validation failed event_type=meal.rated schema_id=meal.rated",
+        ))
+        .stderr(predicate::str::contains("field='note': unknown field"))
+        .stderr(predicate::str::contains(
+            "field='cuisine': missing required field",
+        ))
+        .stderr(predicate::str::contains(
+            "field='rating': expects type=integer",
+        ));
+
+    fs::write(&event, r#"{"cuisine":"thai","rating":5}"#).unwrap();
+    ingest().assert().success();
+    let conn = Connection::open(&db_path).unwrap();
+    let stored: String = conn
+        .query_row("SELECT payload_json FROM events LIMIT 1", [], |r| r.get(0))
+        .unwrap();
+    assert!(stored.contains(r#""spicy":false"#), "{stored}");
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "schema",
+            "bind",
+            "--event-type",
+            "meal.rated",
+        ])
+        .args(["--schema", "meal.rated", "--mode", "lenient"])
+        .assert()
+        .success();
+    fs::write(&event, r#"{"cuisine":"thai","rating":4,"note":"x"}"#).unwrap();
+    ingest().assert().success();
+
+    bin()
+        .args(["--db", &db_str, "schema", "bindings"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "event_type=meal.rated schema_id=meal.rated mode=lenient",
+        ));
+    bin()
+        .args(["--db", &db_str, "schema", "bind", "--event-type", "x.y"])
+        .args(["--schema", "missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("schema not found: missing"));
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "schema",
+            "unbind",
+            "--event-type",
+            "meal.rated",
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn schema_versions_diff_and_migrate_data() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("schema-evolve.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    bin()
+        .args(["--db", &db_str, "admin", "migrate", "--to", "5"])
+        .assert()
+        .success();
+
+    let schema = dir.path().join("place.schema.json");
+    let write_schema = |version: &str, fields: &str| {
+        fs::write(
+            &schema,
+            format!(
+                r#"{{"schema_id":"place","version":"{version}","class":"domain","fields":[{fields}]}}"#
+            ),
+        )
+        .unwrap();
+    };
+    let register = |extra: &[&str]| {
+        let mut cmd = bin();
+        cmd.args(["--db", &db_str, "schema", "register", "--file"])
+            .arg(&schema)
+            .args(extra);
+        cmd
+    };
+
+    write_schema(
+        "1",
+        r#"{"name":"name","type":"string"},{"name":"stars","type":"int"}"#,
+    );
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO schema_registry (schema_id, version, schema_json, is_active, created_at) VALUES ('place', '1', ?1, 1, '1')",
+        [fs::read_to_string(&schema).unwrap()],
+    )
+    .unwrap();
+    // schema_registry is rebuilt with (schema_id, version) keys by migration 6.
+    bin()
+        .args(["--db", &db_str, "admin", "migrate"])
+        .assert()
+        .success();
+    register(&[])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("schema already registered"));
+
+    let record = dir.path().join("place.json");
+    fs::write(&record, r#"{"name":"Jungsik","stars":2}"#).unwrap();
+    bin()
+        .args(["--db", &db_str, "record", "put", "--schema", "place"])
+        .args(["--id", "p_1", "--key", "jungsik", "--file"])
+        .arg(&record)
+        .assert()
+        .success();
+
+    write_schema("1", r#"{"name":"name","type":"string"}"#);
+    register(&[])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "already registered with a different definition",
+        ));
+
+    write_schema(
+        "2",
+        r#"{"name":"name","type":"string"},{"name":"stars","type":"integer"},{"name":"city","type":"string","default":"Seoul"}"#,
+    );
+    register(&["--migrate-data"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "previous_version=1 changes=1 breaking=false",
+        ))
+        .stdout(predicate::str::contains("rows_copied=1"));
+
+    bin()
+        .args([
+            "--db", &db_str, "--json", "record", "get", "--schema", "place",
+        ])
+        .args(["--id", "p_1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""city":"Seoul""#));
+    bin()
+        .args(["--db", &db_str, "record", "get", "--schema", "place"])
+        .args(["--version", "1", "--id", "p_1"])
+        .assert()
+        .success();
+
+    write_schema(
+        "3",
+        r#"{"name":"name","type":"string"},{"name":"city","type":"string"}"#,
+    );
+    register(&[])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("change=field_removed field=stars"))
+        .stderr(predicate::str::contains("--allow-breaking"));
+    register(&["--allow-breaking"]).assert().success();
+
+    bin()
+        .args(["--db", &db_str, "schema", "diff", "--schema", "place"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "schema diff schema_id=place from=2 to=3 compatible=false",
+        ))
+        .stdout(predicate::str::contains(
+            "change=field_removed field=stars breaking=true",
+        ));
+    bin()
+        .args([
+            "--db", &db_str, "--json", "schema", "diff", "--schema", "place",
+        ])
+        .args(["--from", "1", "--to", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""compatible":true"#))
+        .stdout(predicate::str::contains(r#""change":"field_added""#));
+
+    bin()
+        .args(["--db", &db_str, "schema", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("schema_id=place version=1"))
+        .stdout(predicate::str::contains("schema_id=place version=3"));
+}
+
+#[test]
+fn schema_migrate_data_fails_on_rows_violating_new_not_null() {
+    let dir = tempdir().unwrap();
+    let db_str = dir
+        .path()
+        .join("schema-notnull.db")
+        .to_string_lossy()
+        .to_string();
+    migrate_db(&db_str);
+    let schema = dir.path().join("place.schema.json");
+    let write_schema = |version: &str, nullable: bool| {
+        fs::write(
+            &schema,
+            format!(
+                r#"{{"schema_id":"place","version":"{version}","class":"domain","fields":[{{"name":"name","type":"string"}},{{"name":"note","type":"string","nullable":{nullable}}}]}}"#
+            ),
+        )
+        .unwrap();
+    };
+    write_schema("1", true);
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .assert()
+        .success();
+    let record = dir.path().join("place.json");
+    fs::write(&record, r#"{"name":"Jungsik"}"#).unwrap();
+    bin()
+        .args(["--db", &db_str, "record", "put", "--schema", "place"])
+        .args(["--id", "p_1", "--key", "jungsik", "--file"])
+        .arg(&record)
+        .assert()
+        .success();
+
+    write_schema("2", false);
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .args(["--allow-breaking", "--migrate-data"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed to copy rows"))
+        .stderr(predicate::str::contains("NOT NULL constraint failed"));
+    bin()
+        .args(["--db", &db_str, "schema", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("schema_id=place version=1"))
+        .stdout(predicate::str::contains("version=2").not());
+}
+
+fn register_sample_schema(db_str: &str, dir: &std::path::Path, schema_id: &str) {
+    let schema = dir.join(format!("{schema_id}.schema.json"));
+    fs::write(
+        &schema,
+        format!(
+            r#"{{"schema_id":"{schema_id}","version":"1","class":"domain","fields":[{{"name":"name","type":"string"}}]}}"#
+        ),
+    )
+    .unwrap();
+    bin()
+        .args(["--db", db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .assert()
+        .success();
+}
+
+#[test]
+fn outbox_drain_rejects_non_http_targets_for_http_sink() {
+    let dir = tempdir().unwrap();
+    let db_str = dir
+        .path()
+        .join("outbox-http.db")
+        .to_string_lossy()
+        .to_string();
+    migrate_db(&db_str);
+    register_sample_schema(&db_str, dir.path(), "place");
+
+    bin()
+        .args(["--db", &db_str, "outbox", "drain", "--sink", "http"])
+        .args(["--target", "ftp://localhost/projection"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "invalid --target for http sink: ftp://localhost/projection",
+        ));
+    bin()
+        .args(["--db", &db_str, "outbox", "list", "--status", "pending"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "stream=schema key=schema.registered status=pending attempts=0",
+        ));
+}
+
+#[test]
+fn outbox_exec_sink_judges_delivery_by_exit_status_alone() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("outbox-epipe.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_o", "private:u_o");
+    // An envelope larger than a pipe buffer cannot be written to a sink that never reads it.
+    let key = "k".repeat(70_000);
+    bin()
+        .args(["--db", &db_str, "state", "set", "--uid", "u_o"])
+        .args(["--scope", "private:u_o", "--key", &key, "--value", "1"])
+        .assert()
+        .success();
+
+    bin()
+        .args(["--db", &db_str, "outbox", "drain", "--sink", "exec"])
+        .args(["--target", "exec 0<&-; exit 0"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "outbox drained delivered=1 retrying=0 dead=0",
+        ));
+}
+
+#[test]
+fn outbox_drain_delivers_retries_and_dead_letters() {
+    let dir = tempdir().unwrap();
+    let db_str = dir.path().join("outbox.db").to_string_lossy().to_string();
+    migrate_db(&db_str);
+    register_sample_schema(&db_str, dir.path(), "place");
+    register_sample_schema(&db_str, dir.path(), "show");
+
+    bin()
+        .args(["--db", &db_str, "outbox", "list", "--status", "pending"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "stream=schema key=schema.registered status=pending attempts=0",
+        ));
+
+    bin()
+        .args(["--db", &db_str, "outbox", "drain", "--sink", "exec"])
+        .args(["--target", "echo boom >&2; exit 3", "--max-attempts", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "outbox drained delivered=0 retrying=2 dead=0",
+        ));
+    // Retries wait for their backoff before they are due again.
+    bin()
+        .args(["--db", &db_str, "outbox", "drain", "--sink", "exec"])
+        .args(["--target", "exit 3", "--max-attempts", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("delivered=0 retrying=0 dead=0"));
+    bin()
+        .args(["--db", &db_str, "outbox", "list", "--status", "pending"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("attempts=1"))
+        .stdout(predicate::str::contains("last_error=sink command exited"));
+
+    let conn = Connection::open(dir.path().join("outbox.db")).unwrap();
+    conn.execute("UPDATE projection_outbox SET next_attempt_at = '0'", [])
+        .unwrap();
+    bin()
+        .args(["--db", &db_str, "outbox", "drain", "--sink", "exec"])
+        .args(["--target", "exit 3", "--max-attempts", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dead=2"));
+
+    bin()
+        .args(["--db", &db_str, "outbox", "requeue"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("requeued outbox rows=2"));
+
+    let sink_file = dir.path().join("sink.ndjson");
+    bin()
+        .args([
+            "--db", &db_str, "outbox", "drain", "--sink", "file", "--target",
+        ])
+        .arg(&sink_file)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("delivered=2"));
+    let lines = fs::read_to_string(&sink_file).unwrap();
+    assert_eq!(lines.lines().count(), 2);
+    assert!(lines.contains(r#""schema_id":"place""#));
+
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "--json",
+            "outbox",
+            "list",
+            "--status",
+            "delivered",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""delivered_at":"#));
+    bin()
+        .args([
+            "--db", &db_str, "outbox", "drain", "--sink", "file", "--target",
+        ])
+        .arg(&sink_file)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("delivered=0"));
+}
+
+#[test]
+fn outbox_drain_posts_to_http_sink() {
+    use std::io::{Read, Write};
+
+    let dir = tempdir().unwrap();
+    let db_str = dir
+        .path()
+        .join("outbox-http.db")
+        .to_string_lossy()
+        .to_string();
+    migrate_db(&db_str);
+    register_sample_schema(&db_str, dir.path(), "place");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while !String::from_utf8_lossy(&buf).contains("\"schema_id\"") {
+            let n = stream.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        String::from_utf8_lossy(&buf).to_string()
+    });
+
+    bin()
+        .args([
+            "--db", &db_str, "outbox", "drain", "--sink", "http", "--target",
+        ])
+        .arg(format!("http://{addr}/ingest"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("delivered=1"));
+    let request = server.join().unwrap();
+    assert!(request.starts_with("POST /ingest"), "{request}");
+    assert!(request
+        .to_ascii_lowercase()
+        .contains("idempotency-key: outbox_"));
+}
+
+#[test]
+fn domain_mutations_enqueue_outbox_rows() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("domain-outbox.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let event_file = dir.path().join("event.json");
+    fs::write(&event_file, r#"{"note":"hi"}"#).unwrap();
+    let event_file_str = event_file.to_string_lossy().to_string();
+
+    let steps: [&[&str]; 6] = [
+        &[
+            "identity",
+            "link",
+            "--uid",
+            "u_a",
+            "--channel",
+            "tg",
+            "--channel-user-id",
+            "42",
+        ],
+        &[
+            "ingest",
+            "event",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+            "--type",
+            "note.added",
+            "--file",
+            &event_file_str,
+        ],
+        &[
+            "state",
+            "set",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+            "--key",
+            "k",
+            "--value",
+            "1",
+        ],
+        &[
+            "state",
+            "delete",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+            "--key",
+            "k",
+        ],
+        &[
+            "identity",
+            "unlink",
+            "--channel",
+            "tg",
+            "--channel-user-id",
+            "42",
+        ],
+        &["user", "merge", "--from", "u_a", "--to", "u_b"],
+    ];
+    for args in steps {
+        bin().args(["--db", &db_str]).args(args).assert().success();
+    }
+    // Rejected mutations leave no outbox row behind.
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "state",
+            "set",
+            "--uid",
+            "u_b",
+            "--scope",
+            "private:nope",
+        ])
+        .args(["--key", "k", "--value", "1"])
+        .assert()
+        .failure();
+
+    let out = bin()
+        .args(["--db", &db_str, "outbox", "list", "--json", "--limit", "50"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let rows: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let keys: Vec<&str> = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["item_key"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "identity.linked",
+            "event.ingested",
+            "state.set",
+            "state.deleted",
+            "identity.unlinked",
+            "user.merged",
+        ]
+    );
+
+    let ingested: serde_json::Value =
+        serde_json::from_str(rows[1]["payload_json"].as_str().unwrap()).unwrap();
+    assert_eq!(rows[1]["stream"], "event");
+    assert_eq!(ingested["event"], "event.ingested");
+    assert_eq!(ingested["uid"], "u_a");
+    assert_eq!(ingested["event_type"], "note.added");
+}
+
+#[test]
+fn hooks_veto_before_commit_and_run_after_commit() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("hooks.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+
+    let log = dir.path().join("post.log");
+    let hooks = dir.path().join("hooks.json");
+    fs::write(
+        &hooks,
+        serde_json::json!({
+            "hooks": [
+                {"event": "user.created", "phase": "pre", "command": "echo no new users >&2; exit 1"},
+                {"event": "*", "phase": "post", "command": format!("cat >> '{}'; echo \"$HOOK_PHASE $HOOK_EVENT\" >> '{}'", log.display(), log.display())},
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let hooks_str = hooks.to_string_lossy().to_string();
+
+    bin()
+        .args([
+            "--db", &db_str, "--hooks", &hooks_str, "user", "create", "--name", "A",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "pre hook vetoed user.created: exited with exit status: 1: no new users",
+        ));
+    let conn = Connection::open(&db_path).unwrap();
+    let count = |table: &str| -> i64 {
+        conn.query_row(&format!("SELECT COUNT(1) FROM {table}"), [], |r| r.get(0))
+            .unwrap()
+    };
+    assert_eq!(count("users"), 0);
+    assert_eq!(count("projection_outbox"), 0);
+    assert!(!log.exists());
+
+    bin()
+        .args(["--db", &db_str, "--hooks", &hooks_str, "scope", "create"])
+        .args(["--id", "shared:team", "--type", "shared"])
+        .assert()
+        .success();
+    let logged = fs::read_to_string(&log).unwrap();
+    assert!(logged
+        .contains(r#"{"event":"scope.created","scope_id":"shared:team","scope_type":"shared"}"#));
+    assert!(logged.contains("post scope.created"));
+
+    // Without --hooks nothing runs.
+    bin()
+        .args(["--db", &db_str, "user", "create", "--name", "B"])
+        .assert()
+        .success();
+    assert_eq!(count("users"), 1);
+    assert!(!fs::read_to_string(&log).unwrap().contains("user.created"));
+
+    fs::write(
+        &hooks,
+        r#"{"hooks":[{"event":"user.made","phase":"pre","command":"true"}]}"#,
+    )
+    .unwrap();
+    bin()
+        .args([
+            "--db", &db_str, "--hooks", &hooks_str, "user", "create", "--name", "C",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid hook event: user.made"));
+}
+
+#[test]
+fn admin_export_import_round_trips_with_conflict_policies_and_uid_map() {
+    let dir = tempdir().unwrap();
+    let src_path = dir.path().join("src.db");
+    let src = src_path.to_string_lossy().to_string();
+    migrate_db(&src);
+    seed_user_and_scope(&src_path, "u_a", "private:u_a");
+
+    let schema = dir.path().join("note.schema.json");
+    fs::write(
+        &schema,
+        r#"{"schema_id":"note","version":"1","class":"user_context","fields":[{"name":"refUserId","type":"string"},{"name":"text","type":"string"}]}"#,
+    )
+    .unwrap();
+    let record = dir.path().join("note.json");
+    fs::write(&record, r#"{"refUserId":"u_a","text":"hi"}"#).unwrap();
+    let event = dir.path().join("event.json");
+    fs::write(&event, r#"{"cuisine":"korean"}"#).unwrap();
+    let (schema, record, event) = (
+        schema.to_string_lossy().to_string(),
+        record.to_string_lossy().to_string(),
+        event.to_string_lossy().to_string(),
+    );
+    let steps: [&[&str]; 4] = [
+        &["schema", "register", "--file", &schema],
+        &[
+            "record", "put", "--schema", "note", "--id", "n1", "--file", &record,
+        ],
+        &[
+            "ingest",
+            "event",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+            "--type",
+            "meal.rated",
+            "--file",
+            &event,
+        ],
+        &[
+            "state",
+            "set",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+            "--key",
+            "k",
+            "--value",
+            "1",
+        ],
+    ];
+    for args in steps {
+        bin().args(["--db", &src]).args(args).assert().success();
+    }
+
+    let bundle = dir.path().join("bundle");
+    let bundle_str = bundle.to_string_lossy().to_string();
+    bin()
+        .args(["--db", &src, "admin", "export", "--out", &bundle_str])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exported table=users rows=1"))
+        .stdout(predicate::str::contains(
+            "exported table=dyn_note_v1 rows=1",
+        ));
+    bin()
+        .args(["--db", &src, "admin", "export", "--out", &bundle_str])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("bundle already exists"));
+
+    let dst_path = dir.path().join("dst.db");
+    let dst = dst_path.to_string_lossy().to_string();
+    migrate_db(&dst);
+    bin()
+        .args(["--db", &dst, "admin", "import", "--in", &bundle_str])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "imported table=events rows=1 written=1 skipped=0",
+        ))
+        .stdout(predicate::str::contains(
+            "imported table=dyn_note_v1 rows=1 written=1 skipped=0",
+        ));
+    bin()
+        .args([
+            "--db",
+            &dst,
+            "query",
+            "topk",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+        ])
+        .args(["--topic", "food_pref"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("item=korean"));
+    bin()
+        .args([
+            "--db", &dst, "record", "get", "--schema", "note", "--id", "n1",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hi"));
+
+    // Identical rows are skipped under every policy.
+    bin()
+        .args(["--db", &dst, "admin", "import", "--in", &bundle_str])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("written=0"));
+
+    let dst_conn = Connection::open(&dst_path).unwrap();
+    let name = || -> String {
+        dst_conn
+            .query_row(
+                "SELECT display_name FROM users WHERE uid = 'u_a'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap()
+    };
+    dst_conn
+        .execute(
+            "UPDATE users SET display_name = 'Renamed' WHERE uid = 'u_a'",
+            [],
+        )
+        .unwrap();
+    bin()
+        .args(["--db", &dst, "admin", "import", "--in", &bundle_str])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed to import row into users"));
+    bin()
+        .args([
+            "--db",
+            &dst,
+            "admin",
+            "import",
+            "--in",
+            &bundle_str,
+            "--on-conflict",
+            "skip",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "imported table=users rows=1 written=0 skipped=1",
+        ));
+    assert_eq!(name(), "Renamed");
+    bin()
+        .args([
+            "--db",
+            &dst,
+            "admin",
+            "import",
+            "--in",
+            &bundle_str,
+            "--on-conflict",
+            "overwrite",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "imported table=users rows=1 written=1 skipped=0",
+        ));
+    assert_eq!(name(), "u_a");
+
+    let mapped_path = dir.path().join("mapped.db");
+    let mapped = mapped_path.to_string_lossy().to_string();
+    migrate_db(&mapped);
+    bin()
+        .args([
+            "--db",
+            &mapped,
+            "admin",
+            "import",
+            "--in",
+            &bundle_str,
+            "--map-uid",
+            "u_a=u_z",
+        ])
+        .assert()
+        .success();
+    let conn = Connection::open(&mapped_path).unwrap();
+    let owners: Vec<String> = [
+        "SELECT uid FROM users",
+        "SELECT uid FROM events",
+        "SELECT uid FROM state",
+        "SELECT ref_user_id FROM dyn_note_v1",
+    ]
+    .iter()
+    .map(|sql| conn.query_row(sql, [], |r| r.get(0)).unwrap())
+    .collect();
+    assert_eq!(owners, ["u_z"; 4]);
+
+    fs::write(bundle.join("users.ndjson"), "{}\n").unwrap();
+    bin()
+        .args(["--db", &mapped, "admin", "import", "--in", &bundle_str])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "bundle checksum mismatch for users.ndjson",
+        ));
+}
+
+#[test]
+fn admin_export_includes_archived_events_and_import_restores_them_live() {
+    let dir = tempdir().unwrap();
+    let src_path = dir.path().join("src.db");
+    let src = src_path.to_string_lossy().to_string();
+    let archive_dir = dir.path().join("cold").to_string_lossy().to_string();
+    migrate_db(&src);
+    seed_user_and_scope(&src_path, "u_a", "private:u_a");
+    let conn = Connection::open(&src_path).unwrap();
+    for (id, ts) in [("evt_old", "1767312000"), ("evt_new", "1770681600")] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, 'u_a', 'private:u_a', 'meal.rated', ?2, '{\"cuisine\":\"thai\"}', ?2)",
+            [id, ts],
+        )
+        .unwrap();
+    }
+    bin()
+        .args(["--db", &src, "admin", "archive", "--month", "2026-01"])
+        .args(["--dir", &archive_dir])
+        .assert()
+        .success();
+
+    let bundle_str = dir.path().join("bundle").to_string_lossy().to_string();
+    bin()
+        .args(["--db", &src, "admin", "export", "--out", &bundle_str])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exported table=events rows=1"))
+        .stdout(predicate::str::contains(
+            "exported table=archived_events rows=1",
+        ));
+
+    let dst_path = dir.path().join("dst.db");
+    let dst = dst_path.to_string_lossy().to_string();
+    migrate_db(&dst);
+    bin()
+        .args(["--db", &dst, "admin", "import", "--in", &bundle_str])
+        .args(["--map-uid", "u_a=u_b"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "imported table=archived_events rows=1 written=1 skipped=0",
+        ));
+    let events: Vec<(String, String)> = Connection::open(&dst_path)
+        .unwrap()
+        .prepare("SELECT event_id, uid FROM events ORDER BY event_id")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            ("evt_new".to_string(), "u_b".to_string()),
+            ("evt_old".to_string(), "u_b".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn user_export_collects_every_row_referencing_the_uid() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("user-export.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let schema = dir.path().join("note.schema.json");
+    fs::write(
+        &schema,
+        r#"{"schema_id":"note","version":"1","class":"user_context","fields":[{"name":"refUserId","type":"string"},{"name":"text","type":"string"}]}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .assert()
+        .success();
+    for uid in ["u_a", "u_b"] {
+        let record = dir.path().join(format!("{uid}.json"));
+        fs::write(
+            &record,
+            format!(r#"{{"refUserId":"{uid}","text":"about {uid}"}}"#),
+        )
+        .unwrap();
+        bin()
+            .args([
+                "--db", &db_str, "record", "put", "--schema", "note", "--file",
+            ])
+            .arg(&record)
+            .assert()
+            .success();
+    }
+    let event = dir.path().join("meal.json");
+    fs::write(&event, r#"{"cuisine":"korean"}"#).unwrap();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "ingest",
+            "event",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_a",
+        ])
+        .args(["--type", "meal.rated", "--file"])
+        .arg(&event)
+        .assert()
+        .success();
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "identity",
+            "link",
+            "--uid",
+            "u_a",
+            "--channel",
+            "tg",
+        ])
+        .args(["--channel-user-id", "42"])
+        .assert()
+        .success();
+    let conn = Connection::open(&db_path).unwrap();
+    for (id, uid) in [("evt_old_a", "u_a"), ("evt_old_b", "u_b")] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, ?2, 'private:' || ?2, 'meal.rated', '1767312000', '{\"cuisine\":\"thai\"}', '1767312000')",
+            [id, uid],
+        )
+        .unwrap();
+    }
+    let archive_dir = dir.path().join("cold").to_string_lossy().to_string();
+    for args in [
+        vec!["admin", "compact"],
+        vec![
+            "admin",
+            "archive",
+            "--month",
+            "2026-01",
+            "--dir",
+            &archive_dir,
+        ],
+    ] {
+        bin().args(["--db", &db_str]).args(&args).assert().success();
+    }
+
+    let out = bin()
+        .args(["--db", &db_str, "user", "export", "--uid", "u_a"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let doc: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(doc["user"]["uid"], "u_a");
+    let tables = &doc["tables"];
+    let len = |table: &str| tables[table].as_array().unwrap().len();
+    assert_eq!(len("user_identities"), 1);
+    assert_eq!(len("scope_members"), 1);
+    assert_eq!(len("events"), 1);
+    assert_eq!(len("topk"), 1);
+    assert_eq!(len("state"), 0);
+    assert_eq!(len("dynamic_records"), 0);
+    assert_eq!(len("dyn_note_v1"), 1);
+    assert_eq!(tables["dyn_note_v1"][0]["text"], "about u_a");
+    let summaries = tables["event_summaries"].as_array().unwrap();
+    assert!(!summaries.is_empty());
+    assert!(summaries.iter().all(|row| row["uid"] == "u_a"));
+    assert!(summaries
+        .iter()
+        .any(|row| row["period"] == "month" && row["period_start"] == "2026-01"));
+    assert_eq!(len("archived_events"), 1);
+    assert_eq!(tables["archived_events"][0]["event_id"], "evt_old_a");
+    assert_eq!(tables["archived_events"][0]["uid"], "u_a");
+    assert_eq!(
+        tables["events"][0]["payload_json"],
+        r#"{"cuisine":"korean"}"#
+    );
+
+    let file = dir.path().join("u_a.json");
+    bin()
+        .args(["--db", &db_str, "user", "export", "--uid", "u_a", "--out"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "exported user uid=u_a rows={}",
+            8 + summaries.len()
+        )));
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(written["tables"], doc["tables"]);
+
+    bin()
+        .args(["--db", &db_str, "user", "export", "--uid", "u_missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("user not found: u_missing"));
+}
+
+#[test]
+fn user_delete_hard_purges_every_tier_and_leaves_tombstone() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("user-purge.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    let archive_str = dir.path().join("cold").to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let conn = Connection::open(&db_path).unwrap();
+    for (id, uid, ts) in [
+        ("evt_a_jan", "u_a", "1768435200"),
+        ("evt_b_jan", "u_b", "1768435201"),
+        ("evt_a_feb", "u_a", "1770681600"),
+    ] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, created_at)
+             VALUES (?1, ?2, 'private:' || ?2, 'note.added', ?3, '{}', ?3)",
+            [id, uid, ts],
+        )
+        .unwrap();
+    }
+    bin()
+        .args(["--db", &db_str, "admin", "archive", "--month", "2026-02"])
+        .args(["--dir", &archive_str])
+        .assert()
+        .success();
+    conn.execute(
+        "INSERT INTO event_summaries (period, period_start, scope_id, uid, event_type, event_count, updated_at)
+         VALUES ('month', '2026-01', 'private:u_a', 'u_a', 'note.added', 1, '1')",
+        [],
+    )
+    .unwrap();
+
+    let schema = dir.path().join("note.schema.json");
+    fs::write(
+        &schema,
+        r#"{"schema_id":"note","version":"1","class":"user_context","fields":[{"name":"refUserId","type":"string"},{"name":"text","type":"string"}]}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .assert()
+        .success();
+    for uid in ["u_a", "u_b"] {
+        let record = dir.path().join(format!("{uid}.json"));
+        fs::write(&record, format!(r#"{{"refUserId":"{uid}","text":"x"}}"#)).unwrap();
+        bin()
+            .args([
+                "--db", &db_str, "record", "put", "--schema", "note", "--file",
+            ])
+            .arg(&record)
+            .assert()
+            .success();
+    }
+    let meal = dir.path().join("meal.json");
+    fs::write(&meal, r#"{"cuisine":"korean"}"#).unwrap();
+    bin()
+        .args(["--db", &db_str, "ingest", "event", "--uid", "u_a"])
+        .args(["--scope", "private:u_a", "--type", "meal.rated", "--file"])
+        .arg(&meal)
+        .assert()
+        .success();
+    bin()
+        .args(["--db", &db_str, "identity", "link", "--uid", "u_a"])
+        .args(["--channel", "tg", "--channel-user-id", "42"])
+        .assert()
+        .success();
+
+    let preflight = bin()
+        .args(["--db", &db_str, "user", "delete", "--uid", "u_a"])
+        .args(["--mode", "hard", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(preflight.status.success());
+    let preflight = String::from_utf8_lossy(&preflight.stdout).to_string();
+    let counts = preflight
+        .trim()
+        .strip_prefix("delete preflight uid=u_a mode=hard ")
+        .unwrap()
+        .to_string();
+    assert!(counts.contains("identities=1 scope_members=1 events=1"));
+    assert!(counts.contains("summaries=1 dynamic_records=0 dynamic_rows=1 archived_events=2"));
+
+    bin()
+        .args(["--db", &db_str, "user", "delete", "--uid", "u_a"])
+        .args(["--mode", "hard", "--force"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "deleted user uid=u_a mode=hard {counts}"
+        )));
+
+    for table in [
+        "users",
+        "user_identities",
+        "scope_members",
+        "events",
+        "state",
+        "metrics",
+        "topk",
+        "event_summaries",
+        "dynamic_records",
+    ] {
+        let n: i64 = conn
+            .query_row(
+                &format!("SELECT COUNT(1) FROM {table} WHERE uid = 'u_a'"),
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(n, 0, "{table} still references u_a");
+    }
+    let notes: Vec<String> = conn
+        .prepare("SELECT ref_user_id FROM dyn_note_v1")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(notes, vec!["u_b".to_string()]);
+
+    let tombstone: String = conn
+        .query_row(
+            "SELECT counts_json FROM user_tombstones WHERE uid = 'u_a'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    let tombstone: serde_json::Value = serde_json::from_str(&tombstone).unwrap();
+    assert_eq!(tombstone["archived_events"], 2);
+    let deleted: String = conn
+        .query_row(
+            "SELECT payload_json FROM projection_outbox WHERE item_key = 'user.deleted'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert!(deleted.contains(r#""mode":"hard""#));
+
+    // Jan keeps u_b's event; Feb held only u_a's and is gone.
+    let archives: Vec<(String, i64)> = conn
+        .prepare("SELECT month, row_count FROM archive_manifest ORDER BY month")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(archives, vec![("2026-01".to_string(), 1)]);
+    bin()
+        .args(["--db", &db_str, "admin", "archive", "--month", "2026-01"])
+        .arg("--restore")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rows=1 skipped=0"));
+    let leftover = fs::read_dir(dir.path().join("cold"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension() == Some("purge".as_ref()))
+        .count();
+    assert_eq!(leftover, 0);
+
+    bin()
+        .args(["--db", &db_str, "user", "delete", "--uid", "u_a"])
+        .args(["--mode", "hard", "--force"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("user not found: u_a"));
+}
+
+#[test]
+fn user_merge_counts_duplicates_once_and_carries_summaries_and_archives() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("user-merge-history.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    let archive_str = dir.path().join("cold").to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_from", "shared:couple");
+    seed_user_and_scope(&db_path, "u_to", "private:u_to");
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES ('shared:couple', 'u_to', 'member', '1')",
+        [],
+    )
+    .unwrap();
+    // January is archived below; evt_from_k repeats evt_to_k under the same idempotency key.
+    for (id, uid, ts, cuisine, key) in [
+        ("evt_to_k", "u_to", "1768435200", "thai", Some("k1")),
+        ("evt_from_k", "u_from", "1768435200", "thai", Some("k1")),
+        ("evt_from_old", "u_from", "1767312000", "italian", None),
+        ("evt_from_new", "u_from", "1770681600", "thai", None),
+    ] {
+        conn.execute(
+            "INSERT INTO events (event_id, uid, scope_id, event_type, event_ts, payload_json, idempotency_key, created_at)
+             VALUES (?1, ?2, 'shared:couple', 'meal.rated', ?3, ?4, ?5, ?3)",
+            rusqlite::params![id, uid, ts, format!(r#"{{"cuisine":"{cuisine}"}}"#), key],
+        )
+        .unwrap();
+    }
+    for args in [
+        vec!["admin", "reindex"],
+        vec!["admin", "compact"],
+        vec![
+            "admin",
+            "archive",
+            "--month",
+            "2026-01",
+            "--dir",
+            &archive_str,
+        ],
+        vec!["user", "merge", "--from", "u_from", "--to", "u_to"],
+    ] {
+        bin().args(["--db", &db_str]).args(&args).assert().success();
+    }
+
+    let counters: Vec<(String, String, f64)> = conn
+        .prepare(
+            "SELECT uid, metric_key, metric_value FROM metrics
+             WHERE scope_id = 'shared:couple' ORDER BY metric_key",
+        )
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        counters,
+        vec![
+            (
+                "u_to".to_string(),
+                "counter:food_pref:italian".to_string(),
+                1.0
+            ),
+            (
+                "u_to".to_string(),
+                "counter:food_pref:thai".to_string(),
+                2.0
+            ),
+        ]
+    );
+
+    let month_summary = |conn: &Connection| -> Vec<(String, String, i64)> {
+        conn.prepare(
+            "SELECT uid, period_start, event_count FROM event_summaries
+             WHERE period = 'month' AND topic = '' ORDER BY uid, period_start",
+        )
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    };
+    let expected = vec![
+        ("u_to".to_string(), "2026-01".to_string(), 2),
+        ("u_to".to_string(), "2026-02".to_string(), 1),
+    ];
+    assert_eq!(month_summary(&conn), expected);
+    let items: String = conn
+        .query_row(
+            "SELECT items_json FROM event_summaries
+             WHERE uid = 'u_to' AND period = 'month' AND period_start = '2026-01' AND topic = 'food_pref'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(items, r#"{"italian":1.0,"thai":1.0}"#);
+
+    // The rewritten archive holds the relinked event but not the duplicate.
+    bin()
+        .args([
+            "--db",
+            &db_str,
+            "admin",
+            "archive",
+            "--month",
+            "2026-01",
+            "--restore",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rows=2 skipped=0"));
+    let restored: Vec<(String, String)> = conn
+        .prepare("SELECT event_id, uid FROM events ORDER BY event_id")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        restored,
+        vec![
+            ("evt_from_new".to_string(), "u_to".to_string()),
+            ("evt_from_old".to_string(), "u_to".to_string()),
+            ("evt_to_k".to_string(), "u_to".to_string()),
+        ]
+    );
+    bin()
+        .args(["--db", &db_str, "admin", "compact"])
+        .assert()
+        .success();
+    assert_eq!(month_summary(&conn), expected);
+}
+
+#[test]
+fn user_merge_rebuilds_counters_and_topk_and_relinks_dynamic_tables() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("user-merge-counters.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_from", "shared:couple");
+    seed_user_and_scope(&db_path, "u_to", "private:u_to");
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute(
+        "INSERT INTO scope_members (scope_id, uid, role, added_at) VALUES ('shared:couple', 'u_to', 'member', '1')",
+        [],
+    )
+    .unwrap();
+    // Before the merge u_to ranks korean first; u_from's thai history outweighs it.
+    let mut batch = String::new();
+    for (uid, cuisine, times) in [
+        ("u_to", "korean", 3),
+        ("u_to", "thai", 2),
+        ("u_from", "thai", 2),
+        ("u_from", "italian", 1),
+    ] {
+        for _ in 0..times {
+            batch.push_str(&format!(
+                r#"{{"uid":"{uid}","scope":"shared:couple","type":"meal.rated","payload":{{"cuisine":"{cuisine}"}}}}"#
+            ));
+            batch.push('\n');
+        }
+    }
+    bin()
+        .args(["--db", &db_str, "ingest", "batch", "--file", "-"])
+        .write_stdin(batch)
+        .assert()
+        .success();
+    conn.execute(
+        "INSERT INTO dynamic_records (record_id, schema_id, entity_key, uid, scope_id, payload_json, created_at, updated_at)
+         VALUES ('rec_1', 'legacy', 'k', 'u_from', NULL, '{}', '1', '1')",
+        [],
+    )
+    .unwrap();
+
+    let schema = dir.path().join("note.schema.json");
+    fs::write(
+        &schema,
+        r#"{"schema_id":"note","version":"1","class":"user_context","fields":[{"name":"refUserId","type":"string"},{"name":"text","type":"string"}]}"#,
+    )
+    .unwrap();
+    bin()
+        .args(["--db", &db_str, "schema", "register", "--file"])
+        .arg(&schema)
+        .assert()
+        .success();
+    let record = dir.path().join("note.json");
+    fs::write(&record, r#"{"refUserId":"u_from","text":"x"}"#).unwrap();
+    bin()
+        .args([
+            "--db", &db_str, "record", "put", "--schema", "note", "--file",
+        ])
+        .arg(&record)
+        .assert()
+        .success();
+
+    bin()
+        .args(["--db", &db_str, "user", "merge", "--from", "u_from"])
+        .args(["--to", "u_to"])
+        .assert()
+        .success();
+
+    let thai: f64 = conn
+        .query_row(
+            "SELECT metric_value FROM metrics WHERE uid='u_to' AND metric_key='counter:food_pref:thai'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(thai, 4.0);
+
+    let ranked: Vec<(i64, String, f64)> = conn
+        .prepare(
+            "SELECT rank, item_key, weight FROM topk
+             WHERE scope_id='shared:couple' AND uid='u_to' AND topic='food_pref' ORDER BY rank",
+        )
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(
+        ranked,
+        vec![
+            (1, "thai".to_string(), 4.0),
+            (2, "korean".to_string(), 3.0),
+            (3, "italian".to_string(), 1.0),
+        ]
+    );
+    let from_topk: i64 = conn
+        .query_row("SELECT COUNT(1) FROM topk WHERE uid='u_from'", [], |r| {
+            r.get(0)
+        })
+        .unwrap();
+    assert_eq!(from_topk, 0);
+
+    let record_uid: String = conn
+        .query_row(
+            "SELECT uid FROM dynamic_records WHERE record_id='rec_1'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(record_uid, "u_to");
+    let (ref_user_id, refuserid): (String, String) = conn
+        .query_row("SELECT ref_user_id, refuserid FROM dyn_note_v1", [], |r| {
+            Ok((r.get(0)?, r.get(1)?))
+        })
+        .unwrap();
+    assert_eq!((ref_user_id.as_str(), refuserid.as_str()), ("u_to", "u_to"));
+}
+
+#[test]
+fn errors_map_to_stable_exit_codes_and_json_envelope() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("errors.db");
+    let db_str = db_path.to_string_lossy().to_string();
+
+    let error_of = |args: &[&str], exit: i32| -> serde_json::Value {
+        let out = bin().arg("--json").args(args).output().unwrap();
+        assert_eq!(out.status.code(), Some(exit), "{args:?}");
+        serde_json::from_slice::<serde_json::Value>(&out.stderr).unwrap()["error"].clone()
+    };
+
+    let err = error_of(&["--db", &db_str, "user", "list"], 6);
+    assert_eq!(err["code"], "schema_not_initialized");
+
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_a", "private:u_a");
+    seed_user_and_scope(&db_path, "u_b", "private:u_b");
+
+    let err = error_of(&["--db", &db_str, "user", "show", "--uid", "u_x"], 3);
+    assert_eq!(err["code"], "not_found");
+    assert_eq!(err["message"], "user not found: u_x");
+    assert!(err["details"].is_null());
+
+    let err = error_of(
+        &[
+            "--db", &db_str, "user", "delete", "--uid", "u_a", "--mode", "bad",
+        ],
+        2,
+    );
+    assert_eq!(err["code"], "validation");
+
+    // clap usage errors use the same envelope under --json; --help stays plain text.
+    let err = error_of(&["user", "merge", "--from", "u_a"], 2);
+    assert_eq!(err["code"], "validation");
+    assert_eq!(
+        err["message"],
+        "the following required arguments were not provided: --to <TO_UID>"
+    );
+    let err = error_of(&["--ndjson", "user", "show", "--bogus"], 2);
+    assert_eq!(err["message"], "unexpected argument '--bogus' found");
+    bin()
+        .args(["--json", "user", "--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Usage:"));
+
+    let link = [
+        "--db",
+        &db_str,
+        "identity",
+        "link",
+        "--uid",
+        "u_a",
+        "--channel",
+        "tg",
+        "--channel-user-id",
+        "7",
+    ];
+    bin().args(link).assert().success();
+    let err = error_of(&link, 4);
+    assert_eq!(err["code"], "conflict");
+
+    let err = error_of(
+        &[
+            "--db",
+            &db_str,
+            "state",
+            "set",
+            "--uid",
+            "u_a",
+            "--scope",
+            "private:u_b",
+            "--key",
+            "k",
+            "--value",
+            "1",
+        ],
+        5,
+    );
+    assert_eq!(err["code"], "unauthorized");
+    assert_eq!(err["details"]["scope_id"], "private:u_b");
+
+    // Without --json the message alone goes to stderr; the exit code is the same.
+    bin()
+        .args(["--db", &db_str, "user", "show", "--uid", "u_x"])
+        .assert()
+        .code(3)
+        .stderr("user not found: u_x\n");
+}
+
+#[test]
+fn every_command_honors_json_and_ndjson_output() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("output.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+
+    let json_of = |args: &[&str]| -> serde_json::Value {
+        let out = bin()
+            .args(["--db", &db_str, "--json"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{args:?}");
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert_eq!(stdout.lines().count(), 1, "{args:?}: {stdout}");
+        serde_json::from_str(&stdout).unwrap()
+    };
+
+    let created = json_of(&["user", "create", "--name", "Ann"]);
+    let uid = created["uid"].as_str().unwrap().to_string();
+    assert_eq!(created["name"], "Ann");
+
+    let users = json_of(&["user", "list"]);
+    assert_eq!(users[0]["uid"], uid.as_str());
+    assert_eq!(json_of(&["user", "show", "--uid", &uid])["name"], "Ann");
+
+    let scope = json_of(&["scope", "create", "--id", "team:a", "--type", "team"]);
+    assert_eq!(scope["scope_id"], "team:a");
+    let member = json_of(&[
+        "scope",
+        "add-member",
+        "--id",
+        "team:a",
+        "--uid",
+        &uid,
+        "--role",
+        "owner",
+    ]);
+    assert_eq!(member["role"], "owner");
+    let members = json_of(&["scope", "members", "--id", "team:a"]);
+    assert_eq!(members[0]["uid"], uid.as_str());
+
+    json_of(&[
+        "identity",
+        "link",
+        "--uid",
+        &uid,
+        "--channel",
+        "tg",
+        "--channel-user-id",
+        "42",
+    ]);
+    let resolved = json_of(&[
+        "identity",
+        "resolve",
+        "--channel",
+        "tg",
+        "--channel-user-id",
+        "42",
+    ]);
+    assert_eq!(resolved["uid"], uid.as_str());
+
+    let event_file = dir.path().join("event.json");
+    fs::write(&event_file, r#"{"item":"thai"}"#).unwrap();
+    let ingested = json_of(&[
+        "ingest",
+        "event",
+        "--uid",
+        &uid,
+        "--scope",
+        "team:a",
+        "--type",
+        "food.viewed",
+        "--file",
+        &event_file.to_string_lossy(),
+    ]);
+    assert_eq!(ingested["status"], "inserted");
+    assert_eq!(ingested["event_type"], "food.viewed");
+
+    assert_eq!(json_of(&["schema", "list"]), serde_json::json!([]));
+    let rules = json_of(&["materializer", "list"]);
+    assert_eq!(rules[0]["rule_id"], "builtin.expense_logged.spend_category");
+    assert_eq!(rules[0]["active"], true);
+
+    // --ndjson prints list results one object per line.
+    json_of(&["user", "create", "--name", "Bo"]);
+    bin()
+        .args(["--db", &db_str, "--ndjson", "user", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::function(|s: &str| {
+            s.lines().count() == 2
+                && s.lines().all(|l| {
+                    serde_json::from_str::<serde_json::Value>(l).unwrap()["uid"].is_string()
+                })
+        }));
+}
+
+#[test]
+fn serve_stdio_answers_json_rpc_requests_on_one_connection() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("serve.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_s", "private:u_s");
+
+    let requests = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"identity.link","params":{"uid":"u_s","channel":"tg","channel_user_id":"9"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"ingest.event","params":{"channel":"tg","channel_user_id":"9","scope_id":"private:u_s","event_type":"meal.rated","payload":{"cuisine":"thai"}}}"#,
+        r#"{"jsonrpc":"2.0","id":"t","method":"query.topk","params":{"uid":"u_s","read_order":"auto","topic":"food_pref"}}"#,
+        r#"{"jsonrpc":"2.0","method":"state.set","params":{"uid":"u_s","scope_id":"private:u_s","key":"k","value":1}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"state.get","params":{"uid":"u_s","scope_id":"private:u_s","key":"k"}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"user.show","params":{"uid":"u_x"}}"#,
+        r#"{"jsonrpc":"2.0","id":5,"method":"user.fly"}"#,
+        r#"{"jsonrpc":"2.0","id":6,"method":"query.topk","params":{"uid":"u_s"}}"#,
+        "not json",
+    ];
+    let out = bin()
+        .args(["--db", &db_str, "serve", "--stdio"])
+        .write_stdin(requests.join("\n") + "\n")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let responses: Vec<serde_json::Value> = String::from_utf8(out.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    // The state.set notification gets no response.
+    assert_eq!(responses.len(), 8);
+    assert!(responses.iter().all(|r| r["jsonrpc"] == "2.0"));
+
+    assert_eq!(responses[0]["result"]["uid"], "u_s");
+    assert_eq!(responses[1]["result"]["status"], "inserted");
+    assert_eq!(responses[2]["id"], "t");
+    assert_eq!(responses[2]["result"][0]["item"], "thai");
+    assert_eq!(responses[2]["result"][0]["scope_id"], "private:u_s");
+    assert_eq!(responses[3]["result"]["value"], 1);
+
+    assert_eq!(responses[4]["error"]["code"], -32003);
+    assert_eq!(responses[4]["error"]["data"]["code"], "not_found");
+    assert_eq!(responses[5]["error"]["code"], -32601);
+    assert_eq!(responses[6]["error"]["code"], -32602);
+    assert_eq!(responses[7]["error"]["code"], -32700);
+    assert!(responses[7]["id"].is_null());
+}
+
+/// Starts `serve` with `listen_args` and returns the child plus its `listening=` value.
+fn spawn_http_server(db: &str, listen_args: &[&str]) -> (std::process::Child, String) {
+    use std::io::BufRead;
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("agent-memory-cli"))
+        .args(["--db", db, "serve"])
+        .args(listen_args)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    std::io::BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let listening = line.trim().strip_prefix("listening=").unwrap().to_string();
+    (child, listening)
+}
+
+/// One HTTP/1.1 exchange with `Connection: close`; returns the status and JSON body.
+fn http_exchange<S: std::io::Read + std::io::Write>(
+    mut stream: S,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> (u16, serde_json::Value) {
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn serve_http_rejects_non_loopback_addresses() {
+    let dir = tempdir().unwrap();
+    let db_str = dir
+        .path()
+        .join("http-bind.db")
+        .to_string_lossy()
+        .to_string();
+    migrate_db(&db_str);
+
+    for addr in ["0.0.0.0:0", "[::]:0", "localhost"] {
+        bin()
+            .args(["--db", &db_str, "serve", "--http", addr])
+            .assert()
+            .code(2)
+            .stdout(predicate::str::contains("listening=").not());
+    }
+    bin()
+        .args(["--db", &db_str, "serve", "--http", "0.0.0.0:0"])
+        .assert()
+        .stderr(predicate::str::contains(
+            "--http must listen on a loopback address",
+        ));
+}
+
+#[test]
+fn serve_http_exposes_rest_routes_errors_and_openapi() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("http.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_h", "private:u_h");
+
+    let (mut child, listening) = spawn_http_server(&db_str, &["--http", "127.0.0.1:0"]);
+    let addr = listening.strip_prefix("http://").unwrap().to_string();
+    let call = |method: &str, path: &str, body: Option<&str>| {
+        http_exchange(
+            std::net::TcpStream::connect(&addr).unwrap(),
+            method,
+            path,
+            body,
+        )
+    };
+
+    let (status, user) = call("POST", "/users", Some(r#"{"name":"Ada"}"#));
+    assert_eq!(status, 200);
+    let uid = user["uid"].as_str().unwrap().to_string();
+    let (status, renamed) = call(
+        "PATCH",
+        &format!("/users/{uid}"),
+        Some(r#"{"name":"Ada L"}"#),
+    );
+    assert_eq!((status, renamed["name"].as_str()), (200, Some("Ada L")));
+    let (_, users) = call("GET", "/users", None);
+    assert_eq!(users.as_array().unwrap().len(), 2);
+
+    let (status, _) = call(
+        "POST",
+        "/identities",
+        Some(r#"{"uid":"u_h","channel":"tg","channel_user_id":"7"}"#),
+    );
+    assert_eq!(status, 200);
+    let (_, identity) = call("GET", "/identities/tg/7", None);
+    assert_eq!(identity["uid"], "u_h");
+    let (_, members) = call("GET", "/scopes/private%3Au_h/members", None);
+    assert_eq!(members[0]["uid"], "u_h");
+
+    let (status, ingested) = call(
+        "POST",
+        "/events",
+        Some(
+            r#"{"channel":"tg","channel_user_id":"7","scope_id":"private:u_h","event_type":"meal.rated","payload":{"cuisine":"thai"}}"#,
+        ),
+    );
+    assert_eq!(
+        (status, ingested["status"].as_str()),
+        (200, Some("inserted"))
+    );
+    let (status, topk) = call(
+        "GET",
+        "/query/topk?uid=u_h&read_order=auto&topic=food_pref&limit=2",
+        None,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(topk[0]["item"], "thai");
+    let (_, latest) = call("GET", "/query/latest?uid=u_h&scopes=private:u_h", None);
+    assert_eq!(latest["event_type"], "meal.rated");
+
+    let (status, _) = call(
+        "PUT",
+        "/state/private:u_h/mood",
+        Some(r#"{"uid":"u_h","value":{"level":3}}"#),
+    );
+    assert_eq!(status, 200);
+    let (_, state) = call("GET", "/state/private:u_h/mood?uid=u_h", None);
+    assert_eq!(state["value"]["level"], 3);
+    let (status, _) = call("DELETE", "/state/private:u_h/mood?uid=u_h", None);
+    assert_eq!(status, 200);
+
+    let (status, err) = call("GET", "/state/private:u_h/mood?uid=u_h", None);
+    assert_eq!(
+        (status, err["error"]["code"].as_str()),
+        (404, Some("not_found"))
+    );
+    let (status, err) = call("GET", "/query/topk?uid=u_h&scope_id=private:u_h", None);
+    assert_eq!(
+        (status, err["error"]["code"].as_str()),
+        (400, Some("validation"))
+    );
+    let (status, _) = call("GET", "/query/topk?uid=u_h&bogus=1", None);
+    assert_eq!(status, 400);
+    let (status, _) = call("POST", "/scopes", Some("[1]"));
+    assert_eq!(status, 400);
+    let (status, err) = call(
+        "POST",
+        "/scopes",
+        Some(r#"{"scope_id":"private:u_h","scope_type":"private"}"#),
+    );
+    assert_eq!(
+        (status, err["error"]["code"].as_str()),
+        (409, Some("conflict"))
+    );
+    assert_eq!(call("PUT", "/users", None).0, 405);
+    assert_eq!(call("GET", "/nowhere", None).0, 404);
+
+    let (status, spec) = call("GET", "/openapi.json", None);
+    assert_eq!(status, 200);
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert_eq!(
+        spec["paths"]["/users/{uid}"]["patch"]["operationId"],
+        "user.update"
+    );
+    assert_eq!(
+        spec["paths"]["/state/{scope_id}/{key}"]["put"]["requestBody"]["content"]
+            ["application/json"]["schema"]["required"],
+        serde_json::json!(["value"])
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn serve_http_listens_on_a_unix_socket() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("unix.db");
+    let db_str = db_path.to_string_lossy().to_string();
+    migrate_db(&db_str);
+    seed_user_and_scope(&db_path, "u_x", "private:u_x");
+    let socket = dir.path().join("api.sock");
+    let socket_str = socket.to_string_lossy().to_string();
+
+    let (mut child, listening) = spawn_http_server(&db_str, &["--unix-socket", &socket_str]);
+    assert_eq!(listening, format!("unix:{socket_str}"));
+    let (status, user) = http_exchange(
+        std::os::unix::net::UnixStream::connect(&socket).unwrap(),
+        "GET",
+        "/users/u_x",
+        None,
+    );
+    assert_eq!((status, user["uid"].as_str()), (200, Some("u_x")));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
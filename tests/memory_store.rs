@@ -0,0 +1,71 @@
+use agent_memory_cli::{ErrorKind, MemoryStore, ScopeSelection, UserRef};
+use serde_json::json;
+use tempfile::tempdir;
+
+#[test]
+fn memory_store_covers_users_identities_scopes_ingest_query_and_state() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("nested").join("store.db");
+    let db_str = db_path.to_string_lossy().to_string();
+
+    let empty = dir.path().join("empty.db");
+    let err = MemoryStore::open(&empty.to_string_lossy()).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::SchemaNotInitialized);
+
+    let mut store = MemoryStore::open_and_migrate(&db_str).unwrap();
+    let ann = store.create_user("Ann").unwrap();
+    assert_eq!(store.list_users().unwrap(), vec![ann.clone()]);
+    assert_eq!(store.get_user("u_missing").unwrap(), None);
+
+    store.link_identity(&ann.uid, "tg", "42").unwrap();
+    let via_tg = UserRef::Channel {
+        channel: "tg".to_string(),
+        channel_user_id: "42".to_string(),
+    };
+    assert_eq!(
+        store.resolve_identity("tg", "42").unwrap().unwrap().uid,
+        ann.uid
+    );
+
+    let private = format!("private:{}", ann.uid);
+    store.create_scope(&private, "private").unwrap();
+    store.add_scope_member(&private, &ann.uid, "owner").unwrap();
+    assert_eq!(store.scope_members(&private).unwrap()[0].role, "owner");
+
+    for cuisine in ["thai", "thai", "korean"] {
+        store
+            .ingest_event(
+                &via_tg,
+                &private,
+                "meal.rated",
+                &json!({"cuisine": cuisine}),
+                None,
+            )
+            .unwrap();
+    }
+    let top = store
+        .topk(&via_tg, &ScopeSelection::Auto, "food_pref", 5)
+        .unwrap();
+    assert_eq!((top[0].rank, top[0].item.as_str()), (1, "thai"));
+    assert_eq!(top[0].scope_id, private);
+    let latest = store
+        .latest_event(&via_tg, &ScopeSelection::Single(private.clone()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.event_type, "meal.rated");
+
+    let entry = store
+        .set_state(&via_tg, &private, "mood", &json!("calm"))
+        .unwrap();
+    assert_eq!(entry.uid, ann.uid);
+    let read = store.get_state(&via_tg, &private, "mood").unwrap();
+    assert_eq!(read.value, json!("calm"));
+    store.delete_state(&via_tg, &private, "mood").unwrap();
+    let err = store.delete_state(&via_tg, &private, "mood").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    // The handle reopens the same db the CLI uses.
+    drop(store);
+    let store = MemoryStore::open(&db_str).unwrap();
+    assert_eq!(store.list_scopes().unwrap()[0].scope_id, private);
+}